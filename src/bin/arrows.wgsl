@@ -0,0 +1,100 @@
+// Draws a line from each particle in the direction it is moving, with an arrowhead at the end
+
+struct VertexIn {
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) particle_index: u32,
+};
+
+struct VertexOut {
+    @builtin(position) position: vec4<f32>,
+    @location(0) @interpolate(flat) particle_index: u32,
+};
+
+struct Camera {
+    view_matrix: mat4x4<f32>,
+    projection_matrix: mat4x4<f32>,
+    particle_render_radius: f32,
+    color_mode: u32,
+    max_color_speed: f32,
+    selected_particle: u32,
+    fog_color: vec3<f32>,
+    fog_density: f32,
+    border_color: vec3<f32>,
+    size_mode: u32,
+    // The length of the arrows per unit of speed
+    arrow_scale: f32,
+    slice_axis: u32,
+    slice_center: f32,
+    slice_thickness: f32,
+};
+
+@group(0)
+@binding(0)
+var<uniform> camera: Camera;
+
+struct Particle {
+    position: vec3<f32>,
+    velocity: vec3<f32>,
+    id: u32,
+};
+
+struct Particles {
+    world_size: vec3<f32>,
+    length: u32,
+    particles: array<Particle>,
+};
+
+@group(1)
+@binding(0)
+var<storage, read> particles: Particles;
+
+struct TypeStyle {
+    color: vec3<f32>,
+    render_scale: f32,
+};
+
+struct Colors {
+    length: u32,
+    colors: array<TypeStyle>,
+};
+
+@group(1)
+@binding(1)
+var<storage, read> colors: Colors;
+
+// The length of the arrowhead compared to the whole arrow
+const head_length = 0.25;
+
+// Each arrow is 3 lines, the shaft followed by the 2 sides of the head
+@vertex
+fn vs_main(in: VertexIn) -> VertexOut {
+    var out: VertexOut;
+    out.particle_index = in.particle_index;
+
+    let particle = particles.particles[in.particle_index];
+    let start = (camera.view_matrix * vec4(particle.position, 1.0)).xyz;
+    let end = (camera.view_matrix * vec4(particle.position + particle.velocity * camera.arrow_scale, 1.0)).xyz;
+
+    var position = start;
+    if in.vertex_index != 0u {
+        position = end;
+    }
+    if in.vertex_index == 3u || in.vertex_index == 5u {
+        // the head spreads out sideways on screen, so it is visible from any angle the arrow isnt pointing straight at the camera
+        let direction = end - start;
+        let side = cross(direction, vec3<f32>(0.0, 0.0, 1.0)) * head_length * 0.5;
+        var spread = side;
+        if in.vertex_index == 5u {
+            spread = -side;
+        }
+        position = end - direction * head_length + spread;
+    }
+
+    out.position = camera.projection_matrix * vec4(position, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
+    return vec4(colors.colors[particles.particles[in.particle_index].id].color, 1.0);
+}
@@ -0,0 +1,91 @@
+use particle_life_3d::{AccelStructure, Particles};
+
+const PARTICLE_COUNTS: [usize; 4] = [1_000, 10_000, 50_000, 100_000];
+const SEED: u64 = 0;
+const TS: f32 = 1.0 / 60.0;
+/// The number of updates that run before timing starts, so the particles have moved away from their uniform spawn
+const WARMUP_STEPS: usize = 5;
+/// Each size is updated until both of these are reached
+const MIN_STEPS: usize = 10;
+const MIN_DURATION: std::time::Duration = std::time::Duration::from_secs(2);
+/// The number of particles used to compare the hashes with and without `Particles::spatial_sort`
+const SPATIAL_SORT_PARTICLE_COUNT: usize = 50_000;
+
+/// The same parameters as the default simulation, with the world scaled so that
+/// every size has the same number of particles per unit of volume as 1000 particles in the default world
+fn particles(particle_count: usize) -> Particles {
+    let mut particles = Particles::builder()
+        .world_size(cgmath::vec3(10.0, 10.0, 10.0) * (particle_count as f32 / 1000.0).cbrt())
+        .build()
+        .unwrap();
+    particles.spawn_random(particle_count, SEED);
+    particles
+}
+
+/// Updates `particles` until both `MIN_STEPS` and `MIN_DURATION` are reached, returning the number of steps and the seconds they took
+fn run(particles: &mut Particles) -> (usize, f64) {
+    let mut steps = 0;
+    let start = std::time::Instant::now();
+    while steps < MIN_STEPS || start.elapsed() < MIN_DURATION {
+        particles.update(TS);
+        steps += 1;
+    }
+    (steps, start.elapsed().as_secs_f64())
+}
+
+fn main() {
+    println!("Using {} threads", rayon::current_num_threads());
+    println!(
+        "{:>10} | {:>10} | {:>8} | {:>12} | {:>17}",
+        "particles", "world size", "steps", "steps/second", "ns/particle/step"
+    );
+    println!(
+        "{:-<11}+{:-<12}+{:-<10}+{:-<14}+{:-<18}",
+        "", "", "", "", ""
+    );
+
+    for particle_count in PARTICLE_COUNTS {
+        let mut particles = particles(particle_count);
+        particles.update_n(TS, WARMUP_STEPS);
+
+        let (steps, elapsed) = run(&mut particles);
+
+        println!(
+            "{:>10} | {:>10.2} | {:>8} | {:>12.2} | {:>17.1}",
+            particle_count,
+            particles.world_size.x,
+            steps,
+            steps as f64 / elapsed,
+            elapsed * 1e9 / (steps * particle_count) as f64
+        );
+    }
+
+    // the particles spawn in a random order, so the unsorted run is the worst case for the cache
+    println!();
+    for (accel_structure, name) in [
+        (AccelStructure::SpatialHash, "spatial hash"),
+        (AccelStructure::MortonHash, "morton hash"),
+    ] {
+        for sorted in [false, true] {
+            let mut particles = particles(SPATIAL_SORT_PARTICLE_COUNT);
+            particles.accel_structure = accel_structure;
+            particles.update_n(TS, WARMUP_STEPS);
+            if sorted {
+                particles.spatial_sort();
+            }
+
+            let (steps, elapsed) = run(&mut particles);
+            println!(
+                "{} particles {}, {}: {:.2} steps/second",
+                SPATIAL_SORT_PARTICLE_COUNT,
+                name,
+                if sorted {
+                    "spatially sorted"
+                } else {
+                    "unsorted"
+                },
+                steps as f64 / elapsed
+            );
+        }
+    }
+}
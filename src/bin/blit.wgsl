@@ -0,0 +1,30 @@
+// Copies the resolved scene onto egui's render target, drawn as a single triangle that covers the viewport
+
+struct VertexIn {
+    @builtin(vertex_index) vertex_index: u32,
+};
+
+struct VertexOut {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@group(0)
+@binding(0)
+var scene: texture_2d<f32>;
+
+@vertex
+fn vs_main(in: VertexIn) -> VertexOut {
+    let uv = vec2<f32>(f32((in.vertex_index << 1u) & 2u), f32(in.vertex_index & 2u));
+    var out: VertexOut;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(scene));
+    let texel = min(vec2<i32>(in.uv * size), vec2<i32>(size) - 1);
+    return textureLoad(scene, texel, 0);
+}
@@ -9,6 +9,18 @@ struct VertexOut {
 struct Camera {
     view_matrix: mat4x4<f32>,
     projection_matrix: mat4x4<f32>,
+    particle_render_radius: f32,
+    color_mode: u32,
+    max_color_speed: f32,
+    selected_particle: u32,
+    fog_color: vec3<f32>,
+    fog_density: f32,
+    border_color: vec3<f32>,
+    size_mode: u32,
+    arrow_scale: f32,
+    slice_axis: u32,
+    slice_center: f32,
+    slice_thickness: f32,
 };
 
 @group(0)
@@ -22,7 +34,7 @@ struct Particle {
 };
 
 struct Particles {
-    world_size: f32,
+    world_size: vec3<f32>,
     length: u32,
     particles: array<Particle>,
 };
@@ -68,5 +80,5 @@ fn vs_main(in: VertexIn) -> VertexOut {
 
 @fragment
 fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
-    return vec4(1.0);
+    return vec4(camera.border_color, 1.0);
 }
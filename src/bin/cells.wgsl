@@ -0,0 +1,104 @@
+// Draws the edges of the cells of the acceleration structure that have particles in them
+
+struct VertexIn {
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) cell_index: u32,
+};
+
+struct VertexOut {
+    @builtin(position) position: vec4<f32>,
+};
+
+struct Camera {
+    view_matrix: mat4x4<f32>,
+    projection_matrix: mat4x4<f32>,
+    particle_render_radius: f32,
+    color_mode: u32,
+    max_color_speed: f32,
+    selected_particle: u32,
+    fog_color: vec3<f32>,
+    fog_density: f32,
+    border_color: vec3<f32>,
+    size_mode: u32,
+    arrow_scale: f32,
+    slice_axis: u32,
+    slice_center: f32,
+    slice_thickness: f32,
+};
+
+@group(0)
+@binding(0)
+var<uniform> camera: Camera;
+
+struct Particle {
+    position: vec3<f32>,
+    velocity: vec3<f32>,
+    id: u32,
+};
+
+struct Particles {
+    world_size: vec3<f32>,
+    length: u32,
+    particles: array<Particle>,
+};
+
+@group(1)
+@binding(0)
+var<storage, read> particles: Particles;
+
+struct Cell {
+    min: vec3<f32>,
+    max: vec3<f32>,
+};
+
+struct Cells {
+    length: u32,
+    cells: array<Cell>,
+};
+
+@group(1)
+@binding(4)
+var<storage, read> cells: Cells;
+
+// TODO: change this to a `const` when naga is fixed
+var<private> vertices: array<vec3<f32>, 24> = array<vec3<f32>, 24>(
+    vec3<f32>(-1.0, 1.0, 1.0),
+    vec3<f32>(-1.0, -1.0, 1.0),
+    vec3<f32>(1.0, 1.0, 1.0),
+    vec3<f32>(1.0, -1.0, 1.0),
+    vec3<f32>(-1.0, 1.0, 1.0),
+    vec3<f32>(1.0, 1.0, 1.0),
+    vec3<f32>(-1.0, -1.0, 1.0),
+    vec3<f32>(1.0, -1.0, 1.0),
+    vec3<f32>(-1.0, 1.0, -1.0),
+    vec3<f32>(-1.0, -1.0, -1.0),
+    vec3<f32>(1.0, 1.0, -1.0),
+    vec3<f32>(1.0, -1.0, -1.0),
+    vec3<f32>(-1.0, 1.0, -1.0),
+    vec3<f32>(1.0, 1.0, -1.0),
+    vec3<f32>(-1.0, -1.0, -1.0),
+    vec3<f32>(1.0, -1.0, -1.0),
+    vec3<f32>(1.0, 1.0, -1.0),
+    vec3<f32>(1.0, 1.0, 1.0),
+    vec3<f32>(-1.0, 1.0, -1.0),
+    vec3<f32>(-1.0, 1.0, 1.0),
+    vec3<f32>(1.0, -1.0, -1.0),
+    vec3<f32>(1.0, -1.0, 1.0),
+    vec3<f32>(-1.0, -1.0, -1.0),
+    vec3<f32>(-1.0, -1.0, 1.0),
+);
+
+@vertex
+fn vs_main(in: VertexIn) -> VertexOut {
+    var out: VertexOut;
+    let cell = cells.cells[in.cell_index];
+    // the vertices go from -1 to 1, so this moves them to go from the min to the max corner
+    let position = mix(cell.min, cell.max, vertices[in.vertex_index] * 0.5 + 0.5);
+    out.position = camera.projection_matrix * (camera.view_matrix * vec4(position, 1.0));
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
+    return vec4(0.2, 0.8, 0.2, 1.0);
+}
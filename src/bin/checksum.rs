@@ -0,0 +1,26 @@
+//! Prints `Particles::state_hash` after the fixed simulation that `tests/checksum.rs` compares with `EXPECTED_CHECKSUM`,
+//! run it with `cargo run --release --bin checksum` and replace `EXPECTED_CHECKSUM` with the value it prints
+//! when a change to the physics is intentional.
+//! The simulation is `Particles::deterministic` so the number of threads doesnt matter,
+//! but the forces use SSE on x86_64 and plain loops everywhere else, so the checksum is only expected to match on x86_64
+
+use particle_life_3d::Particles;
+
+const PARTICLE_COUNT: usize = 300;
+const SEED: u64 = 0;
+const STEPS: usize = 60;
+const TS: f32 = 1.0 / 60.0;
+
+/// Runs the fixed simulation and returns the state hash at the end
+pub fn checksum() -> u64 {
+    let mut particles = Particles::builder().deterministic(true).build().unwrap();
+    particles.spawn_random(PARTICLE_COUNT, SEED);
+    for _ in 0..STEPS {
+        particles.update(TS);
+    }
+    particles.state_hash()
+}
+
+fn main() {
+    println!("{:#018x}", checksum());
+}
@@ -0,0 +1,295 @@
+use std::{io::Write, path::PathBuf};
+
+use particle_life_3d::Particles;
+
+struct Args {
+    particle_count: usize,
+    steps: usize,
+    seed: u64,
+    ticks_per_second: f32,
+    config: Option<PathBuf>,
+    output: PathBuf,
+    trajectory: Option<PathBuf>,
+    trajectory_interval: usize,
+    sweep: Option<Sweep>,
+    sweep_metric: SweepMetric,
+    sweep_output: PathBuf,
+}
+
+/// Runs the simulation once for each of `steps` values evenly spaced from `start` to `stop`
+struct Sweep {
+    parameter: String,
+    start: f32,
+    stop: f32,
+    steps: usize,
+}
+
+impl Sweep {
+    fn values(&self) -> impl Iterator<Item = f32> + '_ {
+        (0..self.steps).map(|i| {
+            if self.steps == 1 {
+                self.start
+            } else {
+                self.start + (self.stop - self.start) * i as f32 / (self.steps - 1) as f32
+            }
+        })
+    }
+}
+
+/// What gets recorded at the end of each run of a sweep
+#[derive(Clone, Copy)]
+enum SweepMetric {
+    /// The largest bin of the pair correlation, higher values mean the particles are more clustered
+    PairCorrelationPeak,
+    TotalKineticEnergy,
+}
+
+impl SweepMetric {
+    fn name(self) -> &'static str {
+        match self {
+            SweepMetric::PairCorrelationPeak => "pair_correlation_peak",
+            SweepMetric::TotalKineticEnergy => "total_kinetic_energy",
+        }
+    }
+
+    fn measure(self, particles: &Particles) -> f32 {
+        match self {
+            SweepMetric::PairCorrelationPeak => {
+                let max_r = particles
+                    .particle_effect_radius
+                    .min(particles.min_world_size() * 0.5);
+                particles
+                    .pair_correlation(PAIR_CORRELATION_BINS, max_r)
+                    .into_iter()
+                    .fold(0.0, f32::max)
+            }
+            SweepMetric::TotalKineticEnergy => particles.total_kinetic_energy(),
+        }
+    }
+}
+
+const PAIR_CORRELATION_BINS: usize = 50;
+
+/// The parameters that can be swept
+const SWEEP_PARAMETERS: &[&str] = &[
+    "friction",
+    "force_scale",
+    "min_attraction_percentage",
+    "particle_effect_radius",
+    "thermostat_coupling",
+    "wall_restitution",
+];
+
+fn set_parameter(particles: &mut Particles, parameter: &str, value: f32) {
+    match parameter {
+        "friction" => particles.friction = value,
+        "force_scale" => particles.force_scale = value,
+        "min_attraction_percentage" => particles.min_attraction_percentage = value,
+        "particle_effect_radius" => particles.particle_effect_radius = value,
+        "thermostat_coupling" => particles.thermostat_coupling = value,
+        "wall_restitution" => particles.wall_restitution = value,
+        _ => unreachable!("{parameter} is checked against SWEEP_PARAMETERS when parsing"),
+    }
+}
+
+const USAGE: &str = "\
+usage: headless [options]
+    --count <n>       number of particles to spawn (default 1000)
+    --steps <n>       number of updates to run (default 1000)
+    --seed <n>        seed used to spawn the particles (default 0)
+    --tps <n>         ticks per second, each update advances by 1 / tps (default 60)
+    --config <path>   json file to load the simulation parameters from
+    --output <path>   json file to write the final simulation state to (default particles.json)
+    --trajectory <path>       csv file to write the particles to while the simulation runs
+    --trajectory-interval <n> number of updates between each frame written to the trajectory (default 1)
+    --sweep <name>,<start>,<stop>,<steps>   run once for each of <steps> values of the parameter from <start> to <stop>,
+                      one of friction, force_scale, min_attraction_percentage, particle_effect_radius,
+                      thermostat_coupling, wall_restitution
+    --sweep-metric <name>     measured at the end of each run, pair_correlation_peak or total_kinetic_energy
+                              (default pair_correlation_peak)
+    --sweep-output <path>     csv file to write the sweep results to (default sweep.csv)";
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        particle_count: 1000,
+        steps: 1000,
+        seed: 0,
+        ticks_per_second: 60.0,
+        config: None,
+        output: PathBuf::from("particles.json"),
+        trajectory: None,
+        trajectory_interval: 1,
+        sweep: None,
+        sweep_metric: SweepMetric::PairCorrelationPeak,
+        sweep_output: PathBuf::from("sweep.csv"),
+    };
+
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .ok_or_else(|| format!("missing value for {arg}"))
+        };
+        fn parse<T: std::str::FromStr>(arg: &str, value: String) -> Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| format!("invalid value for {arg}: {value}"))
+        }
+        match arg.as_str() {
+            "--count" => args.particle_count = parse(&arg, value()?)?,
+            "--steps" => args.steps = parse(&arg, value()?)?,
+            "--seed" => args.seed = parse(&arg, value()?)?,
+            "--tps" => args.ticks_per_second = parse(&arg, value()?)?,
+            "--config" => args.config = Some(value()?.into()),
+            "--output" => args.output = value()?.into(),
+            "--trajectory" => args.trajectory = Some(value()?.into()),
+            "--trajectory-interval" => args.trajectory_interval = parse(&arg, value()?)?,
+            "--sweep" => {
+                let value = value()?;
+                let [parameter, start, stop, steps] = value.split(',').collect::<Vec<_>>()[..]
+                else {
+                    return Err(format!("invalid value for {arg}: {value}"));
+                };
+                if !SWEEP_PARAMETERS.contains(&parameter) {
+                    return Err(format!("unknown sweep parameter {parameter}"));
+                }
+                args.sweep = Some(Sweep {
+                    parameter: parameter.into(),
+                    start: parse(&arg, start.into())?,
+                    stop: parse(&arg, stop.into())?,
+                    steps: parse(&arg, steps.into())?,
+                });
+            }
+            "--sweep-metric" => {
+                args.sweep_metric = match value()?.as_str() {
+                    "pair_correlation_peak" => SweepMetric::PairCorrelationPeak,
+                    "total_kinetic_energy" => SweepMetric::TotalKineticEnergy,
+                    metric => return Err(format!("invalid value for {arg}: {metric}")),
+                }
+            }
+            "--sweep-output" => args.sweep_output = value()?.into(),
+            _ => return Err(format!("unknown argument {arg}")),
+        }
+    }
+
+    if args.ticks_per_second <= 0.0 {
+        return Err("--tps must be greater than 0".into());
+    }
+    if args.trajectory_interval == 0 {
+        return Err("--trajectory-interval must be greater than 0".into());
+    }
+    if let Some(sweep) = &args.sweep {
+        if sweep.steps == 0 {
+            return Err("--sweep must have at least 1 step".into());
+        }
+        if args.trajectory.is_some() {
+            return Err("--trajectory cant be used with --sweep".into());
+        }
+    }
+    Ok(args)
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(error) => {
+            eprintln!("{error}");
+            eprintln!("{USAGE}");
+            std::process::exit(1);
+        }
+    };
+
+    let particles = match &args.config {
+        Some(path) => {
+            let config = std::fs::read_to_string(path)
+                .unwrap_or_else(|error| panic!("failed to read {}: {error}", path.display()));
+            let particles = Particles::from_json(&config)
+                .unwrap_or_else(|error| panic!("failed to parse {}: {error}", path.display()));
+            if let Err(error) = particles.validate() {
+                panic!("invalid config {}: {error}", path.display());
+            }
+            particles
+        }
+        None => Particles::default(),
+    };
+
+    match &args.sweep {
+        Some(sweep) => run_sweep(&args, sweep, particles),
+        None => run(&args, particles),
+    }
+}
+
+fn run(args: &Args, mut particles: Particles) {
+    particles.spawn_random(args.particle_count, args.seed);
+
+    let mut trajectory = args.trajectory.as_ref().map(|path| {
+        let mut file = std::fs::File::create(path)
+            .unwrap_or_else(|error| panic!("failed to create {}: {error}", path.display()));
+        Particles::write_csv_header(&mut file).unwrap();
+        particles.append_csv(&mut file, 0).unwrap();
+        file
+    });
+
+    let ts = 1.0 / args.ticks_per_second;
+    let mut elapsed = std::time::Duration::ZERO;
+    for step in 1..=args.steps {
+        let start = std::time::Instant::now();
+        particles.update(ts);
+        elapsed += start.elapsed();
+
+        if let Some(file) = &mut trajectory {
+            if step % args.trajectory_interval == 0 {
+                particles.append_csv(file, step as u64).unwrap();
+            }
+        }
+    }
+
+    std::fs::write(&args.output, particles.to_json())
+        .unwrap_or_else(|error| panic!("failed to write {}: {error}", args.output.display()));
+
+    println!(
+        "Ran {} steps with {} particles in {:.3}s",
+        args.steps,
+        particles.current_particles.len(),
+        elapsed.as_secs_f64()
+    );
+    if args.steps > 0 {
+        println!(
+            "Average update time: {:.3}ms ({:.1} steps per second)",
+            elapsed.as_secs_f64() * 1000.0 / args.steps as f64,
+            args.steps as f64 / elapsed.as_secs_f64()
+        );
+    }
+    println!("Wrote final state to {}", args.output.display());
+}
+
+fn run_sweep(args: &Args, sweep: &Sweep, base: Particles) {
+    let mut file = std::fs::File::create(&args.sweep_output).unwrap_or_else(|error| {
+        panic!("failed to create {}: {error}", args.sweep_output.display())
+    });
+    writeln!(file, "{},{}", sweep.parameter, args.sweep_metric.name()).unwrap();
+
+    let ts = 1.0 / args.ticks_per_second;
+    for value in sweep.values() {
+        let mut particles = base.clone();
+        set_parameter(&mut particles, &sweep.parameter, value);
+        if let Err(error) = particles.validate() {
+            panic!("invalid {} of {value}: {error}", sweep.parameter);
+        }
+        // the same seed every run so the only difference between runs is the swept parameter
+        particles.spawn_random(args.particle_count, args.seed);
+
+        let elapsed = particles.update_n(ts, args.steps);
+        let metric = args.sweep_metric.measure(&particles);
+        writeln!(file, "{value},{metric}").unwrap();
+
+        println!(
+            "{} = {value}: {} = {metric} ({:.3}s)",
+            sweep.parameter,
+            args.sweep_metric.name(),
+            elapsed.as_secs_f64()
+        );
+    }
+
+    println!("Wrote sweep results to {}", args.sweep_output.display());
+}
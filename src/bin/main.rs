@@ -1,21 +1,79 @@
-use std::cmp::Ordering;
+use std::{cmp::Ordering, collections::VecDeque};
 
 use cgmath::prelude::*;
 use eframe::egui_wgpu::wgpu;
 use eframe::wgpu::include_wgsl;
 use eframe::{egui, wgpu::util::DeviceExt};
 use encase::{ArrayLength, ShaderSize, ShaderType, StorageBuffer, UniformBuffer};
-use particle_life_3d::{Particle, Particles};
+use particle_life_3d::{
+    presets,
+    replay::{Player, Precision, Recorder},
+    AccelStructure, Attractor, BoundaryMode, Dimensions, ForceProfile, GravityMode, Integrator,
+    Particle, Particles,
+};
 use rand::prelude::*;
 
-const CAMERA_SPEED: f32 = 5.0;
-const CAMERA_ROTATION_SPEED: f32 = 90.0;
+use gpu_types::*;
 
+/// The most particles that can be spawned from the ui, to avoid running out of memory from a mistyped count
+const MAX_PARTICLE_COUNT: usize = 1_000_000;
+/// How much faster the camera moves while shift is held
+const CAMERA_SPRINT_MULTIPLIER: f32 = 4.0;
+/// Degrees of rotation per point the mouse is dragged in the viewport
+const CAMERA_MOUSE_SENSITIVITY: f32 = 0.3;
+/// Distance moved per point scrolled in the viewport
+const CAMERA_SCROLL_SPEED: f32 = 0.01;
+/// How quickly the point followed by the camera catches up with the center of mass, higher is faster
+const CAMERA_FOLLOW_SMOOTHING: f32 = 5.0;
+/// The number of frames that the frame and update time graphs show
+const TIMING_HISTORY_LENGTH: usize = 240;
+/// How far in front of the camera new attractors are placed
+const ATTRACTOR_PLACEMENT_DISTANCE: f32 = 10.0;
+/// How far along the ray under the cursor the brush spawns particles
+const BRUSH_DISTANCE: f32 = 10.0;
+/// How far from the brush position particles are spawned along each axis, so they dont all start at the same point
+const BRUSH_JITTER: f32 = 0.5;
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum Projection {
+    Perspective {
+        /// The vertical field of view in degrees
+        fov_deg: f32,
+    },
+    /// Removes perspective distortion, so parallel lines stay parallel on screen
+    Orthographic {
+        /// The height of the view in world units
+        height: f32,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum CameraMode {
+    /// Moves freely and looks along its pitch and yaw
+    Fly,
+    /// Pitch and yaw rotate the camera around the center of the world, at `Camera::orbit_radius` from it
+    Orbit,
+}
+
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 struct Camera {
+    pub mode: CameraMode,
+    /// The distance from the center of the world in `CameraMode::Orbit`
+    pub orbit_radius: f32,
     pub position: cgmath::Vector3<f32>,
     pub up: cgmath::Vector3<f32>,
     pub pitch: f32,
     pub yaw: f32,
+    pub projection: Projection,
+    /// The distances to the near and far clip planes, nothing closer than `near` or further than `far` is drawn.
+    /// Most of the depth buffer precision is spent close to `near`, so a tiny `near` makes distant surfaces z-fight
+    pub near: f32,
+    pub far: f32,
+    /// The point the camera looks at, the camera looks along `pitch` and `yaw` when this is `None`
+    pub target: Option<cgmath::Vector3<f32>>,
 }
 
 struct Axes {
@@ -36,103 +94,529 @@ impl Camera {
         let up = right.cross(forward).normalize();
         Axes { forward, right, up }
     }
+
+    pub fn get_view_matrix(&self) -> cgmath::Matrix4<f32> {
+        let axes = self.get_axes();
+        let position = cgmath::point3(self.position.x, self.position.y, self.position.z);
+        match self.target {
+            Some(target) => cgmath::Matrix4::look_at_rh(
+                position,
+                cgmath::point3(target.x, target.y, target.z),
+                axes.up,
+            ),
+            None => cgmath::Matrix4::look_to_rh(position, axes.forward, axes.up),
+        }
+    }
+
+    /// Places the camera `orbit_radius` away from the point it orbits, which is its target if it has one and the center of the world otherwise
+    pub fn update_orbit(&mut self) {
+        if self.mode == CameraMode::Orbit {
+            let center = self.target.unwrap_or(cgmath::Vector3::zero());
+            self.position = center - self.get_axes().forward * self.orbit_radius;
+        }
+    }
+
+    /// Turns the camera so that it looks along `direction`
+    pub fn look_along(&mut self, direction: cgmath::Vector3<f32>) {
+        let direction = direction.normalize();
+        self.pitch = direction.y.asin().to_degrees().clamp(-89.9999, 89.9999);
+        self.yaw = direction.x.atan2(-direction.z).to_degrees();
+    }
+
+    /// The ray through `screen_position` as its origin and direction,
+    /// `screen_position` goes from -1 to 1 across the viewport with y pointing up
+    pub fn get_screen_ray(
+        &self,
+        aspect: f32,
+        screen_position: cgmath::Vector2<f32>,
+    ) -> (cgmath::Vector3<f32>, cgmath::Vector3<f32>) {
+        let inverse_view_projection = (self.get_projection_matrix(aspect) * self.get_view_matrix())
+            .invert()
+            .unwrap();
+        // the points on the near and far planes under the cursor
+        let unproject = |depth: f32| {
+            let position = inverse_view_projection
+                * cgmath::vec4(screen_position.x, screen_position.y, depth, 1.0);
+            position.truncate() / position.w
+        };
+        let near = unproject(-1.0);
+        let far = unproject(1.0);
+        (near, (far - near).normalize())
+    }
+
+    pub fn get_projection_matrix(&self, aspect: f32) -> cgmath::Matrix4<f32> {
+        match self.projection {
+            Projection::Perspective { fov_deg } => {
+                cgmath::perspective(cgmath::Deg(fov_deg), aspect, self.near, self.far)
+            }
+            Projection::Orthographic { height } => cgmath::ortho(
+                -height * aspect * 0.5,
+                height * aspect * 0.5,
+                -height * 0.5,
+                height * 0.5,
+                self.near,
+                self.far,
+            ),
+        }
+    }
+
+    /// The same as `get_projection_matrix`, but maps `near` to a depth of 1 and `far` to a depth of 0.
+    /// Floats are much more precise close to 0, which cancels out perspective putting most of the depth range close to `near`,
+    /// so distant surfaces in large worlds dont z-fight. The depth buffer has to be cleared to 0 and compared with `Greater`
+    pub fn get_reverse_z_projection_matrix(&self, aspect: f32) -> cgmath::Matrix4<f32> {
+        let mut matrix = self.get_projection_matrix(aspect);
+        let (near, far) = (self.near, self.far);
+        // only the row that calculates the depth changes
+        match self.projection {
+            Projection::Perspective { .. } => {
+                matrix.z.z = near / (far - near);
+                matrix.w.z = near * far / (far - near);
+            }
+            Projection::Orthographic { .. } => {
+                matrix.z.z = 1.0 / (far - near);
+                matrix.w.z = far / (far - near);
+            }
+        }
+        matrix
+    }
 }
 
-#[derive(ShaderType)]
-struct GpuParticles<'a> {
-    pub world_size: f32,
-    pub length: ArrayLength,
-    #[size(runtime)]
-    pub particles: &'a [Particle],
+/// The layouts of the buffers that are shared with the shaders
+mod gpu_types {
+    // the `ShaderType` derive generates a `check` function for each field that is never called
+    #![allow(dead_code)]
+
+    use encase::{ArrayLength, ShaderType};
+    use particle_life_3d::Particle;
+
+    #[derive(ShaderType)]
+    pub struct GpuParticles<'a> {
+        pub world_size: cgmath::Vector3<f32>,
+        pub length: ArrayLength,
+        #[size(runtime)]
+        pub particles: &'a [Particle],
+    }
+
+    #[derive(ShaderType)]
+    pub struct GpuTypeStyle {
+        pub color: cgmath::Vector3<f32>,
+        /// Multiplies the particle render radius when using `SizeMode::ByType`
+        pub render_scale: f32,
+    }
+
+    #[derive(ShaderType)]
+    pub struct GpuColors<'a> {
+        pub length: ArrayLength,
+        #[size(runtime)]
+        pub particles: &'a [GpuTypeStyle],
+    }
+
+    #[derive(ShaderType)]
+    pub struct GpuTrails<'a> {
+        pub frame_count: u32,
+        pub opacity: f32,
+        pub length: ArrayLength,
+        #[size(runtime)]
+        pub positions: &'a [cgmath::Vector3<f32>],
+    }
+
+    #[derive(ShaderType)]
+    pub struct GpuObstacles<'a> {
+        pub length: ArrayLength,
+        #[size(runtime)]
+        pub obstacles: &'a [cgmath::Vector4<f32>],
+    }
+
+    #[derive(ShaderType)]
+    pub struct GpuCell {
+        pub min: cgmath::Vector3<f32>,
+        pub max: cgmath::Vector3<f32>,
+    }
+
+    #[derive(ShaderType)]
+    pub struct GpuCells<'a> {
+        pub length: ArrayLength,
+        #[size(runtime)]
+        pub cells: &'a [GpuCell],
+    }
+
+    #[derive(ShaderType)]
+    pub struct GpuCamera {
+        pub view_matrix: cgmath::Matrix4<f32>,
+        pub projection_matrix: cgmath::Matrix4<f32>,
+        pub particle_render_radius: f32,
+        pub color_mode: u32,
+        pub max_color_speed: f32,
+        /// The index of the selected particle plus 1, or 0 when no particle is selected
+        pub selected_particle: u32,
+        /// Linear when the render target is srgb, like the clear color
+        pub fog_color: cgmath::Vector3<f32>,
+        pub fog_density: f32,
+        /// The color of the lines around the edges of the world, in the same color space as `fog_color`
+        pub border_color: cgmath::Vector3<f32>,
+        pub size_mode: u32,
+        /// The length of the velocity arrows per unit of speed
+        pub arrow_scale: f32,
+        /// The axis that particles are sliced along plus 1, or 0 when every particle is drawn
+        pub slice_axis: u32,
+        pub slice_center: f32,
+        pub slice_thickness: f32,
+    }
+
+    #[derive(ShaderType)]
+    pub struct GpuUpdateParameters {
+        /// The acceleration from `GravityMode::Uniform`
+        pub gravity: cgmath::Vector3<f32>,
+        /// The strength of `GravityMode::Radial`
+        pub radial_gravity: f32,
+        pub world_size: cgmath::Vector3<f32>,
+        pub boundaries: cgmath::Vector3<u32>,
+        pub cell_size: f32,
+        pub particle_effect_radius: f32,
+        pub min_attraction_percentage: f32,
+        pub wall_restitution: f32,
+        pub max_velocity: f32,
+        pub ts: f32,
+        pub id_count: u32,
+        pub particle_count: u32,
+        pub cell_range: i32,
+        pub velocity_verlet: u32,
+        pub has_previous_accelerations: u32,
+        pub two_dimensional: u32,
+        pub obstacle_count: u32,
+        pub attractor_count: u32,
+        /// The index of the `ForceProfile` variant
+        pub force_profile: u32,
+    }
+
+    #[derive(ShaderType)]
+    pub struct GpuTypeProperties {
+        pub mass: f32,
+        pub friction: f32,
+        pub force_scale: f32,
+        pub radius: f32,
+    }
+}
+
+/// How the color of each particle is chosen when rendering
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum ColorMode {
+    /// Uses the color of the particle's type
+    ByType,
+    /// Uses a gradient from blue to red based on the particle's speed
+    BySpeed,
+}
+
+/// How the size of each particle is chosen when rendering
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum SizeMode {
+    /// Every particle is drawn with the particle render radius
+    Uniform,
+    /// Multiplies the particle render radius by the render scale of the particle's type
+    ByType,
+    /// Grows particles up to twice the particle render radius as they get closer to the max color speed
+    BySpeed,
 }
 
-#[derive(ShaderType)]
-struct GpuColors<'a> {
-    pub length: ArrayLength,
-    #[size(runtime)]
-    pub particles: &'a [cgmath::Vector3<f32>],
+/// Where particles are placed when they are respawned
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SpawnShape {
+    /// Uniformly throughout the whole world
+    Cube,
+    /// Inside the largest sphere that fits in the world
+    Sphere,
+    /// Between the largest sphere that fits in the world and a sphere `shell_inner_fraction` of its size
+    Shell,
 }
 
-#[derive(ShaderType)]
-struct GpuCamera {
-    pub view_matrix: cgmath::Matrix4<f32>,
-    pub projection_matrix: cgmath::Matrix4<f32>,
+/// What the brush does to the particles under the cursor
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BrushMode {
+    Spawn,
+    Delete,
 }
 
 struct App {
     particles: Particles,
     camera: Camera,
+    /// Distance the camera moves per second while a movement key is held
+    camera_speed: f32,
+    /// Degrees the camera turns per second while an arrow key is held
+    camera_rotation_speed: f32,
+    /// Whether the camera speeds up and slows down smoothly instead of starting and stopping instantly
+    smooth_camera_movement: bool,
+    /// Like `smooth_camera_movement`, but for turning
+    smooth_camera_rotation: bool,
+    /// How quickly the camera gets up to speed while a key is held, see `ease_velocity`
+    camera_acceleration: f32,
+    /// How quickly the camera stops once the keys are released, see `ease_velocity`
+    camera_damping: f32,
+    /// Along the camera's right, up, and forward axes
+    camera_velocity: cgmath::Vector3<f32>,
+    /// The change in pitch and yaw in degrees per second
+    camera_angular_velocity: cgmath::Vector2<f32>,
+    /// Whether changing the world size in the ui scales the particle positions with it
+    rescale_on_world_resize: bool,
+    /// Whether the camera keeps looking at the center of mass, movement and rotation then orbit around it
+    follow_center_of_mass: bool,
     last_time: std::time::Instant,
+    /// The frame time of each of the last `TIMING_HISTORY_LENGTH` frames in milliseconds, oldest first
+    frame_time_history: VecDeque<f32>,
+    /// The time spent updating the particles in each of the last `TIMING_HISTORY_LENGTH` frames in milliseconds, oldest first
+    update_time_history: VecDeque<f32>,
     fixed_time: std::time::Duration,
     ticks_per_second: f32,
+    /// The number of updates each tick is split into, more substeps keep strong forces stable without changing the tick rate
+    substeps: u32,
+    /// The number of ticks between each `Particles::spatial_sort`, 0 never sorts the particles
+    spatial_sort_interval: usize,
+    ticks_since_spatial_sort: usize,
+    /// How fast simulated time passes compared to real time, this changes how many updates run rather than their timestep
+    time_scale: f32,
+    /// Whether ticks stop running for the frame once they have taken `update_budget` of the frame time
+    adaptive_tick_rate: bool,
+    /// The fraction of the frame time that ticks can take when `adaptive_tick_rate` is set
+    update_budget: f32,
+    /// Whether the last frame dropped ticks because it couldnt keep up, the simulation runs slower than real time while this is set
+    running_slow: bool,
     color_window_open: bool,
-}
-
-fn random_particle(world_size: f32) -> Particle {
-    let mut rng = thread_rng();
-    Particle {
-        position: cgmath::vec3(
-            rng.gen_range(world_size * -0.5..=world_size * 0.5),
-            rng.gen_range(world_size * -0.5..=world_size * 0.5),
-            rng.gen_range(world_size * -0.5..=world_size * 0.5),
-        ),
-        velocity: cgmath::vec3(0.0, 0.0, 0.0),
-        id: rng.gen_range(0..5),
-    }
+    pair_correlation_window_open: bool,
+    pair_correlation_bins: usize,
+    /// The largest distance shown in the pair correlation plot, this is limited to half the world size
+    pair_correlation_max_r: f32,
+    gpu_updater: GpuUpdater,
+    gpu_update: bool,
+    /// The number of threads used by cpu updates, all of the cores are used when this is not set.
+    /// Use `set_thread_count` to change it, which rebuilds `thread_pool`
+    thread_count: Option<usize>,
+    /// The pool that cpu updates run in when `thread_count` is set
+    thread_pool: Option<rayon::ThreadPool>,
+    /// The seed that the current particles were spawned with, `spawn` uses it so that resetting gives the same particles
+    seed: u64,
+    /// The seed that respawning uses, shown in the ui so that it can be typed in
+    next_seed: u64,
+    /// Whether `next_seed` stays the same after respawning instead of going up by 1
+    lock_seed: bool,
+    target_particle_count: usize,
+    spawn_shape: SpawnShape,
+    /// Whether choosing an attraction preset keeps the particles where they are instead of respawning them
+    keep_particles_on_preset: bool,
+    /// The inner radius of `SpawnShape::Shell` as a fraction of its outer radius
+    shell_inner_fraction: f32,
+    /// The radius particles are drawn with, this doesnt affect the simulation
+    particle_render_radius: f32,
+    /// The number of samples per pixel used for anti-aliasing, 1 turns it off
+    sample_count: u32,
+    /// Whether the scene is drawn with `Camera::get_reverse_z_projection_matrix`, which gives distant surfaces more depth precision
+    reverse_z: bool,
+    /// Whether the particles are drawn as additive glowing blobs instead of solid spheres
+    glow: bool,
+    /// The color behind everything in the viewport, the render target is cleared to this
+    background_color: egui::Color32,
+    /// The color that distant particles fade into
+    fog_color: egui::Color32,
+    /// Whether particles fade into `background_color` instead of `fog_color`, so distant particles blend into the background
+    fog_matches_background: bool,
+    /// How quickly particles fade into `fog_color` with distance, 0 turns fog off
+    fog_density: f32,
+    show_border: bool,
+    border_color: egui::Color32,
+    screenshot_requested: bool,
+    /// The index of the particle that was last clicked on in the viewport
+    selected_particle: Option<usize>,
+    brush_mode: BrushMode,
+    /// The number of particles the brush spawns every frame that it is used
+    brush_count: usize,
+    /// How close particles have to be to the ray under the cursor to be deleted by the brush
+    brush_delete_radius: f32,
+    /// The type of the particles that the brush spawns
+    brush_type: u32,
+    show_trails: bool,
+    /// The number of updates that the trails are kept for
+    trail_length: usize,
+    trail_opacity: f32,
+    /// The positions of every particle for each of the last `trail_length` updates, oldest first
+    trail_history: VecDeque<Vec<cgmath::Vector3<f32>>>,
+    /// Whether an arrow is drawn from each particle in the direction it is moving
+    show_arrows: bool,
+    /// The length of the arrows per unit of speed
+    arrow_scale: f32,
+    /// Whether the cells of the acceleration structure that have particles in them are drawn
+    show_cells: bool,
+    /// Whether only the particles in a slab through the world are drawn
+    slice: bool,
+    /// The axis that the slab is perpendicular to, 0 to 2 for x to z
+    slice_axis: usize,
+    /// The position of the middle of the slab along `slice_axis`
+    slice_center: f32,
+    /// How far particles can be from `slice_center` along `slice_axis` to still be drawn
+    slice_thickness: f32,
+    color_mode: ColorMode,
+    /// The speed that gets the last color in the gradient when using `ColorMode::BySpeed`, and the largest size with `SizeMode::BySpeed`
+    max_color_speed: f32,
+    size_mode: SizeMode,
+    /// How much bigger each particle type is drawn with `SizeMode::ByType`, this is kept the same length as `id_count`
+    type_render_scales: Vec<f32>,
+    paused: bool,
+    single_step: bool,
+    /// Records the particles after every update while it is set
+    recorder: Option<Recorder>,
+    /// Whether the next recording quantizes positions
+    quantize_recording: bool,
+    /// The recording being played back, the simulation is paused and the recorded frames are shown instead while this is set
+    player: Option<Player>,
+    playback_frame: usize,
+    /// Whether `playback_frame` advances every frame
+    playback_playing: bool,
+    /// Whether to respawn the particles after loading a preset
+    #[cfg(feature = "serde")]
+    respawn_on_load: bool,
+    /// The result of the last preset save or load, shown in the ui
+    #[cfg(feature = "serde")]
+    preset_status: Option<Result<String, String>>,
+    #[cfg(feature = "serde")]
+    session_path: String,
+    /// The result of the last session save or load, shown in the ui
+    #[cfg(feature = "serde")]
+    session_status: Option<Result<String, String>>,
+    /// The csv file that particles are exported to and imported from
+    particles_path: String,
+    /// The result of the last particle export or import, shown in the ui
+    particles_status: Option<Result<String, String>>,
+    system_info: SystemInfo,
 }
 
 impl App {
     fn new(cc: &eframe::CreationContext) -> Self {
-        let mut particles = Particles {
-            world_size: 10.0,
-            id_count: 5,
-            colors: vec![
-                cgmath::vec3(1.0, 0.0, 0.0), // red
-                cgmath::vec3(0.0, 1.0, 0.0), // green
-                cgmath::vec3(0.0, 0.0, 1.0), // blue
-                cgmath::vec3(1.0, 1.0, 0.0), // yellow
-                cgmath::vec3(1.0, 0.0, 1.0), // purple
-            ],
-            attraction_matrix: vec![
-                0.5, 1.0, -0.5, 0.0, -1.0, // red
-                1.0, 1.0, 1.0, 0.0, -1.0, // green
-                0.0, 0.0, 0.5, 1.5, -1.0, // blue
-                0.0, 0.0, 0.0, 0.0, -1.0, // yellow
-                1.0, 1.0, 1.0, 1.0, 0.5, // purple
-            ],
-            particle_effect_radius: 2.0,
-            friction: 0.97,
-            force_scale: 1.0,
-            min_attraction_percentage: 0.3,
-            current_particles: vec![],
-            previous_particles: vec![],
-            solid_walls: false,
-            gravity: cgmath::vec3(0.0, 0.0, 0.0),
-        };
+        let mut particles = Particles::default();
+        #[cfg(feature = "serde")]
+        if let Some(preset) = load_autosave() {
+            if let Err(error) = particles.apply_preset(preset) {
+                eprintln!("Ignoring the autosaved parameters: {error}");
+            }
+        }
 
-        particles.current_particles =
-            std::iter::repeat_with(|| random_particle(particles.world_size))
-                .take(1000)
-                .collect();
+        // small enough to be edited exactly in the ui, which goes through f64
+        let seed = thread_rng().gen::<u32>() as u64;
+        let target_particle_count = 1000;
+        particles.spawn_random(target_particle_count, seed);
 
+        let max_world_size = particles
+            .world_size
+            .x
+            .max(particles.world_size.y)
+            .max(particles.world_size.z);
         let camera = Camera {
-            position: cgmath::vec3(1.0, 0.0, particles.world_size * 1.6),
+            position: cgmath::vec3(1.0, 0.0, max_world_size * 1.6),
+            mode: CameraMode::Fly,
+            orbit_radius: 0.0,
             up: cgmath::vec3(0.0, 1.0, 0.0),
             pitch: 0.0,
             yaw: 0.0,
+            projection: Projection::Perspective { fov_deg: 90.0 },
+            // far enough to see the whole world from outside it, with the near plane as far out as that allows
+            near: max_world_size * 0.001,
+            far: max_world_size * 20.0,
+            target: None,
         };
 
+        let render_state = cc.wgpu_render_state.as_ref().unwrap();
+        let gpu_updater = GpuUpdater::new(render_state);
+        let renderer = Renderer::new(render_state, false);
+
         let app = Self {
             particles,
             camera,
+            camera_speed: 5.0,
+            camera_rotation_speed: 90.0,
+            smooth_camera_movement: true,
+            smooth_camera_rotation: false,
+            camera_acceleration: 8.0,
+            camera_damping: 4.0,
+            camera_velocity: cgmath::Vector3::zero(),
+            camera_angular_velocity: cgmath::Vector2::zero(),
+            follow_center_of_mass: false,
+            rescale_on_world_resize: true,
             last_time: std::time::Instant::now(),
+            frame_time_history: VecDeque::with_capacity(TIMING_HISTORY_LENGTH),
+            update_time_history: VecDeque::with_capacity(TIMING_HISTORY_LENGTH),
             fixed_time: std::time::Duration::ZERO,
             ticks_per_second: 60.0,
+            substeps: 1,
+            adaptive_tick_rate: true,
+            update_budget: 0.5,
+            running_slow: false,
+            spatial_sort_interval: 0,
+            ticks_since_spatial_sort: 0,
+            time_scale: 1.0,
             color_window_open: false,
+            pair_correlation_window_open: false,
+            pair_correlation_bins: 100,
+            pair_correlation_max_r: 2.0,
+            gpu_updater,
+            gpu_update: false,
+            thread_count: None,
+            thread_pool: None,
+            seed,
+            next_seed: seed.wrapping_add(1),
+            lock_seed: false,
+            target_particle_count,
+            spawn_shape: SpawnShape::Cube,
+            keep_particles_on_preset: false,
+            shell_inner_fraction: 0.8,
+            particle_render_radius: 0.05,
+            sample_count: 4,
+            reverse_z: false,
+            glow: false,
+            background_color: cc.egui_ctx.style().visuals.panel_fill,
+            fog_color: cc.egui_ctx.style().visuals.panel_fill,
+            fog_matches_background: true,
+            fog_density: 0.03,
+            show_border: true,
+            border_color: egui::Color32::WHITE,
+            screenshot_requested: false,
+            selected_particle: None,
+            brush_mode: BrushMode::Spawn,
+            brush_count: 5,
+            brush_delete_radius: 0.5,
+            brush_type: 0,
+            show_trails: false,
+            trail_length: 30,
+            trail_opacity: 0.5,
+            trail_history: VecDeque::new(),
+            show_arrows: false,
+            arrow_scale: 0.5,
+            show_cells: false,
+            slice: false,
+            slice_axis: 2,
+            slice_center: 0.0,
+            slice_thickness: 1.0,
+            color_mode: ColorMode::ByType,
+            size_mode: SizeMode::Uniform,
+            type_render_scales: vec![],
+            max_color_speed: 1.0,
+            paused: false,
+            single_step: false,
+            recorder: None,
+            quantize_recording: false,
+            player: None,
+            playback_frame: 0,
+            playback_playing: false,
+            #[cfg(feature = "serde")]
+            respawn_on_load: true,
+            #[cfg(feature = "serde")]
+            preset_status: None,
+            #[cfg(feature = "serde")]
+            session_path: "session.json".into(),
+            #[cfg(feature = "serde")]
+            session_status: None,
+            particles_path: "particles.csv".into(),
+            particles_status: None,
+            system_info: renderer.system_info.clone(),
         };
 
-        let render_state = cc.wgpu_render_state.as_ref().unwrap();
-        let renderer = Renderer::new(render_state);
         render_state
             .renderer
             .write()
@@ -141,79 +625,624 @@ impl App {
 
         app
     }
+
+    #[cfg(feature = "serde")]
+    fn preset_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("Save Preset").clicked() {
+                if let Some(path) = preset_file_dialog()
+                    .set_file_name("preset.toml")
+                    .save_file()
+                {
+                    self.preset_status = Some(
+                        std::fs::write(&path, self.particles.preset().to_toml())
+                            .map(|()| format!("Saved {}", path.display()))
+                            .map_err(|error| format!("Failed to save {}: {error}", path.display())),
+                    );
+                }
+            }
+            if ui.button("Load Preset").clicked() {
+                if let Some(path) = preset_file_dialog().pick_file() {
+                    self.preset_status = Some(self.load_preset(&path));
+                }
+            }
+            ui.checkbox(&mut self.respawn_on_load, "Respawn");
+        });
+        match &self.preset_status {
+            Some(Ok(message)) => {
+                ui.label(message);
+            }
+            Some(Err(error)) => {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+            None => {}
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    fn load_preset(&mut self, path: &std::path::Path) -> Result<String, String> {
+        let preset = std::fs::read_to_string(path)
+            .map_err(|error| format!("Failed to read {}: {error}", path.display()))?;
+        let preset = particle_life_3d::Preset::from_toml(&preset)
+            .map_err(|error| format!("Failed to parse {}: {error}", path.display()))?;
+        self.particles
+            .apply_preset(preset)
+            .map_err(|error| format!("Invalid preset {}: {error}", path.display()))?;
+        if self.respawn_on_load {
+            self.spawn(self.target_particle_count);
+        }
+        self.trail_history.clear();
+        Ok(format!("Loaded {}", path.display()))
+    }
+
+    #[cfg(feature = "serde")]
+    fn session_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Session: ");
+            ui.text_edit_singleline(&mut self.session_path);
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Save Session").clicked() {
+                self.session_status = Some(
+                    std::fs::write(&self.session_path, self.session().to_json())
+                        .map(|()| format!("Saved {}", self.session_path))
+                        .map_err(|error| format!("Failed to save {}: {error}", self.session_path)),
+                );
+            }
+            if ui.button("Load Session").clicked() {
+                self.session_status = Some(self.load_session());
+            }
+        });
+        match &self.session_status {
+            Some(Ok(message)) => {
+                ui.label(message);
+            }
+            Some(Err(error)) => {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+            None => {}
+        }
+    }
+
+    /// The particles, camera, and display settings, so the simulation can be carried on later with `apply_session`
+    #[cfg(feature = "serde")]
+    fn session(&self) -> Session {
+        Session {
+            version: SESSION_VERSION,
+            particles: self.particles.clone(),
+            camera: self.camera,
+            settings: SessionSettings {
+                paused: self.paused,
+                seed: self.seed,
+                target_particle_count: self.target_particle_count,
+                particle_render_radius: self.particle_render_radius,
+                color_mode: self.color_mode,
+                max_color_speed: self.max_color_speed,
+                size_mode: self.size_mode,
+                type_render_scales: self.type_render_scales.clone(),
+                fog_density: self.fog_density,
+                show_border: self.show_border,
+                show_trails: self.show_trails,
+                trail_length: self.trail_length,
+                trail_opacity: self.trail_opacity,
+                show_arrows: self.show_arrows,
+                arrow_scale: self.arrow_scale,
+            },
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    fn apply_session(&mut self, session: Session) {
+        self.particles = session.particles;
+        self.camera = session.camera;
+        self.camera_velocity = cgmath::Vector3::zero();
+        self.camera_angular_velocity = cgmath::Vector2::zero();
+        let settings = session.settings;
+        self.paused = settings.paused;
+        self.seed = settings.seed;
+        self.target_particle_count = settings.target_particle_count;
+        self.particle_render_radius = settings.particle_render_radius;
+        self.color_mode = settings.color_mode;
+        self.max_color_speed = settings.max_color_speed;
+        self.size_mode = settings.size_mode;
+        self.type_render_scales = settings.type_render_scales;
+        self.fog_density = settings.fog_density;
+        self.show_border = settings.show_border;
+        self.show_trails = settings.show_trails;
+        self.trail_length = settings.trail_length;
+        self.trail_opacity = settings.trail_opacity;
+        self.show_arrows = settings.show_arrows;
+        self.arrow_scale = settings.arrow_scale;
+        self.selected_particle = None;
+        self.trail_history.clear();
+    }
+
+    #[cfg(feature = "serde")]
+    fn load_session(&mut self) -> Result<String, String> {
+        let path = self.session_path.clone();
+        let session = std::fs::read_to_string(&path)
+            .map_err(|error| format!("Failed to read {path}: {error}"))?;
+        let session =
+            Session::from_json(&session).map_err(|error| format!("Cant load {path}: {error}"))?;
+        self.apply_session(session);
+        Ok(format!("Loaded {path}"))
+    }
+
+    fn particles_file_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Particles File: ");
+            ui.text_edit_singleline(&mut self.particles_path);
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Export Particles").clicked() {
+                self.particles_status = Some(self.export_particles());
+            }
+            if ui.button("Import Particles").clicked() {
+                let path = &self.particles_path;
+                self.particles_status = Some(
+                    self.particles
+                        .load_positions(std::path::Path::new(path))
+                        .map(|()| format!("Imported {path}"))
+                        .map_err(|error| format!("Failed to import {path}: {error}")),
+                );
+                if let Some(Ok(_)) = self.particles_status {
+                    self.selected_particle = None;
+                    self.trail_history.clear();
+                }
+            }
+        });
+        match &self.particles_status {
+            Some(Ok(message)) => {
+                ui.label(message);
+            }
+            Some(Err(error)) => {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+            None => {}
+        }
+    }
+
+    fn export_particles(&self) -> Result<String, String> {
+        let path = &self.particles_path;
+        let mut file = std::fs::File::create(path)
+            .map_err(|error| format!("Failed to create {path}: {error}"))?;
+        Particles::write_csv_header(&mut file)
+            .and_then(|()| self.particles.append_csv(&mut file, 0))
+            .map_err(|error| format!("Failed to write {path}: {error}"))?;
+        Ok(format!("Exported {path}"))
+    }
+
+    /// Replaces the particle types with the ones from a function in `presets`, respawning the particles unless `keep_particles_on_preset` is set
+    fn apply_attraction_preset(
+        &mut self,
+        (attraction_matrix, colors, _): (Vec<f32>, Vec<cgmath::Vector3<f32>>, u32),
+    ) {
+        self.particles.set_types(attraction_matrix, colors);
+        if !self.keep_particles_on_preset {
+            self.spawn(self.target_particle_count);
+            self.trail_history.clear();
+        }
+    }
+
+    /// Replaces all the particles with `count` particles placed in `spawn_shape`
+    fn spawn(&mut self, count: usize) {
+        match self.spawn_shape {
+            SpawnShape::Cube => self.particles.spawn_random(count, self.seed),
+            SpawnShape::Sphere => self.particles.spawn_in_sphere(count, self.seed),
+            SpawnShape::Shell => {
+                let outer_r = self.particles.min_world_size() * 0.5;
+                self.particles.spawn_in_shell(
+                    count,
+                    outer_r * self.shell_inner_fraction,
+                    outer_r,
+                    self.seed,
+                );
+            }
+        }
+    }
+
+    fn set_thread_count(&mut self, thread_count: Option<usize>) {
+        self.thread_count = thread_count;
+        self.thread_pool = thread_count.map(|thread_count| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(thread_count)
+                .build()
+                .unwrap()
+        });
+    }
+
+    fn system_info_ui(&self, ui: &mut egui::Ui) {
+        ui.collapsing("System Info", |ui| {
+            let info = &self.system_info;
+            ui.label(format!("Adapter: {}", info.adapter_name));
+            ui.label(format!("Backend: {}", info.backend));
+            ui.label(format!("Device Type: {}", info.device_type));
+            ui.label(format!("Driver: {}", info.driver));
+            for (name, value) in &info.limits {
+                ui.label(format!("{name}: {value}"));
+            }
+        });
+    }
+
+    fn replay_ui(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Replay", |ui| {
+            if let Some(frame_count) = self.recorder.as_ref().map(Recorder::frame_count) {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Recording: {frame_count} frames"));
+                    if ui.button("Stop").clicked() {
+                        let recorder = self.recorder.take().unwrap();
+                        // there is nothing to play back or save if no updates ran while recording
+                        if recorder.frame_count() == 0 {
+                            return;
+                        }
+                        self.player = Some(Player::from_recorder(&recorder));
+                        self.playback_frame = 0;
+                        self.playback_playing = false;
+
+                        let timestamp = std::time::SystemTime::now()
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .unwrap()
+                            .as_millis();
+                        let path = format!("recording-{timestamp}.plrp");
+                        match std::fs::write(&path, recorder.bytes()) {
+                            Ok(()) => println!("Saved recording to {path}"),
+                            Err(error) => eprintln!("Failed to save {path}: {error}"),
+                        }
+                    }
+                });
+            } else if let Some(frame_count) = self.player.as_ref().map(Player::frame_count) {
+                let last_frame = frame_count.saturating_sub(1);
+                ui.horizontal(|ui| {
+                    if ui
+                        .button(if self.playback_playing {
+                            "Pause"
+                        } else {
+                            "Play"
+                        })
+                        .clicked()
+                    {
+                        self.playback_playing = !self.playback_playing;
+                    }
+                    if ui.button("Close").clicked() {
+                        self.player = None;
+                    }
+                });
+                ui.add(egui::Slider::new(&mut self.playback_frame, 0..=last_frame).text("frame"));
+            } else {
+                ui.horizontal(|ui| {
+                    if ui.button("Record").clicked() {
+                        self.recorder = Some(Recorder::new(if self.quantize_recording {
+                            Precision::Quantized
+                        } else {
+                            Precision::Full
+                        }));
+                    }
+                    ui.checkbox(&mut self.quantize_recording, "Quantize Positions");
+                });
+            }
+        });
+    }
+
+    /// Moves the point the camera looks at towards the center of mass of the shown particles,
+    /// keeping the camera at the same distance from it
+    fn follow_center_of_mass(&mut self, ts: f32) {
+        if !self.follow_center_of_mass {
+            self.camera.target = None;
+            return;
+        }
+
+        let center_of_mass = match &self.player {
+            Some(player) => {
+                let (weighted_sum, total_mass) = player.frame(self.playback_frame).iter().fold(
+                    (cgmath::Vector3::zero(), 0.0),
+                    |(weighted_sum, total_mass), particle| {
+                        let mass = self.particles.mass(particle.id);
+                        (weighted_sum + particle.position * mass, total_mass + mass)
+                    },
+                );
+                if total_mass > 0.0 {
+                    weighted_sum / total_mass
+                } else {
+                    cgmath::Vector3::zero()
+                }
+            }
+            None => self.particles.center_of_mass(),
+        };
+        let target = match self.camera.target {
+            Some(target) => target,
+            None => {
+                // start by turning towards the center of mass so the camera doesnt jump
+                let offset = center_of_mass - self.camera.position;
+                if offset.magnitude2() > 0.0 {
+                    self.camera.look_along(offset);
+                }
+                center_of_mass
+            }
+        };
+        let distance = (self.camera.position - target).magnitude();
+        let target = target.lerp(center_of_mass, 1.0 - (-CAMERA_FOLLOW_SMOOTHING * ts).exp());
+        self.camera.position = target - self.camera.get_axes().forward * distance;
+        self.camera.target = Some(target);
+    }
+
+    /// Spawns or deletes particles along the ray under the cursor, depending on `brush_mode`
+    fn brush(&mut self, origin: cgmath::Vector3<f32>, direction: cgmath::Vector3<f32>) {
+        match self.brush_mode {
+            BrushMode::Spawn => self.brush_spawn(origin + direction * BRUSH_DISTANCE),
+            BrushMode::Delete => {
+                let sqr_radius = self.brush_delete_radius * self.brush_delete_radius;
+                let count = self.particles.current_particles.len();
+                self.particles.retain_particles(|particle| {
+                    let relative_position = particle.position - origin;
+                    let distance_along_ray = relative_position.dot(direction);
+                    distance_along_ray < 0.0
+                        || (relative_position - direction * distance_along_ray).magnitude2()
+                            > sqr_radius
+                });
+                if self.particles.current_particles.len() != count {
+                    // the indices have moved, so the selection could point at a different particle
+                    self.selected_particle = None;
+                }
+            }
+        }
+    }
+
+    /// Spawns `brush_count` particles of type `brush_type` around `center`, without going over `MAX_PARTICLE_COUNT`
+    fn brush_spawn(&mut self, center: cgmath::Vector3<f32>) {
+        if self.particles.id_count == 0 {
+            return;
+        }
+        let mut rng = thread_rng();
+        // the type can be removed after the brush ui has been shown this frame
+        let id = self.brush_type.min(self.particles.id_count - 1);
+        let count = self
+            .brush_count
+            .min(MAX_PARTICLE_COUNT.saturating_sub(self.particles.current_particles.len()));
+        for _ in 0..count {
+            let jitter = cgmath::vec3(
+                rng.gen_range(-BRUSH_JITTER..=BRUSH_JITTER),
+                rng.gen_range(-BRUSH_JITTER..=BRUSH_JITTER),
+                rng.gen_range(-BRUSH_JITTER..=BRUSH_JITTER),
+            );
+            let mut position = self.particles.wrap_position(center + jitter);
+            if self.particles.dimensions == Dimensions::Two {
+                position.z = 0.0;
+            }
+            self.particles.current_particles.push(Particle {
+                position,
+                velocity: cgmath::vec3(0.0, 0.0, 0.0),
+                id,
+            });
+        }
+    }
+
+    fn step(&mut self, ts: f32) {
+        let substep_ts = ts / self.substeps as f32;
+        for _ in 0..self.substeps {
+            if self.gpu_update {
+                self.gpu_updater.update(&mut self.particles, substep_ts);
+            } else if let Some(thread_pool) = &self.thread_pool {
+                thread_pool.install(|| self.particles.update(substep_ts));
+            } else {
+                self.particles.update(substep_ts);
+            }
+        }
+
+        if self.spatial_sort_interval > 0 {
+            self.ticks_since_spatial_sort += 1;
+            if self.ticks_since_spatial_sort >= self.spatial_sort_interval {
+                self.ticks_since_spatial_sort = 0;
+                let order = self.particles.spatial_sort();
+                // keep the selection and trails on the same particles
+                self.selected_particle = self.selected_particle.and_then(|selected_particle| {
+                    order.iter().position(|&index| index == selected_particle)
+                });
+                for positions in &mut self.trail_history {
+                    if positions.len() == order.len() {
+                        *positions = order.iter().map(|&index| positions[index]).collect();
+                    }
+                }
+            }
+        }
+
+        if let Some(recorder) = &mut self.recorder {
+            recorder.push(&self.particles);
+        }
+
+        if self.show_trails {
+            // the history is useless once the particles dont line up with it anymore
+            if self
+                .trail_history
+                .front()
+                .is_some_and(|positions| positions.len() != self.particles.current_particles.len())
+            {
+                self.trail_history.clear();
+            }
+            self.trail_history.push_back(
+                self.particles
+                    .current_particles
+                    .iter()
+                    .map(|particle| particle.position)
+                    .collect(),
+            );
+            while self.trail_history.len() > self.trail_length {
+                self.trail_history.pop_front();
+            }
+        } else {
+            self.trail_history.clear();
+        }
+    }
 }
 
 impl eframe::App for App {
-    fn update(&mut self, ctx: &eframe::egui::Context, _frame: &mut eframe::Frame) {
+    /// This runs however the app quits, so the parameters are still saved when `on_close_event` is skipped
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        #[cfg(feature = "serde")]
+        if let Err(error) = save_autosave(self.particles.preset()) {
+            eprintln!("Failed to autosave the parameters: {error}");
+        }
+    }
+
+    fn update(&mut self, ctx: &eframe::egui::Context, frame: &mut eframe::Frame) {
         let time = std::time::Instant::now();
         let ts = time.duration_since(self.last_time);
         self.last_time = time;
 
-        self.fixed_time += ts;
+        self.fixed_time += ts.mul_f32(self.time_scale);
         let start_update = std::time::Instant::now();
+        let update_budget = ts.mul_f32(self.update_budget);
         {
-            const MAX_ITERATIONS: usize = 2;
+            // faster time scales need more iterations per frame to keep up, but too many would make frames slow
+            // which would need even more iterations next frame
+            const MAX_ITERATIONS_CAP: usize = 16;
+            let max_iterations = ((2.0 * self.time_scale).ceil() as usize).min(MAX_ITERATIONS_CAP);
             let mut iterations = 0;
             let ts = 1.0 / self.ticks_per_second;
-            while iterations < MAX_ITERATIONS && (self.fixed_time.as_secs_f32() >= ts) {
-                self.particles.update(ts);
+            let paused = self.paused || self.player.is_some();
+            let mut over_budget = false;
+            if paused {
+                // dont let time build up while paused, otherwise resuming would have to catch up on all of it
+                self.fixed_time = std::time::Duration::ZERO;
+                if std::mem::take(&mut self.single_step) {
+                    self.step(ts);
+                }
+            }
+            while !paused && iterations < max_iterations && (self.fixed_time.as_secs_f32() >= ts) {
+                if self.adaptive_tick_rate
+                    && iterations > 0
+                    && start_update.elapsed() > update_budget
+                {
+                    over_budget = true;
+                    break;
+                }
+                self.step(ts);
                 self.fixed_time -= std::time::Duration::from_secs_f32(ts);
                 iterations += 1;
             }
-            if iterations == MAX_ITERATIONS {
+            self.running_slow = false;
+            if over_budget || iterations == max_iterations {
+                // drop the ticks that didnt fit instead of catching up on them next frame,
+                // otherwise one slow frame would make the next frames even slower
                 let iterations_skipped = (self.fixed_time.as_secs_f32() / ts) as usize;
-                eprintln!(
-                    "Cant keep up, skipped {} physics iterations",
-                    iterations_skipped
-                );
-                self.fixed_time -=
-                    std::time::Duration::from_secs_f32(iterations_skipped as f32 * ts);
+                if iterations_skipped > 0 {
+                    self.running_slow = true;
+                    eprintln!(
+                        "Cant keep up, skipped {} physics iterations",
+                        iterations_skipped
+                    );
+                    self.fixed_time -=
+                        std::time::Duration::from_secs_f32(iterations_skipped as f32 * ts);
+                }
             }
         }
         let update_elapsed = start_update.elapsed();
 
         let ts = ts.as_secs_f32();
 
+        if let Some(player) = &self.player {
+            if self.playback_playing {
+                self.playback_frame += 1;
+            }
+            if self.playback_frame + 1 >= player.frame_count() {
+                self.playback_frame = player.frame_count().saturating_sub(1);
+                self.playback_playing = false;
+            }
+        }
+
+        for (history, milliseconds) in [
+            (&mut self.frame_time_history, ts * 1000.0),
+            (
+                &mut self.update_time_history,
+                update_elapsed.as_secs_f32() * 1000.0,
+            ),
+        ] {
+            if history.len() == TIMING_HISTORY_LENGTH {
+                history.pop_front();
+            }
+            history.push_back(milliseconds);
+        }
+
+        // the velocity the keys are asking for, movement is along the camera's right, up, and forward axes
+        // and rotation is the change in pitch and yaw
+        let mut target_velocity = cgmath::Vector3::zero();
+        let mut target_angular_velocity = cgmath::Vector2::zero();
         if !ctx.wants_keyboard_input() {
             ctx.input(|i| {
-                let axes = self.camera.get_axes();
+                let speed = if i.modifiers.shift {
+                    self.camera_speed * CAMERA_SPRINT_MULTIPLIER
+                } else {
+                    self.camera_speed
+                };
+                let rotation_speed = self.camera_rotation_speed;
+                let axis = |positive: egui::Key, negative: egui::Key| {
+                    i.key_down(positive) as i32 as f32 - i.key_down(negative) as i32 as f32
+                };
 
-                if i.key_down(egui::Key::W) {
-                    self.camera.position += axes.forward * CAMERA_SPEED * ts;
-                }
-                if i.key_down(egui::Key::S) {
-                    self.camera.position -= axes.forward * CAMERA_SPEED * ts;
-                }
-                if i.key_down(egui::Key::A) {
-                    self.camera.position -= axes.right * CAMERA_SPEED * ts;
-                }
-                if i.key_down(egui::Key::D) {
-                    self.camera.position += axes.right * CAMERA_SPEED * ts;
-                }
-                if i.key_down(egui::Key::Q) {
-                    self.camera.position -= axes.up * CAMERA_SPEED * ts;
-                }
-                if i.key_down(egui::Key::E) {
-                    self.camera.position += axes.up * CAMERA_SPEED * ts;
-                }
-
-                if i.key_down(egui::Key::ArrowUp) {
-                    self.camera.pitch += CAMERA_ROTATION_SPEED * ts;
-                }
-                if i.key_down(egui::Key::ArrowDown) {
-                    self.camera.pitch -= CAMERA_ROTATION_SPEED * ts;
-                }
-                if i.key_down(egui::Key::ArrowLeft) {
-                    self.camera.yaw -= CAMERA_ROTATION_SPEED * ts;
-                }
-                if i.key_down(egui::Key::ArrowRight) {
-                    self.camera.yaw += CAMERA_ROTATION_SPEED * ts;
+                match self.camera.mode {
+                    CameraMode::Fly => {
+                        target_velocity = cgmath::vec3(
+                            axis(egui::Key::D, egui::Key::A),
+                            axis(egui::Key::E, egui::Key::Q),
+                            axis(egui::Key::W, egui::Key::S),
+                        ) * speed;
+                    }
+                    // W and S zoom, the other keys rotate around the orbited point
+                    CameraMode::Orbit => {
+                        target_velocity.z = axis(egui::Key::W, egui::Key::S) * speed;
+                        target_angular_velocity += cgmath::vec2(
+                            axis(egui::Key::Q, egui::Key::E),
+                            axis(egui::Key::A, egui::Key::D),
+                        ) * rotation_speed;
+                    }
                 }
 
-                self.camera.pitch = self.camera.pitch.clamp(-89.9999, 89.9999);
+                target_angular_velocity += cgmath::vec2(
+                    axis(egui::Key::ArrowUp, egui::Key::ArrowDown),
+                    axis(egui::Key::ArrowRight, egui::Key::ArrowLeft),
+                ) * rotation_speed;
             });
         }
 
+        self.camera_velocity = if self.smooth_camera_movement {
+            ease_velocity(
+                self.camera_velocity,
+                target_velocity,
+                self.camera_acceleration,
+                self.camera_damping,
+                ts,
+            )
+        } else {
+            target_velocity
+        };
+        self.camera_angular_velocity = if self.smooth_camera_rotation {
+            ease_velocity(
+                self.camera_angular_velocity,
+                target_angular_velocity,
+                self.camera_acceleration,
+                self.camera_damping,
+                ts,
+            )
+        } else {
+            target_angular_velocity
+        };
+
+        let axes = self.camera.get_axes();
+        match self.camera.mode {
+            CameraMode::Fly => {
+                self.camera.position += (axes.right * self.camera_velocity.x
+                    + axes.up * self.camera_velocity.y
+                    + axes.forward * self.camera_velocity.z)
+                    * ts;
+            }
+            CameraMode::Orbit => {
+                self.camera.orbit_radius =
+                    (self.camera.orbit_radius - self.camera_velocity.z * ts).max(0.0);
+            }
+        }
+        self.camera.pitch =
+            (self.camera.pitch + self.camera_angular_velocity.x * ts).clamp(-89.9999, 89.9999);
+        self.camera.yaw += self.camera_angular_velocity.y * ts;
+
         egui::SidePanel::left("Left Panel").show(ctx, |ui| {
             egui::ScrollArea::vertical().show(ui, |ui| {
                 ui.label(format!("FPS: {:.3}", 1.0 / ts));
@@ -222,24 +1251,185 @@ impl eframe::App for App {
                     "Update Time: {:.3}ms",
                     update_elapsed.as_secs_f64() * 1000.0
                 ));
+                if self.running_slow {
+                    ui.colored_label(
+                        ui.visuals().warn_fg_color,
+                        "The simulation is running slower than real time",
+                    );
+                }
+                // the gpu updater doesnt count the pairs it evaluates or the wall hits
+                if !self.gpu_update {
+                    ui.label(format!(
+                        "Force Pairs: {}",
+                        self.particles.last_step_pair_count
+                    ))
+                    .on_hover_text("The number of pairs of particles close enough to exert a force on each other in the last update");
+                    ui.label(format!(
+                        "Wall Hits: {}",
+                        self.particles.last_step_wall_hits
+                    ))
+                    .on_hover_text("The number of particles that hit a solid wall in the last update");
+                }
+                let history_line = |history: &VecDeque<f32>, name: &str| {
+                    egui::plot::Line::new(
+                        history
+                            .iter()
+                            .enumerate()
+                            .map(|(i, &milliseconds)| [i as f64, milliseconds as f64])
+                            .collect::<egui::plot::PlotPoints>(),
+                    )
+                    .name(name)
+                };
+                egui::plot::Plot::new("Timing History Plot")
+                    .height(100.0)
+                    .include_x(0.0)
+                    .include_x(TIMING_HISTORY_LENGTH as f64)
+                    .include_y(0.0)
+                    .allow_drag(false)
+                    .allow_zoom(false)
+                    .allow_scroll(false)
+                    .show_x(false)
+                    .y_axis_formatter(|milliseconds, _| format!("{milliseconds}ms"))
+                    .legend(egui::plot::Legend::default())
+                    .show(ui, |plot_ui| {
+                        plot_ui.line(history_line(&self.frame_time_history, "Frame Time"));
+                        plot_ui.line(history_line(&self.update_time_history, "Update Time"));
+                    });
+                ui.label(format!(
+                    "Kinetic Energy: {:.3}",
+                    self.particles.total_kinetic_energy()
+                ));
+                let momentum = self.particles.total_momentum();
+                ui.label(format!(
+                    "Momentum: {:.3}, {:.3}, {:.3}",
+                    momentum.x, momentum.y, momentum.z
+                ));
+                let bars = self
+                    .particles
+                    .count_by_type()
+                    .into_iter()
+                    .zip(&self.particles.colors)
+                    .enumerate()
+                    .map(|(id, (count, color))| {
+                        egui::plot::Bar::new(id as f64, count as f64)
+                            .fill(egui::Rgba::from_rgb(color.x, color.y, color.z))
+                            .name(format!("Type {id}"))
+                    })
+                    .collect();
+                egui::plot::Plot::new("Type Count Plot")
+                    .height(100.0)
+                    .include_y(0.0)
+                    .allow_drag(false)
+                    .allow_zoom(false)
+                    .allow_scroll(false)
+                    .show(ui, |plot_ui| {
+                        plot_ui.bar_chart(egui::plot::BarChart::new(bars).name("Particles"))
+                    });
+                // the index can be out of date if particles were removed since it was selected
+                if let Some(index) = self
+                    .selected_particle
+                    .filter(|&index| index < self.particles.current_particles.len())
+                {
+                    let Particle {
+                        position,
+                        velocity,
+                        id,
+                    } = self.particles.current_particles[index];
+                    ui.horizontal(|ui| {
+                        ui.label(format!("Selected Particle: {index}"));
+                        if ui.button("Deselect").clicked() {
+                            self.selected_particle = None;
+                        }
+                    });
+                    ui.label(format!("Id: {id}"));
+                    ui.label(format!(
+                        "Position: {:.3}, {:.3}, {:.3}",
+                        position.x, position.y, position.z
+                    ));
+                    ui.label(format!(
+                        "Velocity: {:.3}, {:.3}, {:.3}",
+                        velocity.x, velocity.y, velocity.z
+                    ));
+                    ui.label(format!(
+                        "Neighbours: {}",
+                        self.particles.neighbour_count(index)
+                    ));
+                }
                 ui.horizontal(|ui| {
-                    ui.label("Particle Count: ");
-                    let mut particle_count = self.particles.current_particles.len();
                     if ui
-                        .add(egui::DragValue::new(&mut particle_count).speed(0.1))
-                        .changed()
+                        .button(if self.paused { "Resume" } else { "Pause" })
+                        .clicked()
+                    {
+                        self.paused = !self.paused;
+                    }
+                    if ui
+                        .add_enabled(self.paused, egui::Button::new("Step"))
+                        .clicked()
                     {
+                        self.single_step = true;
+                    }
+                    if ui.button("Reset").clicked() {
+                        let particle_count = self.particles.current_particles.len();
+                        self.spawn(particle_count);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Seed: ");
+                    ui.add(egui::DragValue::new(&mut self.next_seed));
+                    ui.checkbox(&mut self.lock_seed, "Lock")
+                        .on_hover_text("Keeps the seed the same after respawning instead of moving on to the next one");
+                    if ui.button("Respawn").clicked() {
+                        self.seed = self.next_seed;
+                        self.spawn(self.target_particle_count);
+                        self.trail_history.clear();
+                        if !self.lock_seed {
+                            self.next_seed = self.next_seed.wrapping_add(1);
+                        }
+                    }
+                });
+                ui.label(format!("Current Seed: {}", self.seed))
+                    .on_hover_text("The seed the particles were last spawned with, reset uses it too");
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_label("Spawn Shape")
+                        .selected_text(match self.spawn_shape {
+                            SpawnShape::Cube => "Cube",
+                            SpawnShape::Sphere => "Sphere",
+                            SpawnShape::Shell => "Shell",
+                        })
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut self.spawn_shape, SpawnShape::Cube, "Cube");
+                            ui.selectable_value(&mut self.spawn_shape, SpawnShape::Sphere, "Sphere");
+                            ui.selectable_value(&mut self.spawn_shape, SpawnShape::Shell, "Shell");
+                        });
+                });
+                if self.spawn_shape == SpawnShape::Shell {
+                    ui.horizontal(|ui| {
+                        ui.label("Shell Inner Radius: ");
+                        ui.add(egui::Slider::new(&mut self.shell_inner_fraction, 0.0..=1.0))
+                            .on_hover_text("As a fraction of the outer radius, which is the largest sphere that fits in the world");
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Particle Count: ");
+                    ui.add(
+                        egui::DragValue::new(&mut self.target_particle_count)
+                            .clamp_range(1..=MAX_PARTICLE_COUNT)
+                            .speed(0.1),
+                    );
+                    if ui.button("Apply").clicked() {
+                        let particle_count = self.target_particle_count;
                         match particle_count.cmp(&self.particles.current_particles.len()) {
                             Ordering::Less => {
                                 self.particles.current_particles.truncate(particle_count);
                             }
                             Ordering::Greater => {
-                                self.particles.current_particles.extend(
-                                    std::iter::repeat_with(|| {
-                                        random_particle(self.particles.world_size)
-                                    })
-                                    .take(particle_count - self.particles.current_particles.len()),
-                                );
+                                let mut rng = thread_rng();
+                                let new_particles: Vec<_> = std::iter::repeat_with(|| {
+                                    self.particles.random_particle(&mut rng)
+                                })
+                                .take(particle_count - self.particles.current_particles.len())
+                                .collect();
+                                self.particles.current_particles.extend(new_particles);
                             }
                             Ordering::Equal => {}
                         }
@@ -247,20 +1437,147 @@ impl eframe::App for App {
                 });
                 ui.horizontal(|ui| {
                     ui.label("World Size: ");
-                    ui.add(egui::DragValue::new(&mut self.particles.world_size).speed(0.1));
-                    self.particles.world_size = self
-                        .particles
-                        .world_size
-                        .max(self.particles.particle_effect_radius * 2.0);
+                    let mut world_size = self.particles.world_size;
+                    for (axis, name) in ["x: ", "y: ", "z: "].into_iter().enumerate() {
+                        ui.add(
+                            egui::DragValue::new(&mut world_size[axis])
+                                .prefix(name)
+                                .speed(0.1),
+                        );
+                    }
+                    let world_size = world_size
+                        .map(|size| size.max(self.particles.particle_effect_radius * 2.0));
+                    if world_size != self.particles.world_size {
+                        if self.rescale_on_world_resize {
+                            self.particles.rescale_world(world_size);
+                        } else {
+                            self.particles.world_size = world_size;
+                        }
+                    }
+                });
+                ui.checkbox(
+                    &mut self.rescale_on_world_resize,
+                    "Rescale Particles On World Resize",
+                );
+                ui.horizontal(|ui| {
+                    ui.label("Dimensions: ");
+                    ui.radio_value(&mut self.particles.dimensions, Dimensions::Two, "2D");
+                    ui.radio_value(&mut self.particles.dimensions, Dimensions::Three, "3D");
                 });
                 ui.horizontal(|ui| {
                     ui.label("Solid Walls: ");
-                    ui.checkbox(&mut self.particles.solid_walls, "");
+                    for (boundary, axis) in
+                        self.particles.boundaries.iter_mut().zip(["x", "y", "z"])
+                    {
+                        let mut solid = *boundary == BoundaryMode::Solid;
+                        ui.checkbox(&mut solid, axis);
+                        *boundary = if solid {
+                            BoundaryMode::Solid
+                        } else {
+                            BoundaryMode::Periodic
+                        };
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Wall Restitution: ");
+                    ui.add(egui::Slider::new(
+                        &mut self.particles.wall_restitution,
+                        0.0..=1.0,
+                    ));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Acceleration Structure: ");
+                    ui.radio_value(
+                        &mut self.particles.accel_structure,
+                        AccelStructure::SpatialHash,
+                        "Spatial Hash",
+                    );
+                    ui.radio_value(
+                        &mut self.particles.accel_structure,
+                        AccelStructure::UniformGrid,
+                        "Uniform Grid",
+                    );
+                    ui.radio_value(
+                        &mut self.particles.accel_structure,
+                        AccelStructure::MortonHash,
+                        "Morton Hash",
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Deterministic: ");
+                    ui.checkbox(&mut self.particles.deterministic, "")
+                        .on_hover_text("Adds up the forces in the same order every update, so the results dont depend on the number of threads. This is slower and doesnt affect the GPU update");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Limit Neighbours: ");
+                    let mut enabled = self.particles.max_neighbours.is_some();
+                    ui.checkbox(&mut enabled, "").on_hover_text(
+                        "Stops adding up the forces on each particle after looking at this many nearby particles, so dense clusters cant slow the update down as much. This is less accurate and doesnt affect the GPU update",
+                    );
+                    if enabled {
+                        let max_neighbours = self.particles.max_neighbours.get_or_insert(64);
+                        ui.add(
+                            egui::DragValue::new(max_neighbours)
+                                .clamp_range(1..=usize::MAX)
+                                .speed(0.1),
+                        );
+                    } else {
+                        self.particles.max_neighbours = None;
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("GPU Update: ");
+                    ui.checkbox(&mut self.gpu_update, "");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Limit CPU Threads: ");
+                    let mut limited = self.thread_count.is_some();
+                    let mut thread_count =
+                        self.thread_count.unwrap_or_else(rayon::current_num_threads);
+                    ui.checkbox(&mut limited, "");
+                    if limited {
+                        let max_threads = std::thread::available_parallelism()
+                            .map_or(thread_count, |threads| threads.get());
+                        ui.add(egui::Slider::new(&mut thread_count, 1..=max_threads));
+                    }
+                    let thread_count = limited.then_some(thread_count);
+                    if thread_count != self.thread_count {
+                        self.set_thread_count(thread_count);
+                    }
                 });
                 ui.horizontal(|ui| {
                     ui.label("Ticks Per Second: ");
                     ui.add(egui::Slider::new(&mut self.ticks_per_second, 1.0..=1000.0));
                 });
+                ui.horizontal(|ui| {
+                    ui.label("Substeps: ");
+                    ui.add(egui::Slider::new(&mut self.substeps, 1..=16));
+                    ui.label(format!(
+                        "dt: {:.5}s",
+                        1.0 / (self.ticks_per_second * self.substeps as f32)
+                    ));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Adaptive Tick Rate: ");
+                    ui.checkbox(&mut self.adaptive_tick_rate, "")
+                        .on_hover_text("Drops ticks when they take too long, so the framerate stays smooth while the simulation slows down");
+                });
+                if self.adaptive_tick_rate {
+                    ui.horizontal(|ui| {
+                        ui.label("Update Budget: ");
+                        ui.add(egui::Slider::new(&mut self.update_budget, 0.1..=1.0))
+                            .on_hover_text("The fraction of the frame time that ticks can take");
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Spatial Sort Interval: ");
+                    ui.add(egui::Slider::new(&mut self.spatial_sort_interval, 0..=600))
+                        .on_hover_text("The number of ticks between reordering the particles in memory so nearby particles are next to each other, this speeds up the update. 0 never reorders them");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Time Scale: ");
+                    ui.add(egui::Slider::new(&mut self.time_scale, 0.1..=8.0).logarithmic(true));
+                });
                 ui.horizontal(|ui| {
                     ui.label("Friction: ");
                     ui.add(
@@ -268,6 +1585,26 @@ impl eframe::App for App {
                             .drag_value_speed(0.01),
                     );
                 });
+                ui.horizontal(|ui| {
+                    ui.label("Thermostat: ");
+                    let mut enabled = self.particles.target_speed.is_some();
+                    ui.checkbox(&mut enabled, "");
+                    if enabled {
+                        let target_speed = self.particles.target_speed.get_or_insert(1.0);
+                        ui.add(
+                            egui::DragValue::new(target_speed)
+                                .prefix("target speed: ")
+                                .speed(0.01)
+                                .clamp_range(0.0..=f32::INFINITY),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut self.particles.thermostat_coupling, 0.0..=10.0)
+                                .text("coupling"),
+                        );
+                    } else {
+                        self.particles.target_speed = None;
+                    }
+                });
                 ui.horizontal(|ui| {
                     ui.label("Force Scale: ");
                     ui.add(egui::Slider::new(
@@ -276,124 +1613,736 @@ impl eframe::App for App {
                     ));
                 });
                 ui.horizontal(|ui| {
-                    ui.label("Particle Effect Radius: ");
-                    ui.add(egui::Slider::new(
-                        &mut self.particles.particle_effect_radius,
-                        0.0..=self.particles.world_size / 2.0,
-                    ));
+                    ui.label("Particle Render Radius: ");
+                    ui.add(
+                        egui::Slider::new(&mut self.particle_render_radius, 0.01..=0.5)
+                            .logarithmic(true),
+                    );
                 });
                 ui.horizontal(|ui| {
-                    ui.label("Repulsion Distance Percentage: ");
-                    ui.add(egui::Slider::new(
-                        &mut self.particles.min_attraction_percentage,
-                        0.0..=1.0,
-                    ));
+                    ui.label("Projection: ");
+                    let perspective =
+                        matches!(self.camera.projection, Projection::Perspective { .. });
+                    if ui.radio(perspective, "Perspective").clicked() && !perspective {
+                        self.camera.projection = Projection::Perspective { fov_deg: 90.0 };
+                    }
+                    if ui.radio(!perspective, "Orthographic").clicked() && perspective {
+                        self.camera.projection = Projection::Orthographic {
+                            height: self.particles.world_size.y,
+                        };
+                    }
+                });
+                ui.horizontal(|ui| match &mut self.camera.projection {
+                    Projection::Perspective { fov_deg } => {
+                        ui.label("Field Of View: ");
+                        ui.add(egui::Slider::new(fov_deg, 10.0..=170.0).suffix("°"));
+                    }
+                    Projection::Orthographic { height } => {
+                        ui.label("View Height: ");
+                        ui.add(
+                            egui::DragValue::new(height)
+                                .clamp_range(0.01..=f32::INFINITY)
+                                .speed(0.1),
+                        );
+                    }
                 });
                 ui.horizontal(|ui| {
-                    ui.label("Gravity: ");
+                    ui.label("Camera Speed: ");
                     ui.add(
-                        egui::DragValue::new(&mut self.particles.gravity.x)
-                            .prefix("x: ")
-                            .speed(0.01),
+                        egui::Slider::new(&mut self.camera_speed, 0.1..=100.0).logarithmic(true),
+                    )
+                    .on_hover_text("Hold shift to move faster");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Camera Rotation Speed: ");
+                    ui.add(
+                        egui::Slider::new(&mut self.camera_rotation_speed, 10.0..=360.0)
+                            .suffix("°/s"),
                     );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Smooth Camera: ");
+                    ui.checkbox(&mut self.smooth_camera_movement, "Movement");
+                    ui.checkbox(&mut self.smooth_camera_rotation, "Rotation");
+                });
+                if self.smooth_camera_movement || self.smooth_camera_rotation {
+                    ui.horizontal(|ui| {
+                        ui.label("Camera Acceleration: ");
+                        ui.add(
+                            egui::Slider::new(&mut self.camera_acceleration, 0.5..=50.0)
+                                .logarithmic(true),
+                        )
+                        .on_hover_text("How quickly the camera gets up to speed");
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Camera Damping: ");
+                        ui.add(
+                            egui::Slider::new(&mut self.camera_damping, 0.5..=50.0)
+                                .logarithmic(true),
+                        )
+                        .on_hover_text("How quickly the camera stops once the keys are released");
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Camera Mode: ");
+                    if ui
+                        .radio(self.camera.mode == CameraMode::Fly, "Fly")
+                        .clicked()
+                    {
+                        self.camera.mode = CameraMode::Fly;
+                    }
+                    if ui
+                        .radio(self.camera.mode == CameraMode::Orbit, "Orbit")
+                        .clicked()
+                        && self.camera.mode != CameraMode::Orbit
+                    {
+                        // start orbiting from where the camera is, so it doesnt jump
+                        let center = self.camera.target.unwrap_or(cgmath::Vector3::zero());
+                        let offset = center - self.camera.position;
+                        self.camera.orbit_radius = offset.magnitude();
+                        if offset.magnitude2() > 0.0 {
+                            self.camera.look_along(offset);
+                        }
+                        self.camera.mode = CameraMode::Orbit;
+                    }
+                });
+                if self.camera.mode == CameraMode::Orbit {
+                    ui.horizontal(|ui| {
+                        ui.label("Orbit Radius: ");
+                        ui.add(
+                            egui::DragValue::new(&mut self.camera.orbit_radius)
+                                .clamp_range(0.0..=f32::INFINITY)
+                                .speed(0.1),
+                        );
+                    });
+                }
+                ui.checkbox(
+                    &mut self.follow_center_of_mass,
+                    "Follow Center Of Mass",
+                )
+                .on_hover_text("Keeps the camera looking at the center of mass, moving and turning orbits around it");
+                ui.horizontal(|ui| {
+                    ui.label("Clip Planes: ");
                     ui.add(
-                        egui::DragValue::new(&mut self.particles.gravity.y)
-                            .prefix("y: ")
-                            .speed(0.01),
+                        egui::DragValue::new(&mut self.camera.near)
+                            .prefix("near: ")
+                            .clamp_range(0.0001..=self.camera.far * 0.5)
+                            .speed(0.001),
+                    )
+                    .on_hover_text("Smaller values let you get closer to particles, but leave less depth precision for everything further away");
+                    ui.add(
+                        egui::DragValue::new(&mut self.camera.far)
+                            .prefix("far: ")
+                            .clamp_range(self.camera.near * 2.0..=f32::INFINITY)
+                            .speed(1.0),
                     );
+                });
+                egui::ComboBox::from_label("Anti-Aliasing")
+                    .selected_text(match self.sample_count {
+                        1 => "Off".to_string(),
+                        sample_count => format!("{sample_count}x MSAA"),
+                    })
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.sample_count, 1, "Off");
+                        ui.selectable_value(&mut self.sample_count, 4, "4x MSAA");
+                        ui.selectable_value(&mut self.sample_count, 8, "8x MSAA");
+                    });
+                ui.horizontal(|ui| {
+                    ui.label("Reverse Z: ");
+                    ui.checkbox(&mut self.reverse_z, "").on_hover_text(
+                        "Gives distant particles more depth precision, which stops them flickering in large worlds",
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Fog Density: ");
+                    ui.add(
+                        egui::DragValue::new(&mut self.fog_density)
+                            .clamp_range(0.0..=f32::INFINITY)
+                            .speed(0.001),
+                    );
+                    ui.checkbox(&mut self.fog_matches_background, "Match Background");
+                    if !self.fog_matches_background {
+                        ui.label("Fog Color: ");
+                        ui.color_edit_button_srgba(&mut self.fog_color);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Background Color: ");
+                    ui.color_edit_button_srgba(&mut self.background_color);
+                });
+                ui.horizontal(|ui| {
+                    ui.label("World Border: ");
+                    ui.checkbox(&mut self.show_border, "");
+                    if self.show_border {
+                        ui.color_edit_button_srgba(&mut self.border_color);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Glow: ");
+                    ui.checkbox(&mut self.glow, "")
+                        .on_hover_text("Overlapping particles add together into a brighter glow");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Color Mode: ");
+                    ui.radio_value(&mut self.color_mode, ColorMode::ByType, "Type");
+                    ui.radio_value(&mut self.color_mode, ColorMode::BySpeed, "Speed");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Size Mode: ");
+                    ui.radio_value(&mut self.size_mode, SizeMode::Uniform, "Uniform");
+                    ui.radio_value(&mut self.size_mode, SizeMode::ByType, "Type")
+                        .on_hover_text("The scale of each type is set in the particle properties");
+                    ui.radio_value(&mut self.size_mode, SizeMode::BySpeed, "Speed");
+                });
+                if self.color_mode == ColorMode::BySpeed || self.size_mode == SizeMode::BySpeed {
+                    ui.horizontal(|ui| {
+                        ui.label("Max Color Speed: ");
+                        ui.add(
+                            egui::DragValue::new(&mut self.max_color_speed)
+                                .clamp_range(0.001..=f32::INFINITY)
+                                .speed(0.01),
+                        );
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Trails: ");
+                    ui.checkbox(&mut self.show_trails, "");
+                });
+                if self.show_trails {
+                    ui.horizontal(|ui| {
+                        ui.label("Trail Length: ");
+                        ui.add(egui::Slider::new(&mut self.trail_length, 2..=200));
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Trail Opacity: ");
+                        ui.add(egui::Slider::new(&mut self.trail_opacity, 0.0..=1.0));
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Slice: ");
+                    ui.checkbox(&mut self.slice, "")
+                        .on_hover_text("Only draws the particles in a slab through the world, to see inside dense clouds");
+                });
+                if self.slice {
+                    ui.horizontal(|ui| {
+                        ui.label("Slice Axis: ");
+                        ui.radio_value(&mut self.slice_axis, 0, "X");
+                        ui.radio_value(&mut self.slice_axis, 1, "Y");
+                        ui.radio_value(&mut self.slice_axis, 2, "Z");
+                    });
+                    let half_size = self.particles.world_size[self.slice_axis] * 0.5;
+                    ui.horizontal(|ui| {
+                        ui.label("Slice Center: ");
+                        ui.add(egui::Slider::new(
+                            &mut self.slice_center,
+                            -half_size..=half_size,
+                        ));
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Slice Thickness: ");
+                        ui.add(
+                            egui::DragValue::new(&mut self.slice_thickness)
+                                .clamp_range(0.0..=f32::INFINITY)
+                                .speed(0.01),
+                        )
+                        .on_hover_text("How far particles can be from the center of the slice along its axis");
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Occupied Cells: ");
+                    ui.checkbox(&mut self.show_cells, "")
+                        .on_hover_text("Draws the cells that particles are sorted into to find their neighbours");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Velocity Arrows: ");
+                    ui.checkbox(&mut self.show_arrows, "");
+                });
+                if self.show_arrows {
+                    ui.horizontal(|ui| {
+                        ui.label("Arrow Length Scale: ");
+                        ui.add(
+                            egui::Slider::new(&mut self.arrow_scale, 0.01..=10.0)
+                                .logarithmic(true),
+                        );
+                    });
+                }
+                ui.horizontal(|ui| {
+                    ui.label("Particle Effect Radius: ");
                     ui.add(
-                        egui::DragValue::new(&mut self.particles.gravity.z)
-                            .prefix("z: ")
+                        egui::DragValue::new(&mut self.particles.particle_effect_radius)
+                            .clamp_range(0.01..=f32::INFINITY)
                             .speed(0.01),
                     );
+                    // grow the world instead of limiting the radius, particles can only interact with one copy of each other
+                    self.particles.world_size = self
+                        .particles
+                        .world_size
+                        .map(|size| size.max(self.particles.particle_effect_radius * 2.0));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Repulsion Distance Percentage: ");
+                    ui.add(egui::Slider::new(
+                        &mut self.particles.min_attraction_percentage,
+                        0.0..=1.0,
+                    ));
+                });
+                egui::ComboBox::from_label("Force Profile")
+                    .selected_text(format!("{:?}", self.particles.force_profile))
+                    .show_ui(ui, |ui| {
+                        for force_profile in [
+                            ForceProfile::ParticleLifeDefault,
+                            ForceProfile::SmoothQuadratic,
+                            ForceProfile::LennardJonesLike,
+                        ] {
+                            ui.selectable_value(
+                                &mut self.particles.force_profile,
+                                force_profile,
+                                format!("{force_profile:?}"),
+                            );
+                        }
+                    });
+                egui::ComboBox::from_label("Gravity")
+                    .selected_text(match self.particles.gravity {
+                        GravityMode::None => "None",
+                        GravityMode::Uniform(_) => "Uniform",
+                        GravityMode::Radial { .. } => "Radial",
+                    })
+                    .show_ui(ui, |ui| {
+                        let gravity = &mut self.particles.gravity;
+                        if ui
+                            .selectable_label(*gravity == GravityMode::None, "None")
+                            .clicked()
+                        {
+                            *gravity = GravityMode::None;
+                        }
+                        if ui
+                            .selectable_label(matches!(gravity, GravityMode::Uniform(_)), "Uniform")
+                            .clicked()
+                            && !matches!(gravity, GravityMode::Uniform(_))
+                        {
+                            *gravity = GravityMode::Uniform(cgmath::vec3(0.0, -1.0, 0.0));
+                        }
+                        if ui
+                            .selectable_label(matches!(gravity, GravityMode::Radial { .. }), "Radial")
+                            .on_hover_text("Pulls every particle towards the center of the world")
+                            .clicked()
+                            && !matches!(gravity, GravityMode::Radial { .. })
+                        {
+                            *gravity = GravityMode::Radial { strength: 1.0 };
+                        }
+                    });
+                match &mut self.particles.gravity {
+                    GravityMode::None => {}
+                    GravityMode::Uniform(gravity) => {
+                        ui.horizontal(|ui| {
+                            ui.label("Gravity: ");
+                            ui.add(egui::DragValue::new(&mut gravity.x).prefix("x: ").speed(0.01));
+                            ui.add(egui::DragValue::new(&mut gravity.y).prefix("y: ").speed(0.01));
+                            ui.add(egui::DragValue::new(&mut gravity.z).prefix("z: ").speed(0.01));
+                        });
+                    }
+                    GravityMode::Radial { strength } => {
+                        ui.horizontal(|ui| {
+                            ui.label("Gravity Strength: ");
+                            ui.add(egui::DragValue::new(strength).speed(0.01));
+                        });
+                    }
+                }
+                ui.collapsing("Obstacles", |ui| {
+                    let mut removed = None;
+                    for (index, (center, radius)) in self.particles.obstacles.iter_mut().enumerate()
+                    {
+                        ui.horizontal(|ui| {
+                            ui.add(egui::DragValue::new(&mut center.x).prefix("x: ").speed(0.1));
+                            ui.add(egui::DragValue::new(&mut center.y).prefix("y: ").speed(0.1));
+                            ui.add(egui::DragValue::new(&mut center.z).prefix("z: ").speed(0.1));
+                            ui.add(
+                                egui::DragValue::new(radius)
+                                    .prefix("radius: ")
+                                    .speed(0.1)
+                                    .clamp_range(0.0..=f32::INFINITY),
+                            );
+                            if ui.button("Remove").clicked() {
+                                removed = Some(index);
+                            }
+                        });
+                    }
+                    if let Some(index) = removed {
+                        self.particles.obstacles.remove(index);
+                    }
+                    if ui.button("Add Obstacle").clicked() {
+                        self.particles
+                            .obstacles
+                            .push((cgmath::vec3(0.0, 0.0, 0.0), 5.0));
+                    }
+                });
+                ui.collapsing("Attractors", |ui| {
+                    let mut removed = None;
+                    for (index, attractor) in self.particles.attractors.iter_mut().enumerate() {
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::DragValue::new(&mut attractor.position.x)
+                                    .prefix("x: ")
+                                    .speed(0.1),
+                            );
+                            ui.add(
+                                egui::DragValue::new(&mut attractor.position.y)
+                                    .prefix("y: ")
+                                    .speed(0.1),
+                            );
+                            ui.add(
+                                egui::DragValue::new(&mut attractor.position.z)
+                                    .prefix("z: ")
+                                    .speed(0.1),
+                            );
+                            ui.add(
+                                egui::DragValue::new(&mut attractor.strength)
+                                    .prefix("strength: ")
+                                    .speed(0.1),
+                            );
+                            ui.add(
+                                egui::DragValue::new(&mut attractor.radius)
+                                    .prefix("radius: ")
+                                    .speed(0.1)
+                                    .clamp_range(0.0..=f32::INFINITY),
+                            );
+                            if ui.button("Remove").clicked() {
+                                removed = Some(index);
+                            }
+                        });
+                    }
+                    if let Some(index) = removed {
+                        self.particles.attractors.remove(index);
+                    }
+                    if ui.button("Add Attractor In Front Of Camera").clicked() {
+                        self.particles.attractors.push(Attractor {
+                            position: self.camera.position
+                                + self.camera.get_axes().forward * ATTRACTOR_PLACEMENT_DISTANCE,
+                            strength: 10.0,
+                            radius: 10.0,
+                        });
+                    }
+                });
+                ui.label("Brush (hold Shift and click or drag in the view): ");
+                ui.horizontal(|ui| {
+                    ui.label("Mode: ");
+                    ui.radio_value(&mut self.brush_mode, BrushMode::Spawn, "Spawn");
+                    ui.radio_value(&mut self.brush_mode, BrushMode::Delete, "Delete");
+                });
+                match self.brush_mode {
+                    BrushMode::Spawn => {
+                        ui.horizontal(|ui| {
+                            ui.label("Particles Per Frame: ");
+                            ui.add(egui::Slider::new(&mut self.brush_count, 1..=100));
+                        });
+                        if let Some(max_type) = self.particles.id_count.checked_sub(1) {
+                            ui.horizontal(|ui| {
+                                ui.label("Type: ");
+                                self.brush_type = self.brush_type.min(max_type);
+                                ui.add(egui::Slider::new(&mut self.brush_type, 0..=max_type));
+                            });
+                        }
+                    }
+                    BrushMode::Delete => {
+                        ui.horizontal(|ui| {
+                            ui.label("Radius: ");
+                            ui.add(
+                                egui::DragValue::new(&mut self.brush_delete_radius)
+                                    .clamp_range(0.01..=f32::INFINITY)
+                                    .speed(0.01),
+                            );
+                        });
+                    }
+                }
+                ui.horizontal_wrapped(|ui| {
+                    ui.label("Colors: ");
+                    for color in &mut self.particles.colors {
+                        let mut ui_color = [color.x, color.y, color.z];
+                        ui.color_edit_button_rgb(&mut ui_color);
+                        *color = cgmath::vec3(ui_color[0], ui_color[1], ui_color[2]);
+                    }
                 });
+                #[cfg(feature = "serde")]
+                self.preset_ui(ui);
+                #[cfg(feature = "serde")]
+                self.session_ui(ui);
+                self.particles_file_ui(ui);
+                self.screenshot_requested |= ui.button("Screenshot").clicked();
+                if ui.button("Recenter").clicked() {
+                    self.particles.recenter();
+                }
+                if ui.button("Freeze Velocities").clicked() {
+                    self.particles.zero_velocities();
+                }
+                self.replay_ui(ui);
                 self.color_window_open |= ui.button("Particle Properties").clicked();
+                self.pair_correlation_window_open |= ui.button("Pair Correlation").clicked();
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_label("Attraction Presets")
+                        .selected_text("Choose...")
+                        .show_ui(ui, |ui| {
+                            if ui.selectable_label(false, "Clusters").clicked() {
+                                self.apply_attraction_preset(presets::clusters(5));
+                            }
+                            if ui.selectable_label(false, "Chains").clicked() {
+                                self.apply_attraction_preset(presets::chains());
+                            }
+                            if ui.selectable_label(false, "Predator Prey").clicked() {
+                                self.apply_attraction_preset(presets::predator_prey());
+                            }
+                            if ui
+                                .selectable_label(false, "Interesting Random")
+                                .on_hover_text("A random matrix that is balanced to keep moving")
+                                .clicked()
+                            {
+                                self.apply_attraction_preset(presets::balanced_random(
+                                    self.particles.id_count,
+                                    thread_rng().gen(),
+                                ));
+                            }
+                        });
+                    ui.checkbox(&mut self.keep_particles_on_preset, "Keep Particles")
+                        .on_hover_text("Only change the attraction matrix and colors, particles with a type that no longer exists get a different type");
+                });
+                self.system_info_ui(ui);
                 ui.allocate_space(ui.available_size());
             });
         });
 
+        egui::Window::new("Pair Correlation")
+            .open(&mut self.pair_correlation_window_open)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Bins: ");
+                    ui.add(egui::Slider::new(&mut self.pair_correlation_bins, 1..=500));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Max Distance: ");
+                    ui.add(egui::Slider::new(
+                        &mut self.pair_correlation_max_r,
+                        0.01..=self.particles.min_world_size() * 0.5,
+                    ));
+                });
+                self.pair_correlation_max_r = self
+                    .pair_correlation_max_r
+                    .min(self.particles.min_world_size() * 0.5);
+
+                let bin_size = self.pair_correlation_max_r / self.pair_correlation_bins as f32;
+                let pair_correlation = self
+                    .particles
+                    .pair_correlation(self.pair_correlation_bins, self.pair_correlation_max_r);
+                let points: egui::plot::PlotPoints = pair_correlation
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &g)| [((i as f32 + 0.5) * bin_size) as f64, g as f64])
+                    .collect();
+                egui::plot::Plot::new("Pair Correlation Plot")
+                    .view_aspect(2.0)
+                    .include_y(0.0)
+                    .show(ui, |plot_ui| plot_ui.line(egui::plot::Line::new(points)));
+            });
+
+        // types can be added and removed in a lot of places, new types start at the normal size
+        self.type_render_scales
+            .resize(self.particles.id_count as usize, 1.0);
+
         egui::Window::new("Particle Properties")
             .open(&mut self.color_window_open)
             .resizable(false)
             .show(ctx, |ui| {
-                ui.horizontal(|ui| {
-                    let size = ui.spacing().interact_size; // stolen from the color picker code
-                    ui.allocate_exact_size(size, egui::Sense::hover());
+                // the grid grows with id_count, so it can get larger than the screen
+                egui::ScrollArea::both().show(ui, |ui| {
+                    ui.horizontal(|ui| {
+                        let size = ui.spacing().interact_size; // stolen from the color picker code
+                        ui.allocate_exact_size(size, egui::Sense::hover());
 
+                        for i in 0..self.particles.id_count {
+                            let mut ui_color = [
+                                self.particles.colors[i as usize].x,
+                                self.particles.colors[i as usize].y,
+                                self.particles.colors[i as usize].z,
+                            ];
+                            ui.color_edit_button_rgb(&mut ui_color);
+                            self.particles.colors[i as usize] =
+                                cgmath::vec3(ui_color[0], ui_color[1], ui_color[2]);
+                        }
+
+                        if ui.button("+").clicked() {
+                            let mut rng = thread_rng();
+                            self.particles
+                                .add_type(cgmath::vec3(rng.gen(), rng.gen(), rng.gen()));
+                            self.type_render_scales.push(1.0);
+                        }
+                    });
+                    let mut removed_type = None;
                     for i in 0..self.particles.id_count {
-                        let mut ui_color = [
-                            self.particles.colors[i as usize].x,
-                            self.particles.colors[i as usize].y,
-                            self.particles.colors[i as usize].z,
-                        ];
-                        ui.color_edit_button_rgb(&mut ui_color);
-                        self.particles.colors[i as usize] =
-                            cgmath::vec3(ui_color[0], ui_color[1], ui_color[2]);
-                    }
-                });
-                for i in 0..self.particles.id_count {
-                    ui.horizontal(|ui| {
-                        let mut ui_color = [
-                            self.particles.colors[i as usize].x,
-                            self.particles.colors[i as usize].y,
-                            self.particles.colors[i as usize].z,
-                        ];
-                        ui.color_edit_button_rgb(&mut ui_color);
-                        self.particles.colors[i as usize] =
-                            cgmath::vec3(ui_color[0], ui_color[1], ui_color[2]);
+                        ui.horizontal(|ui| {
+                            let mut ui_color = [
+                                self.particles.colors[i as usize].x,
+                                self.particles.colors[i as usize].y,
+                                self.particles.colors[i as usize].z,
+                            ];
+                            ui.color_edit_button_rgb(&mut ui_color);
+                            self.particles.colors[i as usize] =
+                                cgmath::vec3(ui_color[0], ui_color[1], ui_color[2]);
+
+                            for j in 0..self.particles.id_count {
+                                ui.add(
+                                    egui::DragValue::new(
+                                        &mut self.particles.attraction_matrix
+                                            [(i * self.particles.id_count + j) as usize],
+                                    )
+                                    .clamp_range(-1.0..=1.0)
+                                    .speed(0.01),
+                                );
+                            }
 
-                        for j in 0..self.particles.id_count {
                             ui.add(
-                                egui::DragValue::new(
-                                    &mut self.particles.attraction_matrix
-                                        [(i * self.particles.id_count + j) as usize],
-                                )
-                                .clamp_range(-1.0..=1.0)
-                                .speed(0.01),
+                                egui::DragValue::new(&mut self.type_render_scales[i as usize])
+                                    .prefix("scale: ")
+                                    .clamp_range(0.1..=10.0)
+                                    .speed(0.01),
+                            )
+                            .on_hover_text(
+                                "How much bigger this type is drawn with the type size mode",
                             );
-                        }
-                    });
-                }
+
+                            // there always has to be at least one type to spawn particles with
+                            if ui
+                                .add_enabled(self.particles.id_count > 1, egui::Button::new("−"))
+                                .clicked()
+                            {
+                                removed_type = Some(i);
+                            }
+                        });
+                    }
+                    if let Some(id) = removed_type {
+                        self.particles.remove_type(id);
+                        self.type_render_scales.remove(id as usize);
+                    }
+                });
             });
 
         egui::CentralPanel::default()
-            .frame(egui::Frame::none().fill(ctx.style().visuals.panel_fill))
+            .frame(egui::Frame::none().fill(self.background_color))
             .show(ctx, |ui| {
-                let (rect, _response) =
-                    ui.allocate_exact_size(ui.available_size(), egui::Sense::drag());
+                let (rect, response) =
+                    ui.allocate_exact_size(ui.available_size(), egui::Sense::click_and_drag());
+
+                // holding shift paints particles with the brush instead of turning the camera
+                let brushing = ui.input(|i| i.modifiers.shift) && self.player.is_none();
+                if brushing && (response.clicked() || response.dragged()) {
+                    if let Some(position) = response.interact_pointer_pos() {
+                        let screen_position = cgmath::vec2(
+                            (position.x - rect.min.x) / rect.width() * 2.0 - 1.0,
+                            1.0 - (position.y - rect.min.y) / rect.height() * 2.0,
+                        );
+                        let (origin, direction) = self
+                            .camera
+                            .get_screen_ray(rect.width() / rect.height(), screen_position);
+                        self.brush(origin, direction);
+                    }
+                } else if response.dragged() {
+                    let delta = response.drag_delta();
+                    self.camera.yaw += delta.x * CAMERA_MOUSE_SENSITIVITY;
+                    self.camera.pitch -= delta.y * CAMERA_MOUSE_SENSITIVITY;
+                    self.camera.pitch = self.camera.pitch.clamp(-89.9999, 89.9999);
+                }
+                if response.hovered() {
+                    let scroll = ui.input(|i| i.scroll_delta.y);
+                    match self.camera.mode {
+                        CameraMode::Fly => {
+                            self.camera.position +=
+                                self.camera.get_axes().forward * scroll * CAMERA_SCROLL_SPEED;
+                        }
+                        CameraMode::Orbit => {
+                            self.camera.orbit_radius =
+                                (self.camera.orbit_radius - scroll * CAMERA_SCROLL_SPEED).max(0.0);
+                        }
+                    }
+                }
+                self.follow_center_of_mass(ts);
+                self.camera.update_orbit();
+
+                // colors written by the shaders have to be linear when the target converts to srgb, like the clear color
+                let srgb_target = frame
+                    .wgpu_render_state()
+                    .unwrap()
+                    .target_format
+                    .describe()
+                    .srgb;
+                let shader_color = |color: egui::Color32| {
+                    let [r, g, b, _] = if srgb_target {
+                        egui::Rgba::from(color).to_array()
+                    } else {
+                        color.to_normalized_gamma_f32()
+                    };
+                    cgmath::vec3(r, g, b)
+                };
 
                 let mut camera_uniform =
                     UniformBuffer::new([0; <GpuCamera as ShaderSize>::SHADER_SIZE.get() as _]);
                 camera_uniform
                     .write(&{
-                        let axes = self.camera.get_axes();
                         GpuCamera {
-                            view_matrix: cgmath::Matrix4::look_to_rh(
-                                cgmath::point3(
-                                    self.camera.position.x,
-                                    self.camera.position.y,
-                                    self.camera.position.z,
-                                ),
-                                axes.forward,
-                                axes.up,
-                            ),
-                            projection_matrix: cgmath::perspective(
-                                cgmath::Rad::from(cgmath::Deg(90.0)),
-                                rect.width() / rect.height(),
-                                0.001,
-                                1000.0,
-                            ),
+                            view_matrix: self.camera.get_view_matrix(),
+                            projection_matrix: if self.reverse_z {
+                                self.camera
+                                    .get_reverse_z_projection_matrix(rect.width() / rect.height())
+                            } else {
+                                self.camera
+                                    .get_projection_matrix(rect.width() / rect.height())
+                            },
+                            particle_render_radius: self.particle_render_radius,
+                            color_mode: match self.color_mode {
+                                ColorMode::ByType => 0,
+                                ColorMode::BySpeed => 1,
+                            },
+                            max_color_speed: self.max_color_speed,
+                            selected_particle: self
+                                .selected_particle
+                                .map_or(0, |index| index as u32 + 1),
+                            fog_color: shader_color(if self.fog_matches_background {
+                                self.background_color
+                            } else {
+                                self.fog_color
+                            }),
+                            fog_density: self.fog_density,
+                            border_color: shader_color(self.border_color),
+                            size_mode: match self.size_mode {
+                                SizeMode::Uniform => 0,
+                                SizeMode::ByType => 1,
+                                SizeMode::BySpeed => 2,
+                            },
+                            arrow_scale: self.arrow_scale,
+                            slice_axis: if self.slice {
+                                self.slice_axis as u32 + 1
+                            } else {
+                                0
+                            },
+                            slice_center: self.slice_center,
+                            slice_thickness: self.slice_thickness,
                         }
                     })
                     .unwrap();
                 let camera = camera_uniform.into_inner();
 
+                let (world_size, shown_particles) = match &self.player {
+                    Some(player) => (
+                        player.world_size(self.playback_frame),
+                        player.frame(self.playback_frame),
+                    ),
+                    None => (
+                        self.particles.world_size,
+                        &self.particles.current_particles[..],
+                    ),
+                };
                 let mut particles_storage = StorageBuffer::new(vec![]);
                 particles_storage
                     .write(&GpuParticles {
-                        world_size: self.particles.world_size,
+                        world_size,
                         length: ArrayLength,
-                        particles: &self.particles.current_particles,
+                        particles: shown_particles,
                     })
                     .unwrap();
                 let particles = particles_storage.into_inner();
@@ -402,13 +2351,168 @@ impl eframe::App for App {
                 colors_storage
                     .write(&GpuColors {
                         length: ArrayLength,
-                        particles: &self.particles.colors,
+                        particles: &self
+                            .particles
+                            .colors
+                            .iter()
+                            .zip(&self.type_render_scales)
+                            .map(|(&color, &render_scale)| GpuTypeStyle {
+                                color,
+                                render_scale,
+                            })
+                            .collect::<Vec<_>>(),
                     })
                     .unwrap();
                 let colors = colors_storage.into_inner();
 
-                let sphere_count = self.particles.current_particles.len();
+                let sphere_count = shown_particles.len();
+
+                let trail_positions: Vec<_> =
+                    self.trail_history.iter().flatten().copied().collect();
+                let mut trails_storage = StorageBuffer::new(vec![]);
+                trails_storage
+                    .write(&GpuTrails {
+                        frame_count: self.trail_history.len() as _,
+                        opacity: self.trail_opacity,
+                        length: ArrayLength,
+                        positions: &trail_positions,
+                    })
+                    .unwrap();
+                let trails = trails_storage.into_inner();
+
+                let obstacle_spheres: Vec<_> = self
+                    .particles
+                    .obstacles
+                    .iter()
+                    .map(|&(center, radius)| center.extend(radius))
+                    .collect();
+                let mut obstacles_storage = StorageBuffer::new(vec![]);
+                obstacles_storage
+                    .write(&GpuObstacles {
+                        length: ArrayLength,
+                        obstacles: &obstacle_spheres,
+                    })
+                    .unwrap();
+                let obstacles = obstacles_storage.into_inner();
+                let obstacle_count = obstacle_spheres.len();
+
+                // the cells are from the live simulation, so they dont line up with a recording
+                let occupied_cells: Vec<_> = if self.show_cells && self.player.is_none() {
+                    self.particles
+                        .occupied_cells()
+                        .into_iter()
+                        .map(|(min, max)| GpuCell { min, max })
+                        .collect()
+                } else {
+                    vec![]
+                };
+                let mut cells_storage = StorageBuffer::new(vec![]);
+                cells_storage
+                    .write(&GpuCells {
+                        length: ArrayLength,
+                        cells: &occupied_cells,
+                    })
+                    .unwrap();
+                let cells = cells_storage.into_inner();
+                let cell_count = occupied_cells.len();
+                // the trails are from the live simulation, so they dont line up with a recording
+                let trail_segment_count = if self.player.is_some() {
+                    0
+                } else {
+                    self.trail_history.len().saturating_sub(1) * sphere_count
+                };
+                let arrow_count = if self.show_arrows { sphere_count } else { 0 };
+
+                let pixels_per_point = ctx.pixels_per_point();
+                let width = ((rect.width() * pixels_per_point) as u32).max(1);
+                let height = ((rect.height() * pixels_per_point) as u32).max(1);
+                let pick_position = (response.clicked() && !brushing)
+                    .then(|| response.interact_pointer_pos())
+                    .flatten()
+                    .map(|position| (position - rect.min) * pixels_per_point);
+
+                let screenshot_requested = std::mem::take(&mut self.screenshot_requested);
+                if screenshot_requested || pick_position.is_some() {
+                    let render_state = frame.wgpu_render_state().unwrap();
+                    let mut egui_renderer = render_state.renderer.write();
+                    let renderer: &mut Renderer =
+                        egui_renderer.paint_callback_resources.get_mut().unwrap();
+                    renderer.set_sample_count(&render_state.device, self.sample_count);
+                    renderer.set_reverse_z(&render_state.device, self.reverse_z);
+                    renderer.set_glow(self.glow);
+                    let encoder = render_state.device.create_command_encoder(
+                        &wgpu::CommandEncoderDescriptor {
+                            label: Some("Offscreen Prepare Command Encoder"),
+                        },
+                    );
+                    renderer.prepare(
+                        &camera,
+                        &particles,
+                        &colors,
+                        &trails,
+                        &obstacles,
+                        &cells,
+                        &render_state.device,
+                        &render_state.queue,
+                        &encoder,
+                    );
+
+                    if let Some(position) = pick_position {
+                        self.selected_particle = renderer
+                            .pick(
+                                sphere_count as _,
+                                width,
+                                height,
+                                (position.x as u32).min(width - 1),
+                                (position.y as u32).min(height - 1),
+                                &render_state.device,
+                                &render_state.queue,
+                            )
+                            .map(|index| index as _);
+                    }
+                }
+
+                if screenshot_requested {
+                    let render_state = frame.wgpu_render_state().unwrap();
+                    let egui_renderer = render_state.renderer.read();
+                    let renderer: &Renderer = egui_renderer.paint_callback_resources.get().unwrap();
+                    let screenshot = renderer.screenshot(
+                        sphere_count as _,
+                        trail_segment_count as _,
+                        arrow_count as _,
+                        obstacle_count as _,
+                        cell_count as _,
+                        self.show_border,
+                        width,
+                        height,
+                        self.background_color,
+                        &render_state.device,
+                        &render_state.queue,
+                    );
+                    match screenshot {
+                        Some(screenshot) => {
+                            let timestamp = std::time::SystemTime::now()
+                                .duration_since(std::time::UNIX_EPOCH)
+                                .unwrap()
+                                .as_millis();
+                            let path = format!("screenshot-{timestamp}.png");
+                            match screenshot.save(&path) {
+                                Ok(()) => println!("Saved screenshot to {path}"),
+                                Err(error) => eprintln!("Failed to save {path}: {error}"),
+                            }
+                        }
+                        None => eprintln!(
+                            "Screenshots are not supported with the {:?} render target format",
+                            renderer.target_format
+                        ),
+                    }
+                }
 
+                let sample_count = self.sample_count;
+                let reverse_z = self.reverse_z;
+                let glow = self.glow;
+                let show_border = self.show_border;
+                let clear_color = self.background_color;
                 ui.painter().add(egui::PaintCallback {
                     rect,
                     callback: std::sync::Arc::new(
@@ -416,12 +2520,31 @@ impl eframe::App for App {
                             .prepare(move |device, queue, encoder, paint_callback_resources| {
                                 let renderer: &mut Renderer =
                                     paint_callback_resources.get_mut().unwrap();
-                                renderer
-                                    .prepare(&camera, &particles, &colors, device, queue, encoder)
+                                renderer.set_sample_count(device, sample_count);
+                                renderer.set_reverse_z(device, reverse_z);
+                                renderer.set_glow(glow);
+                                let command_buffers = renderer.prepare(
+                                    &camera, &particles, &colors, &trails, &obstacles, &cells,
+                                    device, queue, encoder,
+                                );
+                                renderer.render(
+                                    sphere_count as _,
+                                    trail_segment_count as _,
+                                    arrow_count as _,
+                                    obstacle_count as _,
+                                    cell_count as _,
+                                    show_border,
+                                    width,
+                                    height,
+                                    clear_color,
+                                    device,
+                                    encoder,
+                                );
+                                command_buffers
                             })
                             .paint(move |_info, render_pass, paint_callback_resources| {
                                 let renderer: &Renderer = paint_callback_resources.get().unwrap();
-                                renderer.paint(sphere_count as _, render_pass);
+                                renderer.blit(render_pass);
                             }),
                     ),
                 });
@@ -431,6 +2554,350 @@ impl eframe::App for App {
     }
 }
 
+/// The shaders used by `ScenePipelines`, kept so the pipelines can be recreated
+struct SceneShaders {
+    particles: wgpu::ShaderModule,
+    border: wgpu::ShaderModule,
+    trails: wgpu::ShaderModule,
+    arrows: wgpu::ShaderModule,
+    obstacles: wgpu::ShaderModule,
+    cells: wgpu::ShaderModule,
+}
+
+/// The pipelines that draw the scene, these depend on the sample count and depth compare so they are recreated when they change
+struct ScenePipelines {
+    particles: wgpu::RenderPipeline,
+    /// Draws the particles with additive blending instead of `particles`
+    glow_particles: wgpu::RenderPipeline,
+    border: wgpu::RenderPipeline,
+    trails: wgpu::RenderPipeline,
+    arrows: wgpu::RenderPipeline,
+    obstacles: wgpu::RenderPipeline,
+    cells: wgpu::RenderPipeline,
+}
+
+impl ScenePipelines {
+    fn new(
+        device: &wgpu::Device,
+        target_format: wgpu::TextureFormat,
+        layout: &wgpu::PipelineLayout,
+        shaders: &SceneShaders,
+        sample_count: u32,
+        depth_compare: wgpu::CompareFunction,
+    ) -> Self {
+        let particles = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Particles Render Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shaders.particles,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shaders.particles,
+                entry_point: "fs_main",
+                targets: &[Some(target_format.into())],
+            }),
+            primitive: wgpu::PrimitiveState {
+                polygon_mode: wgpu::PolygonMode::Fill,
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        });
+
+        let glow_particles = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Glow Particles Render Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shaders.particles,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shaders.particles,
+                entry_point: "fs_glow",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_format,
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent::OVER,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                polygon_mode: wgpu::PolygonMode::Fill,
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            // the particles are still hidden behind everything else, but they dont hide each other,
+            // additive blending gives the same result in any order so they dont have to be sorted
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        });
+
+        let border = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Border Render Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shaders.border,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shaders.border,
+                entry_point: "fs_main",
+                targets: &[Some(target_format.into())],
+            }),
+            primitive: wgpu::PrimitiveState {
+                polygon_mode: wgpu::PolygonMode::Line,
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        });
+
+        let obstacles = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Obstacles Render Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shaders.obstacles,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shaders.obstacles,
+                entry_point: "fs_main",
+                targets: &[Some(target_format.into())],
+            }),
+            primitive: wgpu::PrimitiveState {
+                polygon_mode: wgpu::PolygonMode::Fill,
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        });
+
+        let trails = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Trails Render Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shaders.trails,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shaders.trails,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                polygon_mode: wgpu::PolygonMode::Fill,
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            // the trails are transparent so they shouldnt hide anything behind them
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        });
+
+        let arrows = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Arrows Render Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shaders.arrows,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shaders.arrows,
+                entry_point: "fs_main",
+                targets: &[Some(target_format.into())],
+            }),
+            primitive: wgpu::PrimitiveState {
+                polygon_mode: wgpu::PolygonMode::Fill,
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        });
+
+        let cells = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Cells Render Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shaders.cells,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shaders.cells,
+                entry_point: "fs_main",
+                targets: &[Some(target_format.into())],
+            }),
+            primitive: wgpu::PrimitiveState {
+                polygon_mode: wgpu::PolygonMode::Line,
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+        });
+
+        Self {
+            particles,
+            glow_particles,
+            border,
+            trails,
+            arrows,
+            obstacles,
+            cells,
+        }
+    }
+}
+
+/// The gpu that the app is rendering with, shown in the ui so it can be included in bug reports
+#[derive(Clone)]
+struct SystemInfo {
+    adapter_name: String,
+    backend: String,
+    device_type: String,
+    driver: String,
+    /// The device limits that affect how many particles can be simulated and drawn, as `(name, value)`
+    limits: Vec<(&'static str, String)>,
+}
+
+impl SystemInfo {
+    fn new(render_state: &eframe::egui_wgpu::RenderState) -> Self {
+        let info = render_state.adapter.get_info();
+        let limits = render_state.device.limits();
+        Self {
+            adapter_name: info.name,
+            backend: format!("{:?}", info.backend),
+            device_type: format!("{:?}", info.device_type),
+            driver: format!("{} {}", info.driver, info.driver_info),
+            limits: vec![
+                (
+                    "Max Texture Size",
+                    limits.max_texture_dimension_2d.to_string(),
+                ),
+                (
+                    "Max Storage Buffer Size",
+                    limits.max_storage_buffer_binding_size.to_string(),
+                ),
+                (
+                    "Max Uniform Buffer Size",
+                    limits.max_uniform_buffer_binding_size.to_string(),
+                ),
+                (
+                    "Max Workgroup Invocations",
+                    limits.max_compute_invocations_per_workgroup.to_string(),
+                ),
+                (
+                    "Max Workgroups Per Dimension",
+                    limits.max_compute_workgroups_per_dimension.to_string(),
+                ),
+            ],
+        }
+    }
+}
+
+/// The textures that the scene is drawn into before it is copied onto egui's render target
+struct SceneTarget {
+    width: u32,
+    height: u32,
+    sample_count: u32,
+    /// The multisampled color texture, without multisampling the scene is drawn straight into `resolve_texture`
+    color_view: Option<wgpu::TextureView>,
+    depth_view: wgpu::TextureView,
+    resolve_texture: wgpu::Texture,
+    resolve_view: wgpu::TextureView,
+    blit_bind_group: wgpu::BindGroup,
+}
+
 struct Renderer {
     camera_uniform_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
@@ -438,14 +2905,94 @@ struct Renderer {
     particles_storage_buffer_size: usize,
     colors_storage_buffer: wgpu::Buffer,
     colors_storage_buffer_size: usize,
+    trails_storage_buffer: wgpu::Buffer,
+    trails_storage_buffer_size: usize,
+    obstacles_storage_buffer: wgpu::Buffer,
+    obstacles_storage_buffer_size: usize,
+    cells_storage_buffer: wgpu::Buffer,
+    cells_storage_buffer_size: usize,
     particles_bind_group_layout: wgpu::BindGroupLayout,
     particles_bind_group: wgpu::BindGroup,
-    particles_render_pipeline: wgpu::RenderPipeline,
-    border_render_pipeline: wgpu::RenderPipeline,
+    scene_shaders: SceneShaders,
+    scene_pipeline_layout: wgpu::PipelineLayout,
+    scene_pipelines: ScenePipelines,
+    /// The number of samples per pixel the scene is drawn with, 1 turns multisampling off
+    sample_count: u32,
+    /// Whether the depth buffer is reversed to match `Camera::get_reverse_z_projection_matrix`
+    reverse_z: bool,
+    /// Whether the particles are drawn with `ScenePipelines::glow_particles`
+    glow: bool,
+    /// Created by `render` when it is first called, and recreated when the viewport size or sample count changes
+    scene_target: Option<SceneTarget>,
+    /// Renders the index of each particle instead of its color, used by `pick`
+    pick_render_pipeline: wgpu::RenderPipeline,
+    blit_bind_group_layout: wgpu::BindGroupLayout,
+    /// Copies the scene from `scene_target` onto egui's render target
+    blit_render_pipeline: wgpu::RenderPipeline,
+    target_format: wgpu::TextureFormat,
+    system_info: SystemInfo,
 }
 
 impl Renderer {
-    fn new(render_state: &eframe::egui_wgpu::RenderState) -> Self {
+    const PICK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+    fn create_pick_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        particles_shader: &wgpu::ShaderModule,
+        depth_compare: wgpu::CompareFunction,
+    ) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Pick Render Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: particles_shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: particles_shader,
+                entry_point: "fs_pick",
+                targets: &[Some(Renderer::PICK_FORMAT.into())],
+            }),
+            primitive: wgpu::PrimitiveState {
+                polygon_mode: wgpu::PolygonMode::Fill,
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                ..Default::default()
+            },
+            multiview: None,
+        })
+    }
+
+    /// The depth compare function for the pipelines, nearer surfaces have a larger depth with reverse z
+    fn depth_compare(reverse_z: bool) -> wgpu::CompareFunction {
+        if reverse_z {
+            wgpu::CompareFunction::Greater
+        } else {
+            wgpu::CompareFunction::Less
+        }
+    }
+
+    /// The depth that is further away than everything else
+    fn depth_clear_value(&self) -> f32 {
+        if self.reverse_z {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    fn new(render_state: &eframe::egui_wgpu::RenderState, reverse_z: bool) -> Self {
         let particles_shader = render_state
             .device
             .create_shader_module(include_wgsl!("./particles.wgsl"));
@@ -453,6 +3000,18 @@ impl Renderer {
         let border_shader = render_state
             .device
             .create_shader_module(include_wgsl!("./border.wgsl"));
+        let trails_shader = render_state
+            .device
+            .create_shader_module(include_wgsl!("./trails.wgsl"));
+        let arrows_shader = render_state
+            .device
+            .create_shader_module(include_wgsl!("./arrows.wgsl"));
+        let cells_shader = render_state
+            .device
+            .create_shader_module(include_wgsl!("./cells.wgsl"));
+        let obstacles_shader = render_state
+            .device
+            .create_shader_module(include_wgsl!("./obstacles.wgsl"));
 
         let camera_bind_group_layout =
             render_state
@@ -461,7 +3020,7 @@ impl Renderer {
                     label: Some("Camera Bind Group Layout"),
                     entries: &[wgpu::BindGroupLayoutEntry {
                         binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                         ty: wgpu::BindingType::Buffer {
                             ty: wgpu::BufferBindingType::Uniform,
                             has_dynamic_offset: false,
@@ -517,6 +3076,36 @@ impl Renderer {
                             },
                             count: None,
                         },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: wgpu::ShaderStages::VERTEX,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: Some(<GpuTrails as ShaderType>::min_size()),
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 3,
+                            visibility: wgpu::ShaderStages::VERTEX,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: Some(<GpuObstacles as ShaderType>::min_size()),
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 4,
+                            visibility: wgpu::ShaderStages::VERTEX,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                                has_dynamic_offset: false,
+                                min_binding_size: Some(<GpuCells as ShaderType>::min_size()),
+                            },
+                            count: None,
+                        },
                     ],
                 });
 
@@ -542,6 +3131,39 @@ impl Renderer {
                     usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
                 });
 
+        const TRAILS_STORAGE_BUFFER_SIZE: usize =
+            <GpuTrails as ShaderType>::METADATA.min_size().get() as _;
+        let trails_storage_buffer =
+            render_state
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Trails Storage Buffer"),
+                    contents: &[0; TRAILS_STORAGE_BUFFER_SIZE],
+                    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+                });
+
+        const OBSTACLES_STORAGE_BUFFER_SIZE: usize =
+            <GpuObstacles as ShaderType>::METADATA.min_size().get() as _;
+        let obstacles_storage_buffer =
+            render_state
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Obstacles Storage Buffer"),
+                    contents: &[0; OBSTACLES_STORAGE_BUFFER_SIZE],
+                    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+                });
+
+        const CELLS_STORAGE_BUFFER_SIZE: usize =
+            <GpuCells as ShaderType>::METADATA.min_size().get() as _;
+        let cells_storage_buffer =
+            render_state
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Cells Storage Buffer"),
+                    contents: &[0; CELLS_STORAGE_BUFFER_SIZE],
+                    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+                });
+
         let particles_bind_group =
             render_state
                 .device
@@ -557,86 +3179,93 @@ impl Renderer {
                             binding: 1,
                             resource: colors_storage_buffer.as_entire_binding(),
                         },
-                    ],
-                });
-
-        let particles_pipeline_layout =
-            render_state
-                .device
-                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("Particles Pipeline Layout"),
-                    bind_group_layouts: &[&camera_bind_group_layout, &particles_bind_group_layout],
-                    push_constant_ranges: &[],
-                });
-
-        let particles_render_pipeline =
-            render_state
-                .device
-                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                    label: Some("Particles Render Pipeline"),
-                    layout: Some(&particles_pipeline_layout),
-                    vertex: wgpu::VertexState {
-                        module: &particles_shader,
-                        entry_point: "vs_main",
-                        buffers: &[],
-                    },
-                    fragment: Some(wgpu::FragmentState {
-                        module: &particles_shader,
-                        entry_point: "fs_main",
-                        targets: &[Some(render_state.target_format.into())],
-                    }),
-                    primitive: wgpu::PrimitiveState {
-                        polygon_mode: wgpu::PolygonMode::Fill,
-                        topology: wgpu::PrimitiveTopology::TriangleStrip,
-                        ..Default::default()
-                    },
-                    depth_stencil: Some(wgpu::DepthStencilState {
-                        format: wgpu::TextureFormat::Depth32Float,
-                        depth_write_enabled: true,
-                        depth_compare: wgpu::CompareFunction::Less,
-                        stencil: wgpu::StencilState::default(),
-                        bias: wgpu::DepthBiasState::default(),
-                    }),
-                    multisample: wgpu::MultisampleState {
-                        ..Default::default()
-                    },
-                    multiview: None,
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: trails_storage_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: obstacles_storage_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 4,
+                            resource: cells_storage_buffer.as_entire_binding(),
+                        },
+                    ],
                 });
 
-        let border_pipeline_layout =
+        let scene_pipeline_layout =
             render_state
                 .device
                 .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("Border Pipeline Layout"),
+                    label: Some("Scene Pipeline Layout"),
                     bind_group_layouts: &[&camera_bind_group_layout, &particles_bind_group_layout],
                     push_constant_ranges: &[],
                 });
 
-        let border_render_pipeline =
+        let pick_render_pipeline = Renderer::create_pick_pipeline(
+            &render_state.device,
+            &scene_pipeline_layout,
+            &particles_shader,
+            Renderer::depth_compare(reverse_z),
+        );
+
+        let blit_shader = render_state
+            .device
+            .create_shader_module(include_wgsl!("./blit.wgsl"));
+
+        let blit_bind_group_layout =
+            render_state
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("Blit Bind Group Layout"),
+                    entries: &[wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    }],
+                });
+
+        let blit_pipeline_layout =
+            render_state
+                .device
+                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: Some("Blit Pipeline Layout"),
+                    bind_group_layouts: &[&blit_bind_group_layout],
+                    push_constant_ranges: &[],
+                });
+
+        let blit_render_pipeline =
             render_state
                 .device
                 .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                    label: Some("Border Render Pipeline"),
-                    layout: Some(&border_pipeline_layout),
+                    label: Some("Blit Render Pipeline"),
+                    layout: Some(&blit_pipeline_layout),
                     vertex: wgpu::VertexState {
-                        module: &border_shader,
+                        module: &blit_shader,
                         entry_point: "vs_main",
                         buffers: &[],
                     },
                     fragment: Some(wgpu::FragmentState {
-                        module: &border_shader,
+                        module: &blit_shader,
                         entry_point: "fs_main",
                         targets: &[Some(render_state.target_format.into())],
                     }),
                     primitive: wgpu::PrimitiveState {
-                        polygon_mode: wgpu::PolygonMode::Line,
-                        topology: wgpu::PrimitiveTopology::LineList,
+                        polygon_mode: wgpu::PolygonMode::Fill,
+                        topology: wgpu::PrimitiveTopology::TriangleList,
                         ..Default::default()
                     },
+                    // egui's render pass has a depth buffer, but the scene has already been depth tested
                     depth_stencil: Some(wgpu::DepthStencilState {
                         format: wgpu::TextureFormat::Depth32Float,
-                        depth_write_enabled: true,
-                        depth_compare: wgpu::CompareFunction::Less,
+                        depth_write_enabled: false,
+                        depth_compare: wgpu::CompareFunction::Always,
                         stencil: wgpu::StencilState::default(),
                         bias: wgpu::DepthBiasState::default(),
                     }),
@@ -646,6 +3275,23 @@ impl Renderer {
                     multiview: None,
                 });
 
+        let scene_shaders = SceneShaders {
+            particles: particles_shader,
+            border: border_shader,
+            trails: trails_shader,
+            arrows: arrows_shader,
+            obstacles: obstacles_shader,
+            cells: cells_shader,
+        };
+        let sample_count = 1;
+        let scene_pipelines = ScenePipelines::new(
+            &render_state.device,
+            render_state.target_format,
+            &scene_pipeline_layout,
+            &scene_shaders,
+            sample_count,
+            Renderer::depth_compare(reverse_z),
+        );
         Self {
             camera_uniform_buffer,
             camera_bind_group,
@@ -653,18 +3299,38 @@ impl Renderer {
             particles_storage_buffer_size: PARTICLES_STORAGE_BUFFER_SIZE,
             colors_storage_buffer,
             colors_storage_buffer_size: COLORS_STORAGE_BUFFER_SIZE,
+            trails_storage_buffer,
+            trails_storage_buffer_size: TRAILS_STORAGE_BUFFER_SIZE,
+            obstacles_storage_buffer,
+            obstacles_storage_buffer_size: OBSTACLES_STORAGE_BUFFER_SIZE,
+            cells_storage_buffer,
+            cells_storage_buffer_size: CELLS_STORAGE_BUFFER_SIZE,
             particles_bind_group_layout,
             particles_bind_group,
-            particles_render_pipeline,
-            border_render_pipeline,
+            scene_shaders,
+            scene_pipeline_layout,
+            scene_pipelines,
+            sample_count,
+            reverse_z,
+            glow: false,
+            scene_target: None,
+            pick_render_pipeline,
+            blit_bind_group_layout,
+            blit_render_pipeline,
+            target_format: render_state.target_format,
+            system_info: SystemInfo::new(render_state),
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn prepare(
         &mut self,
         camera: &[u8],
         particles: &[u8],
         colors: &[u8],
+        trails: &[u8],
+        obstacles: &[u8],
+        cells: &[u8],
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         _encoder: &wgpu::CommandEncoder,
@@ -687,7 +3353,7 @@ impl Renderer {
                     });
                 self.particles_storage_buffer_size = particles.len();
             }
-            if self.colors_storage_buffer_size >= particles.len() {
+            if self.colors_storage_buffer_size >= colors.len() {
                 queue.write_buffer(&self.colors_storage_buffer, 0, colors);
             } else {
                 particles_bind_group_invalidated = true;
@@ -699,6 +3365,42 @@ impl Renderer {
                     });
                 self.colors_storage_buffer_size = colors.len();
             }
+            if self.trails_storage_buffer_size >= trails.len() {
+                queue.write_buffer(&self.trails_storage_buffer, 0, trails);
+            } else {
+                particles_bind_group_invalidated = true;
+                self.trails_storage_buffer =
+                    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some("Trails Storage Buffer"),
+                        contents: trails,
+                        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+                    });
+                self.trails_storage_buffer_size = trails.len();
+            }
+            if self.obstacles_storage_buffer_size >= obstacles.len() {
+                queue.write_buffer(&self.obstacles_storage_buffer, 0, obstacles);
+            } else {
+                particles_bind_group_invalidated = true;
+                self.obstacles_storage_buffer =
+                    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some("Obstacles Storage Buffer"),
+                        contents: obstacles,
+                        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+                    });
+                self.obstacles_storage_buffer_size = obstacles.len();
+            }
+            if self.cells_storage_buffer_size >= cells.len() {
+                queue.write_buffer(&self.cells_storage_buffer, 0, cells);
+            } else {
+                particles_bind_group_invalidated = true;
+                self.cells_storage_buffer =
+                    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some("Cells Storage Buffer"),
+                        contents: cells,
+                        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+                    });
+                self.cells_storage_buffer_size = cells.len();
+            }
             if particles_bind_group_invalidated {
                 self.particles_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
                     label: Some("Particles Bind Group"),
@@ -712,6 +3414,18 @@ impl Renderer {
                             binding: 1,
                             resource: self.colors_storage_buffer.as_entire_binding(),
                         },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: self.trails_storage_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: self.obstacles_storage_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 4,
+                            resource: self.cells_storage_buffer.as_entire_binding(),
+                        },
                     ],
                 });
             }
@@ -720,17 +3434,1098 @@ impl Renderer {
         vec![]
     }
 
-    fn paint<'a>(&'a self, sphere_count: u32, render_pass: &mut wgpu::RenderPass<'a>) {
-        render_pass.set_pipeline(&self.particles_render_pipeline);
-        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-        render_pass.set_bind_group(1, &self.particles_bind_group, &[]);
-        render_pass.draw(0..4, 0..sphere_count);
+    /// Recreates the scene pipelines when `sample_count` is different to the current sample count
+    fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        if sample_count != self.sample_count {
+            self.scene_pipelines = ScenePipelines::new(
+                device,
+                self.target_format,
+                &self.scene_pipeline_layout,
+                &self.scene_shaders,
+                sample_count,
+                Renderer::depth_compare(self.reverse_z),
+            );
+            self.sample_count = sample_count;
+        }
+    }
+
+    fn set_glow(&mut self, glow: bool) {
+        self.glow = glow;
+    }
+
+    fn set_reverse_z(&mut self, device: &wgpu::Device, reverse_z: bool) {
+        if reverse_z != self.reverse_z {
+            self.scene_pipelines = ScenePipelines::new(
+                device,
+                self.target_format,
+                &self.scene_pipeline_layout,
+                &self.scene_shaders,
+                self.sample_count,
+                Renderer::depth_compare(reverse_z),
+            );
+            self.pick_render_pipeline = Renderer::create_pick_pipeline(
+                device,
+                &self.scene_pipeline_layout,
+                &self.scene_shaders.particles,
+                Renderer::depth_compare(reverse_z),
+            );
+            self.reverse_z = reverse_z;
+        }
+    }
+
+    fn create_scene_target(&self, width: u32, height: u32, device: &wgpu::Device) -> SceneTarget {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let color_view = (self.sample_count > 1).then(|| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some("Scene Multisampled Color Texture"),
+                    size,
+                    mip_level_count: 1,
+                    sample_count: self.sample_count,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: self.target_format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        });
+        let depth_view = device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("Scene Depth Texture"),
+                size,
+                mip_level_count: 1,
+                sample_count: self.sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Depth32Float,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let resolve_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Scene Color Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.target_format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let resolve_view = resolve_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let blit_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Blit Bind Group"),
+            layout: &self.blit_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&resolve_view),
+            }],
+        });
+
+        SceneTarget {
+            width,
+            height,
+            sample_count: self.sample_count,
+            color_view,
+            depth_view,
+            resolve_texture,
+            resolve_view,
+            blit_bind_group,
+        }
+    }
+
+    /// Draws the scene into `target`, resolving it into `target.resolve_texture` when multisampling
+    #[allow(clippy::too_many_arguments)]
+    fn draw_scene(
+        &self,
+        target: &SceneTarget,
+        sphere_count: u32,
+        trail_segment_count: u32,
+        arrow_count: u32,
+        obstacle_count: u32,
+        cell_count: u32,
+        show_border: bool,
+        clear_color: egui::Color32,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        // the clear color has to be linear when the target converts to srgb on write
+        let clear_color = if self.target_format.describe().srgb {
+            egui::Rgba::from(clear_color).to_array()
+        } else {
+            clear_color.to_normalized_gamma_f32()
+        };
+
+        let (view, resolve_target) = match &target.color_view {
+            Some(color_view) => (color_view, Some(&target.resolve_view)),
+            None => (&target.resolve_view, None),
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Scene Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: clear_color[0] as _,
+                        g: clear_color[1] as _,
+                        b: clear_color[2] as _,
+                        a: 1.0,
+                    }),
+                    // only the resolved samples are needed after the pass
+                    store: resolve_target.is_none(),
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &target.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.depth_clear_value()),
+                    store: false,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        self.paint(
+            sphere_count,
+            trail_segment_count,
+            arrow_count,
+            obstacle_count,
+            cell_count,
+            show_border,
+            &mut render_pass,
+        );
+    }
+
+    /// Draws the scene into `scene_target` so that `blit` can copy it onto the screen,
+    /// `prepare` has to be called first so that the buffers are up to date
+    #[allow(clippy::too_many_arguments)]
+    fn render(
+        &mut self,
+        sphere_count: u32,
+        trail_segment_count: u32,
+        arrow_count: u32,
+        obstacle_count: u32,
+        cell_count: u32,
+        show_border: bool,
+        width: u32,
+        height: u32,
+        clear_color: egui::Color32,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        let target_outdated = self.scene_target.as_ref().map_or(true, |target| {
+            target.width != width
+                || target.height != height
+                || target.sample_count != self.sample_count
+        });
+        if target_outdated {
+            self.scene_target = Some(self.create_scene_target(width, height, device));
+        }
+        let target = self.scene_target.as_ref().unwrap();
+        self.draw_scene(
+            target,
+            sphere_count,
+            trail_segment_count,
+            arrow_count,
+            obstacle_count,
+            cell_count,
+            show_border,
+            clear_color,
+            encoder,
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn paint<'a>(
+        &'a self,
+        sphere_count: u32,
+        trail_segment_count: u32,
+        arrow_count: u32,
+        obstacle_count: u32,
+        cell_count: u32,
+        show_border: bool,
+        render_pass: &mut wgpu::RenderPass<'a>,
+    ) {
+        if !self.glow {
+            render_pass.set_pipeline(&self.scene_pipelines.particles);
+            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            render_pass.set_bind_group(1, &self.particles_bind_group, &[]);
+            render_pass.draw(0..4, 0..sphere_count);
+        }
+
+        if show_border {
+            render_pass.set_pipeline(&self.scene_pipelines.border);
+            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            render_pass.set_bind_group(1, &self.particles_bind_group, &[]);
+            render_pass.draw(0..24, 0..1);
+        }
+
+        if obstacle_count > 0 {
+            render_pass.set_pipeline(&self.scene_pipelines.obstacles);
+            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            render_pass.set_bind_group(1, &self.particles_bind_group, &[]);
+            // 3 circles of 32 lines each
+            render_pass.draw(0..3 * 32 * 2, 0..obstacle_count);
+        }
+
+        if cell_count > 0 {
+            render_pass.set_pipeline(&self.scene_pipelines.cells);
+            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            render_pass.set_bind_group(1, &self.particles_bind_group, &[]);
+            // the 12 edges of each cell
+            render_pass.draw(0..24, 0..cell_count);
+        }
+
+        // drawn before the trails so the transparent trails blend over them
+        if arrow_count > 0 {
+            render_pass.set_pipeline(&self.scene_pipelines.arrows);
+            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            render_pass.set_bind_group(1, &self.particles_bind_group, &[]);
+            // the shaft and the 2 sides of the head
+            render_pass.draw(0..3 * 2, 0..arrow_count);
+        }
+
+        // glowing particles dont write depth, so everything solid has to be drawn first to hide the particles behind it
+        if self.glow {
+            render_pass.set_pipeline(&self.scene_pipelines.glow_particles);
+            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            render_pass.set_bind_group(1, &self.particles_bind_group, &[]);
+            render_pass.draw(0..4, 0..sphere_count);
+        }
+
+        if trail_segment_count > 0 {
+            render_pass.set_pipeline(&self.scene_pipelines.trails);
+            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            render_pass.set_bind_group(1, &self.particles_bind_group, &[]);
+            render_pass.draw(0..2, 0..trail_segment_count);
+        }
+    }
+
+    /// Copies the scene drawn by `render` onto egui's render target
+    fn blit<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if let Some(target) = &self.scene_target {
+            render_pass.set_pipeline(&self.blit_render_pipeline);
+            render_pass.set_bind_group(0, &target.blit_bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+
+    /// Renders the particles into a `width` by `height` texture and reads it back,
+    /// `prepare` has to be called first so that the buffers are up to date.
+    /// Returns `None` if the render target format cant be converted to an rgba image
+    #[allow(clippy::too_many_arguments)]
+    fn screenshot(
+        &self,
+        sphere_count: u32,
+        trail_segment_count: u32,
+        arrow_count: u32,
+        obstacle_count: u32,
+        cell_count: u32,
+        show_border: bool,
+        width: u32,
+        height: u32,
+        clear_color: egui::Color32,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Option<image::RgbaImage> {
+        let swap_red_blue = match self.target_format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            _ => return None,
+        };
+
+        // a separate target so the one on screen isnt overwritten, the screenshot can be a different size
+        let target = self.create_scene_target(width, height, device);
+
+        // rows copied out of a texture have to be padded to a multiple of 256 bytes
+        let unpadded_bytes_per_row = width * 4;
+        let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = (unpadded_bytes_per_row + alignment - 1) / alignment * alignment;
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Screenshot Staging Buffer"),
+            size: padded_bytes_per_row as u64 * height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Screenshot Command Encoder"),
+        });
+        self.draw_scene(
+            &target,
+            sphere_count,
+            trail_segment_count,
+            arrow_count,
+            obstacle_count,
+            cell_count,
+            show_border,
+            clear_color,
+            &mut encoder,
+        );
+        encoder.copy_texture_to_buffer(
+            target.resolve_texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &staging_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit([encoder.finish()]);
+
+        let slice = staging_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        device.poll(wgpu::Maintain::Wait);
+
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        for row in slice
+            .get_mapped_range()
+            .chunks_exact(padded_bytes_per_row as usize)
+        {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+        staging_buffer.unmap();
+
+        if swap_red_blue {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        image::RgbaImage::from_raw(width, height, pixels)
+    }
+
+    /// Finds the particle drawn at pixel `x`, `y` of a `width` by `height` viewport by rendering the particle indices,
+    /// `prepare` has to be called first so that the buffers are up to date.
+    /// Returns `None` if there is no particle under the pixel
+    #[allow(clippy::too_many_arguments)]
+    fn pick(
+        &self,
+        sphere_count: u32,
+        width: u32,
+        height: u32,
+        x: u32,
+        y: u32,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Option<u32> {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let index_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Pick Index Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::PICK_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Pick Depth Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Depth32Float,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let index_view = index_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let depth_view = depth_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Pick Staging Buffer"),
+            size: 4,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Pick Command Encoder"),
+        });
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Pick Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &index_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(self.depth_clear_value()),
+                        store: false,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            // only the pixel under the cursor is read back
+            render_pass.set_scissor_rect(x, y, 1, 1);
+            render_pass.set_pipeline(&self.pick_render_pipeline);
+            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            render_pass.set_bind_group(1, &self.particles_bind_group, &[]);
+            render_pass.draw(0..4, 0..sphere_count);
+        }
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &index_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &staging_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: None,
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit([encoder.finish()]);
+
+        let slice = staging_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        device.poll(wgpu::Maintain::Wait);
+        let index = u32::from_ne_bytes(slice.get_mapped_range()[..4].try_into().unwrap());
+        staging_buffer.unmap();
+
+        index.checked_sub(1)
+    }
+}
+
+/// The buffers used by `GpuUpdater` that depend on the number of particles and particle types
+struct GpuUpdateBuffers {
+    particle_count: usize,
+    id_count: u32,
+    obstacle_count: usize,
+    attractor_count: usize,
+    particles_in_buffer: wgpu::Buffer,
+    particles_out_buffer: wgpu::Buffer,
+    attraction_matrix_buffer: wgpu::Buffer,
+    types_buffer: wgpu::Buffer,
+    accelerations_buffer: wgpu::Buffer,
+    /// The obstacles followed by the attractors, they share a buffer to stay within the storage buffer limit
+    obstacles_and_attractors_buffer: wgpu::Buffer,
+    particles_staging_buffer: wgpu::Buffer,
+    accelerations_staging_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Runs `Particles::update` on the gpu using the compute shaders in `particles_update.wgsl`,
+/// the particles are uploaded before and read back after every update so the cpu side state stays up to date
+struct GpuUpdater {
+    device: std::sync::Arc<wgpu::Device>,
+    queue: std::sync::Arc<wgpu::Queue>,
+    parameters_uniform_buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    clear_hash_table_pipeline: wgpu::ComputePipeline,
+    count_particles_pipeline: wgpu::ComputePipeline,
+    prefix_sum_pipeline: wgpu::ComputePipeline,
+    fill_particle_indices_pipeline: wgpu::ComputePipeline,
+    update_particles_pipeline: wgpu::ComputePipeline,
+    buffers: Option<GpuUpdateBuffers>,
+}
+
+impl GpuUpdater {
+    const WORKGROUP_SIZE: u32 = 64;
+
+    fn new(render_state: &eframe::egui_wgpu::RenderState) -> Self {
+        let device = render_state.device.clone();
+        let queue = render_state.queue.clone();
+
+        let shader = device.create_shader_module(include_wgsl!("./particles_update.wgsl"));
+
+        let storage_entry = |binding: u32, read_only: bool| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Update Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(<GpuUpdateParameters as ShaderSize>::SHADER_SIZE),
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, false),
+                storage_entry(3, false),
+                storage_entry(4, false),
+                storage_entry(5, true),
+                storage_entry(6, true),
+                storage_entry(7, false),
+                storage_entry(8, true),
+            ],
+        });
+
+        let parameters_uniform_buffer =
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Update Parameters Uniform Buffer"),
+                contents: &[0; <GpuUpdateParameters as ShaderSize>::SHADER_SIZE.get() as _],
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+            });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Update Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+
+        Self {
+            clear_hash_table_pipeline: create_pipeline("clear_hash_table"),
+            count_particles_pipeline: create_pipeline("count_particles"),
+            prefix_sum_pipeline: create_pipeline("prefix_sum"),
+            fill_particle_indices_pipeline: create_pipeline("fill_particle_indices"),
+            update_particles_pipeline: create_pipeline("update_particles"),
+            device,
+            queue,
+            parameters_uniform_buffer,
+            bind_group_layout,
+            buffers: None,
+        }
+    }
+
+    fn update(&mut self, particles: &mut Particles, ts: f32) {
+        for axis in 0..3 {
+            assert!(particles.world_size[axis] >= 2.0 * particles.particle_effect_radius);
+        }
+        assert!(particles.cell_size > 0.0);
+
+        let particle_count = particles.current_particles.len();
+        if particle_count == 0 {
+            particles.previous_particles.clear();
+            particles.accelerations.clear();
+            return;
+        }
+
+        let buffers_invalidated = self.buffers.as_ref().map_or(true, |buffers| {
+            buffers.particle_count != particle_count
+                || buffers.id_count != particles.id_count
+                || buffers.obstacle_count != particles.obstacles.len()
+                || buffers.attractor_count != particles.attractors.len()
+        });
+        if buffers_invalidated {
+            self.buffers = Some(self.create_buffers(
+                particle_count,
+                particles.id_count,
+                particles.obstacles.len(),
+                particles.attractors.len(),
+            ));
+        }
+        let buffers = self.buffers.as_ref().unwrap();
+
+        let mut parameters_uniform =
+            UniformBuffer::new([0; <GpuUpdateParameters as ShaderSize>::SHADER_SIZE.get() as _]);
+        parameters_uniform
+            .write(&GpuUpdateParameters {
+                gravity: match particles.gravity {
+                    GravityMode::Uniform(gravity) => gravity,
+                    GravityMode::None | GravityMode::Radial { .. } => cgmath::Vector3::zero(),
+                },
+                radial_gravity: match particles.gravity {
+                    GravityMode::Radial { strength } => strength,
+                    GravityMode::None | GravityMode::Uniform(_) => 0.0,
+                },
+                world_size: particles.world_size,
+                boundaries: particles
+                    .boundaries
+                    .map(|boundary| (boundary == BoundaryMode::Solid) as u32)
+                    .into(),
+                cell_size: particles.cell_size,
+                particle_effect_radius: particles.particle_effect_radius,
+                min_attraction_percentage: particles.min_attraction_percentage,
+                wall_restitution: particles.wall_restitution,
+                max_velocity: particles.max_velocity.unwrap_or(-1.0),
+                ts,
+                id_count: particles.id_count,
+                particle_count: particle_count as _,
+                cell_range: (particles.particle_effect_radius / particles.cell_size).ceil() as _,
+                velocity_verlet: (particles.integrator == Integrator::VelocityVerlet) as _,
+                has_previous_accelerations: (particles.accelerations.len() == particle_count) as _,
+                two_dimensional: (particles.dimensions == Dimensions::Two) as _,
+                obstacle_count: particles.obstacles.len() as _,
+                attractor_count: particles.attractors.len() as _,
+                force_profile: match particles.force_profile {
+                    ForceProfile::ParticleLifeDefault => 0,
+                    ForceProfile::SmoothQuadratic => 1,
+                    ForceProfile::LennardJonesLike => 2,
+                },
+            })
+            .unwrap();
+        self.queue.write_buffer(
+            &self.parameters_uniform_buffer,
+            0,
+            &parameters_uniform.into_inner(),
+        );
+
+        let mut particles_storage = StorageBuffer::new(vec![]);
+        particles_storage
+            .write(&particles.current_particles)
+            .unwrap();
+        self.queue.write_buffer(
+            &buffers.particles_in_buffer,
+            0,
+            &particles_storage.into_inner(),
+        );
+
+        let mut attraction_matrix_storage = StorageBuffer::new(vec![]);
+        attraction_matrix_storage
+            .write(&particles.attraction_matrix)
+            .unwrap();
+        self.queue.write_buffer(
+            &buffers.attraction_matrix_buffer,
+            0,
+            &attraction_matrix_storage.into_inner(),
+        );
+
+        let types: Vec<_> = (0..particles.id_count)
+            .map(|id| GpuTypeProperties {
+                mass: particles.mass(id),
+                friction: particles
+                    .per_type_friction
+                    .as_ref()
+                    .map_or(particles.friction, |friction| friction[id as usize]),
+                force_scale: particles.force_scale(id),
+                radius: particles.effect_radius(id),
+            })
+            .collect();
+        let mut types_storage = StorageBuffer::new(vec![]);
+        types_storage.write(&types).unwrap();
+        self.queue
+            .write_buffer(&buffers.types_buffer, 0, &types_storage.into_inner());
+
+        let obstacles_and_attractors: Vec<_> = particles
+            .obstacles
+            .iter()
+            .map(|&(center, radius)| center.extend(radius))
+            .chain(particles.attractors.iter().flat_map(|attractor| {
+                [
+                    attractor.position.extend(attractor.strength),
+                    cgmath::vec4(attractor.radius, 0.0, 0.0, 0.0),
+                ]
+            }))
+            .collect();
+        let mut obstacles_and_attractors_storage = StorageBuffer::new(vec![]);
+        obstacles_and_attractors_storage
+            .write(&obstacles_and_attractors)
+            .unwrap();
+        self.queue.write_buffer(
+            &buffers.obstacles_and_attractors_buffer,
+            0,
+            &obstacles_and_attractors_storage.into_inner(),
+        );
+
+        if particles.accelerations.len() == particle_count {
+            let mut accelerations_storage = StorageBuffer::new(vec![]);
+            accelerations_storage
+                .write(&particles.accelerations)
+                .unwrap();
+            self.queue.write_buffer(
+                &buffers.accelerations_buffer,
+                0,
+                &accelerations_storage.into_inner(),
+            );
+        }
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Update Command Encoder"),
+            });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Update Compute Pass"),
+            });
+            compute_pass.set_bind_group(0, &buffers.bind_group, &[]);
+
+            let workgroups =
+                |count: usize| (count as u32 + Self::WORKGROUP_SIZE - 1) / Self::WORKGROUP_SIZE;
+            compute_pass.set_pipeline(&self.clear_hash_table_pipeline);
+            compute_pass.dispatch_workgroups(workgroups(particle_count + 1), 1, 1);
+            compute_pass.set_pipeline(&self.count_particles_pipeline);
+            compute_pass.dispatch_workgroups(workgroups(particle_count), 1, 1);
+            compute_pass.set_pipeline(&self.prefix_sum_pipeline);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+            compute_pass.set_pipeline(&self.fill_particle_indices_pipeline);
+            compute_pass.dispatch_workgroups(workgroups(particle_count), 1, 1);
+            compute_pass.set_pipeline(&self.update_particles_pipeline);
+            compute_pass.dispatch_workgroups(workgroups(particle_count), 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(
+            &buffers.particles_out_buffer,
+            0,
+            &buffers.particles_staging_buffer,
+            0,
+            buffers.particles_staging_buffer.size(),
+        );
+        encoder.copy_buffer_to_buffer(
+            &buffers.accelerations_buffer,
+            0,
+            &buffers.accelerations_staging_buffer,
+            0,
+            buffers.accelerations_staging_buffer.size(),
+        );
+        self.queue.submit([encoder.finish()]);
+
+        let particles_slice = buffers.particles_staging_buffer.slice(..);
+        let accelerations_slice = buffers.accelerations_staging_buffer.slice(..);
+        particles_slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        accelerations_slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        self.device.poll(wgpu::Maintain::Wait);
+
+        std::mem::swap(
+            &mut particles.current_particles,
+            &mut particles.previous_particles,
+        );
+        StorageBuffer::new(&*particles_slice.get_mapped_range())
+            .read(&mut particles.current_particles)
+            .unwrap();
+        StorageBuffer::new(&*accelerations_slice.get_mapped_range())
+            .read(&mut particles.accelerations)
+            .unwrap();
+        buffers.particles_staging_buffer.unmap();
+        buffers.accelerations_staging_buffer.unmap();
+
+        particles.apply_thermostat(ts);
+    }
+
+    fn create_buffers(
+        &self,
+        particle_count: usize,
+        id_count: u32,
+        obstacle_count: usize,
+        attractor_count: usize,
+    ) -> GpuUpdateBuffers {
+        let create_buffer = |label: &str, size: u64, usage: wgpu::BufferUsages| {
+            self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                // bindings can't be empty
+                size: size.max(16),
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+
+        let particles_size = particle_count as u64 * <Particle as ShaderSize>::SHADER_SIZE.get();
+        let accelerations_size =
+            particle_count as u64 * <cgmath::Vector3<f32> as ShaderSize>::SHADER_SIZE.get();
+        let storage = wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE;
+
+        let particles_in_buffer =
+            create_buffer("Update Particles In Buffer", particles_size, storage);
+        let particles_out_buffer = create_buffer(
+            "Update Particles Out Buffer",
+            particles_size,
+            wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::STORAGE,
+        );
+        let hash_table_buffer = create_buffer(
+            "Update Hash Table Buffer",
+            (particle_count as u64 + 1) * 4,
+            wgpu::BufferUsages::STORAGE,
+        );
+        let particle_indices_buffer = create_buffer(
+            "Update Particle Indices Buffer",
+            particle_count as u64 * 4,
+            wgpu::BufferUsages::STORAGE,
+        );
+        let attraction_matrix_buffer = create_buffer(
+            "Update Attraction Matrix Buffer",
+            id_count as u64 * id_count as u64 * 4,
+            storage,
+        );
+        let types_buffer = create_buffer(
+            "Update Types Buffer",
+            id_count as u64 * <GpuTypeProperties as ShaderSize>::SHADER_SIZE.get(),
+            storage,
+        );
+        let accelerations_buffer = create_buffer(
+            "Update Accelerations Buffer",
+            accelerations_size,
+            storage | wgpu::BufferUsages::COPY_SRC,
+        );
+        let obstacles_and_attractors_buffer = create_buffer(
+            "Update Obstacles And Attractors Buffer",
+            (obstacle_count + attractor_count * 2) as u64
+                * <cgmath::Vector4<f32> as ShaderSize>::SHADER_SIZE.get(),
+            storage,
+        );
+        let staging = wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ;
+        let particles_staging_buffer =
+            create_buffer("Update Particles Staging Buffer", particles_size, staging);
+        let accelerations_staging_buffer = create_buffer(
+            "Update Accelerations Staging Buffer",
+            accelerations_size,
+            staging,
+        );
+
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Update Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.parameters_uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: particles_in_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: particles_out_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: hash_table_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: particle_indices_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: attraction_matrix_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: types_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: accelerations_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: obstacles_and_attractors_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        GpuUpdateBuffers {
+            particle_count,
+            id_count,
+            obstacle_count,
+            attractor_count,
+            particles_in_buffer,
+            particles_out_buffer,
+            attraction_matrix_buffer,
+            types_buffer,
+            accelerations_buffer,
+            obstacles_and_attractors_buffer,
+            particles_staging_buffer,
+            accelerations_staging_buffer,
+            bind_group,
+        }
+    }
+}
+
+/// Bumped whenever the autosave format changes, autosaves with a different version are ignored instead of being misread
+#[cfg(feature = "serde")]
+const AUTOSAVE_VERSION: u32 = 1;
+
+/// The parameters that are saved when the app closes and loaded when it starts again
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Autosave {
+    version: u32,
+    preset: particle_life_3d::Preset,
+}
+
+/// Bumped whenever the session format changes, sessions with a different version fail to load instead of being misread
+#[cfg(feature = "serde")]
+const SESSION_VERSION: u32 = 1;
+
+/// Everything needed to carry on a running simulation later, saved as json
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Session {
+    version: u32,
+    particles: Particles,
+    camera: Camera,
+    settings: SessionSettings,
+}
+
+#[cfg(feature = "serde")]
+impl Session {
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// Reads a session saved by `to_json`, failing if it has a different version or its particles are invalid
+    fn from_json(session: &str) -> Result<Session, String> {
+        #[derive(serde::Deserialize)]
+        struct Version {
+            version: u32,
+        }
+
+        // the version is checked first, so that a file in a different format is never parsed as the current one
+        let Version { version } =
+            serde_json::from_str(session).map_err(|error| error.to_string())?;
+        if version != SESSION_VERSION {
+            return Err(format!(
+                "it has version {version} instead of {SESSION_VERSION}"
+            ));
+        }
+        let session: Session = serde_json::from_str(session).map_err(|error| error.to_string())?;
+        session
+            .particles
+            .validate()
+            .map_err(|error| format!("the particles are invalid: {error}"))?;
+        Ok(session)
+    }
+}
+
+/// The ui settings that are saved in a `Session`
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SessionSettings {
+    paused: bool,
+    seed: u64,
+    target_particle_count: usize,
+    particle_render_radius: f32,
+    color_mode: ColorMode,
+    max_color_speed: f32,
+    size_mode: SizeMode,
+    type_render_scales: Vec<f32>,
+    fog_density: f32,
+    show_border: bool,
+    show_trails: bool,
+    trail_length: usize,
+    trail_opacity: f32,
+    show_arrows: bool,
+    arrow_scale: f32,
+}
+
+/// A native file dialog that only shows preset files
+#[cfg(feature = "serde")]
+fn preset_file_dialog() -> rfd::FileDialog {
+    rfd::FileDialog::new().add_filter("Preset", &["toml"])
+}
+
+/// Where the autosave is kept, in the config directory of the platform
+#[cfg(feature = "serde")]
+fn autosave_path() -> Option<std::path::PathBuf> {
+    let env_path = |name: &str| std::env::var_os(name).map(std::path::PathBuf::from);
+    let config_dir = if cfg!(target_os = "windows") {
+        env_path("APPDATA")?
+    } else if cfg!(target_os = "macos") {
+        env_path("HOME")?.join("Library/Application Support")
+    } else {
+        env_path("XDG_CONFIG_HOME").or_else(|| Some(env_path("HOME")?.join(".config")))?
+    };
+    Some(config_dir.join("particle_life_3d").join("autosave.toml"))
+}
+
+/// The autosaved parameters, or `None` if there arent any or they cant be read
+#[cfg(feature = "serde")]
+fn load_autosave() -> Option<particle_life_3d::Preset> {
+    #[derive(serde::Deserialize)]
+    struct Version {
+        version: u32,
+    }
+
+    let path = autosave_path()?;
+    let autosave = std::fs::read_to_string(&path).ok()?;
+    // the version is checked first, so that a file in a different format is never parsed as the current one
+    match toml::from_str::<Version>(&autosave) {
+        Ok(Version {
+            version: AUTOSAVE_VERSION,
+        }) => {}
+        Ok(Version { version }) => {
+            eprintln!(
+                "Ignoring {} because it has version {version} instead of {AUTOSAVE_VERSION}",
+                path.display()
+            );
+            return None;
+        }
+        Err(error) => {
+            eprintln!("Ignoring {}: {error}", path.display());
+            return None;
+        }
+    }
+    match toml::from_str::<Autosave>(&autosave) {
+        Ok(autosave) => Some(autosave.preset),
+        Err(error) => {
+            eprintln!("Ignoring {}: {error}", path.display());
+            None
+        }
+    }
+}
 
-        render_pass.set_pipeline(&self.border_render_pipeline);
-        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-        render_pass.set_bind_group(1, &self.particles_bind_group, &[]);
-        render_pass.draw(0..24, 0..1);
+#[cfg(feature = "serde")]
+fn save_autosave(preset: particle_life_3d::Preset) -> std::io::Result<()> {
+    let path = autosave_path().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::NotFound, "there is no config directory")
+    })?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
     }
+    let autosave = Autosave {
+        version: AUTOSAVE_VERSION,
+        preset,
+    };
+    std::fs::write(path, toml::to_string(&autosave).unwrap())
+}
+
+/// Moves `velocity` towards `target`, closing the gap at a rate of `acceleration` per second while there is input
+/// and slowing down at a rate of `damping` per second once the target is 0. The rates are exponential,
+/// so the result doesnt depend on the frame rate and never overshoots the target
+fn ease_velocity<V: VectorSpace<Scalar = f32> + PartialEq>(
+    velocity: V,
+    target: V,
+    acceleration: f32,
+    damping: f32,
+    ts: f32,
+) -> V {
+    let rate = if target == V::zero() {
+        damping
+    } else {
+        acceleration
+    };
+    velocity.lerp(target, 1.0 - (-rate * ts).exp())
 }
 
 fn main() {
@@ -742,7 +4537,9 @@ fn main() {
                 present_mode: wgpu::PresentMode::AutoNoVsync,
                 depth_format: Some(wgpu::TextureFormat::Depth32Float),
                 device_descriptor: wgpu::DeviceDescriptor {
-                    features: wgpu::Features::POLYGON_MODE_LINE,
+                    // 8x multisampling is only available through the adapter specific format features
+                    features: wgpu::Features::POLYGON_MODE_LINE
+                        | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
                     ..Default::default()
                 },
                 ..Default::default()
@@ -755,3 +4552,72 @@ fn main() {
     )
     .unwrap();
 }
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        let mut particles = Particles::default();
+        particles.spawn_random(50, 0);
+        particles.update(1.0 / 60.0);
+        Session {
+            version: SESSION_VERSION,
+            particles,
+            camera: Camera {
+                mode: CameraMode::Orbit,
+                orbit_radius: 12.0,
+                position: cgmath::vec3(1.0, 2.0, 3.0),
+                up: cgmath::vec3(0.0, 1.0, 0.0),
+                pitch: 10.0,
+                yaw: -20.0,
+                projection: Projection::Orthographic { height: 8.0 },
+                near: 0.01,
+                far: 200.0,
+                target: Some(cgmath::vec3(0.0, 0.5, 0.0)),
+            },
+            settings: SessionSettings {
+                paused: true,
+                seed: 7,
+                target_particle_count: 50,
+                particle_render_radius: 0.1,
+                color_mode: ColorMode::BySpeed,
+                max_color_speed: 2.0,
+                size_mode: SizeMode::ByType,
+                type_render_scales: vec![1.0, 2.0, 0.5, 1.0, 1.5],
+                fog_density: 0.05,
+                show_border: false,
+                show_trails: true,
+                trail_length: 30,
+                trail_opacity: 0.4,
+                show_arrows: true,
+                arrow_scale: 0.2,
+            },
+        }
+    }
+
+    #[test]
+    fn session_round_trip() {
+        let json = session().to_json();
+        let loaded = Session::from_json(&json).unwrap();
+        assert_eq!(
+            loaded.particles.current_particles,
+            session().particles.current_particles
+        );
+        assert_eq!(loaded.to_json(), json);
+    }
+
+    #[test]
+    fn sessions_with_a_different_version_are_rejected() {
+        let mut session = session();
+        session.version = SESSION_VERSION + 1;
+        let error = Session::from_json(&session.to_json()).err().unwrap();
+        assert_eq!(
+            error,
+            format!(
+                "it has version {} instead of {SESSION_VERSION}",
+                SESSION_VERSION + 1
+            )
+        );
+    }
+}
@@ -0,0 +1,70 @@
+struct VertexIn {
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) obstacle_index: u32,
+};
+
+struct VertexOut {
+    @builtin(position) position: vec4<f32>,
+};
+
+struct Camera {
+    view_matrix: mat4x4<f32>,
+    projection_matrix: mat4x4<f32>,
+    particle_render_radius: f32,
+    color_mode: u32,
+    max_color_speed: f32,
+    selected_particle: u32,
+    fog_color: vec3<f32>,
+    fog_density: f32,
+    border_color: vec3<f32>,
+    size_mode: u32,
+    arrow_scale: f32,
+    slice_axis: u32,
+    slice_center: f32,
+    slice_thickness: f32,
+};
+
+@group(0)
+@binding(0)
+var<uniform> camera: Camera;
+
+// The center of each obstacle in xyz and its radius in w
+struct Obstacles {
+    length: u32,
+    obstacles: array<vec4<f32>>,
+};
+
+@group(1)
+@binding(3)
+var<storage, read> obstacles: Obstacles;
+
+// Each obstacle is drawn as 3 circles around the x, y, and z axes, made from this many lines each
+const segment_count = 32u;
+
+@vertex
+fn vs_main(in: VertexIn) -> VertexOut {
+    var out: VertexOut;
+
+    let circle = in.vertex_index / (segment_count * 2u);
+    let point = (in.vertex_index % (segment_count * 2u)) / 2u + in.vertex_index % 2u;
+    let angle = f32(point) / f32(segment_count) * 6.28318530718;
+
+    var direction: vec3<f32>;
+    if circle == 0u {
+        direction = vec3(cos(angle), sin(angle), 0.0);
+    } else if circle == 1u {
+        direction = vec3(0.0, cos(angle), sin(angle));
+    } else {
+        direction = vec3(sin(angle), 0.0, cos(angle));
+    }
+
+    let obstacle = obstacles.obstacles[in.obstacle_index];
+    let position = obstacle.xyz + direction * obstacle.w;
+    out.position = camera.projection_matrix * camera.view_matrix * vec4(position, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
+    return vec4(0.8, 0.8, 0.8, 1.0);
+}
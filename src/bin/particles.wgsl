@@ -5,14 +5,33 @@ struct VertexIn {
 
 struct VertexOut {
     @builtin(position) position: vec4<f32>,
-    @location(0) world_position: vec3<f32>,
-    @location(1) uv: vec2<f32>,
-    @location(2) particle_index: u32,
+    // The point on the quad in view space, the ray through it is intersected with the sphere
+    @location(0) view_position: vec3<f32>,
+    @location(1) @interpolate(flat) view_center: vec3<f32>,
+    @location(2) @interpolate(flat) particle_index: u32,
+    @location(3) @interpolate(flat) radius: f32,
 };
 
 struct Camera {
     view_matrix: mat4x4<f32>,
     projection_matrix: mat4x4<f32>,
+    particle_render_radius: f32,
+    color_mode: u32,
+    max_color_speed: f32,
+    // The index of the selected particle plus 1, or 0 when no particle is selected
+    selected_particle: u32,
+    fog_color: vec3<f32>,
+    // How quickly particles fade into `fog_color` with distance, 0 turns fog off
+    fog_density: f32,
+    border_color: vec3<f32>,
+    // 0 draws every particle the same size, 1 scales them by the render scale of their type, and 2 by their speed
+    size_mode: u32,
+    arrow_scale: f32,
+    // The axis that particles are sliced along plus 1, or 0 when every particle is drawn
+    slice_axis: u32,
+    // Only particles less than `slice_thickness` away from `slice_center` along the slice axis are drawn
+    slice_center: f32,
+    slice_thickness: f32,
 };
 
 @group(0)
@@ -26,7 +45,7 @@ struct Particle {
 };
 
 struct Particles {
-    world_size: f32,
+    world_size: vec3<f32>,
     length: u32,
     particles: array<Particle>,
 };
@@ -35,9 +54,14 @@ struct Particles {
 @binding(0)
 var<storage, read> particles: Particles;
 
+struct TypeStyle {
+    color: vec3<f32>,
+    render_scale: f32,
+};
+
 struct Colors {
     length: u32,
-    colors: array<vec3<f32>>,
+    colors: array<TypeStyle>,
 };
 
 @group(1)
@@ -49,25 +73,174 @@ fn vs_main(in: VertexIn) -> VertexOut {
     var out: VertexOut;
     out.particle_index = in.particle_index;
 
-    out.uv = vec2(f32((in.vertex_index >> 0u) & 1u), f32((in.vertex_index >> 1u) & 1u));
+    let particle = particles.particles[in.particle_index];
+    if camera.slice_axis != 0u && abs(particle.position[camera.slice_axis - 1u] - camera.slice_center) >= camera.slice_thickness {
+        // every corner of the quad is at the same point, so nothing gets drawn
+        out.position = vec4(0.0);
+        return out;
+    }
+
+    var radius = camera.particle_render_radius;
+    if camera.size_mode == 1u {
+        radius *= colors.colors[particle.id].render_scale;
+    } else if camera.size_mode == 2u {
+        // up to twice as big at `max_color_speed`
+        radius *= 1.0 + clamp(length(particle.velocity) / camera.max_color_speed, 0.0, 1.0);
+    }
+
+    let center = (camera.view_matrix * vec4(particle.position, 1.0)).xyz;
+    out.view_center = center;
+    out.radius = radius;
+
+    // The quad has to cover the outline of the sphere on screen
+    var right = vec3<f32>(1.0, 0.0, 0.0);
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    var half_size = radius;
+    let distance = length(center);
+    if !is_orthographic() && distance > radius {
+        // The outline is where the cone from the camera touches the sphere, which is a circle around the line to the center,
+        // so the quad faces the camera and is as big as the cone where it passes the center
+        let forward = center / distance;
+        right = normalize(cross(forward, select(vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(1.0, 0.0, 0.0), abs(forward.y) > 0.99)));
+        up = cross(right, forward);
+        half_size = radius * distance / sqrt(distance * distance - radius * radius);
+    }
+    let corner = vec2(f32(in.vertex_index & 1u), f32((in.vertex_index >> 1u) & 1u)) * 2.0 - 1.0;
+    out.view_position = center + (right * corner.x + up * corner.y) * half_size;
+    out.position = camera.projection_matrix * vec4(out.view_position, 1.0);
+
+    return out;
+}
+
+fn is_orthographic() -> bool {
+    // A perspective projection copies -z into w, which an orthographic one doesnt
+    return camera.projection_matrix[2][3] == 0.0;
+}
 
-    out.position = camera.view_matrix * vec4(particles.particles[in.particle_index].position, 1.0);
-    out.position += vec4(
-        (out.uv - 0.5) * 0.1,
-        0.0,
-        0.0,
-    );
-    out.position = camera.projection_matrix * out.position;
+struct Hit {
+    hit: bool,
+    // The point on the sphere and its normal, in view space
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    // The direction of the ray from the camera
+    direction: vec3<f32>,
+    depth: f32,
+};
 
-    out.world_position = out.position.xyz / out.position.w;
+// Intersects the ray through the fragment with the sphere, so that the sphere has correct depth instead of being a flat quad
+fn intersect_sphere(in: VertexOut) -> Hit {
+    var origin = vec3<f32>(0.0);
+    var direction = normalize(in.view_position);
+    if is_orthographic() {
+        origin = vec3(in.view_position.xy, 0.0);
+        direction = vec3<f32>(0.0, 0.0, -1.0);
+    }
 
+    var out: Hit;
+    let offset = origin - in.view_center;
+    let b = dot(offset, direction);
+    let h = b * b - (dot(offset, offset) - in.radius * in.radius);
+    if h < 0.0 {
+        out.hit = false;
+        return out;
+    }
+    // the camera can be inside a sphere, then the far side is the one that is seen
+    var t = -b - sqrt(h);
+    if t < 0.0 {
+        t = -b + sqrt(h);
+    }
+    out.position = origin + direction * t;
+    out.normal = (out.position - in.view_center) / in.radius;
+    out.direction = direction;
+    let clip_position = camera.projection_matrix * vec4(out.position, 1.0);
+    out.depth = clip_position.z / clip_position.w;
+    // the same as the clipping the quad would get
+    out.hit = t >= 0.0 && out.depth >= 0.0 && out.depth <= 1.0;
     return out;
 }
 
+// The direction towards the light in view space, so the lit side of the spheres stays the same as the camera moves
+const light_direction = vec3<f32>(0.267261, 0.534522, 0.801784);
+const ambient_light = 0.2;
+
+// A blue -> cyan -> green -> yellow -> red gradient for `t` from 0 to 1
+fn speed_color(t: f32) -> vec3<f32> {
+    let t = clamp(t, 0.0, 1.0);
+    return clamp(vec3(4.0 * t - 2.0, 2.0 - abs(4.0 * t - 2.0), 2.0 - 4.0 * t), vec3(0.0), vec3(1.0));
+}
+
+struct FragmentOut {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+};
+
 @fragment
-fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
-    if length(in.uv * 2.0 - 1.0) > 1.0 {
+fn fs_main(in: VertexOut) -> FragmentOut {
+    let hit = intersect_sphere(in);
+    if !hit.hit {
         discard;
     }
-    return vec4(colors.colors[particles.particles[in.particle_index].id], 1.0);
+    var out: FragmentOut;
+    out.depth = hit.depth;
+
+    let diffuse = max(dot(hit.normal, light_direction), 0.0);
+    // Outline the selected particle, where the surface turns away from the camera
+    if in.particle_index + 1u == camera.selected_particle && dot(hit.normal, -hit.direction) < 0.55 {
+        out.color = vec4(1.0);
+        return out;
+    }
+    let lit_color = particle_color(in.particle_index) * (ambient_light + (1.0 - ambient_light) * diffuse);
+    out.color = vec4(mix(lit_color, camera.fog_color, fog_amount(hit)), 1.0);
+    return out;
+}
+
+fn particle_color(particle_index: u32) -> vec3<f32> {
+    let particle = particles.particles[particle_index];
+    if camera.color_mode == 1u {
+        return speed_color(length(particle.velocity) / camera.max_color_speed);
+    }
+    return colors.colors[particle.id].color;
+}
+
+fn fog_amount(hit: Hit) -> f32 {
+    return 1.0 - exp(-camera.fog_density * max(-hit.position.z, 0.0));
+}
+
+// How much of its color each glowing particle adds at its center
+const glow_strength = 0.5;
+
+// Used with additive blending, so overlapping particles add up into a brighter glow
+@fragment
+fn fs_glow(in: VertexOut) -> FragmentOut {
+    let hit = intersect_sphere(in);
+    if !hit.hit {
+        discard;
+    }
+    var out: FragmentOut;
+    out.depth = hit.depth;
+
+    // fades out towards the edges so the particles look like soft blobs instead of discs
+    let facing = max(dot(hit.normal, -hit.direction), 0.0);
+    // fog hides the glow instead of tinting it, adding the fog color would brighten everything behind the particles
+    let brightness = glow_strength * facing * facing * (1.0 - fog_amount(hit));
+    out.color = vec4(particle_color(in.particle_index) * brightness, 1.0);
+    return out;
+}
+
+struct PickOut {
+    @location(0) index: u32,
+    @builtin(frag_depth) depth: f32,
+};
+
+// Writes the index of the particle plus 1, so that 0 can be used for the background
+@fragment
+fn fs_pick(in: VertexOut) -> PickOut {
+    let hit = intersect_sphere(in);
+    if !hit.hit {
+        discard;
+    }
+    var out: PickOut;
+    out.index = in.particle_index + 1u;
+    out.depth = hit.depth;
+    return out;
 }
@@ -0,0 +1,296 @@
+// Assumes the default wgpu limits, every entry point uses a workgroup size of at most 64
+// and the bind group uses 8 storage buffers (the default limit per shader stage is 8)
+
+struct Particle {
+    position: vec3<f32>,
+    velocity: vec3<f32>,
+    id: u32,
+};
+
+struct Parameters {
+    gravity: vec3<f32>,
+    // pulls particles towards the center of the world, gravity is 0 when this is used
+    radial_gravity: f32,
+    world_size: vec3<f32>,
+    boundaries: vec3<u32>,
+    cell_size: f32,
+    particle_effect_radius: f32,
+    min_attraction_percentage: f32,
+    wall_restitution: f32,
+    max_velocity: f32,
+    ts: f32,
+    id_count: u32,
+    particle_count: u32,
+    cell_range: i32,
+    velocity_verlet: u32,
+    has_previous_accelerations: u32,
+    two_dimensional: u32,
+    obstacle_count: u32,
+    attractor_count: u32,
+    force_profile: u32,
+};
+
+struct TypeProperties {
+    mass: f32,
+    friction: f32,
+    force_scale: f32,
+    // the distance this type feels other particles from, at most `parameters.particle_effect_radius`
+    radius: f32,
+};
+
+@group(0)
+@binding(0)
+var<uniform> parameters: Parameters;
+
+@group(0)
+@binding(1)
+var<storage, read> particles_in: array<Particle>;
+
+@group(0)
+@binding(2)
+var<storage, read_write> particles_out: array<Particle>;
+
+@group(0)
+@binding(3)
+var<storage, read_write> hash_table: array<atomic<u32>>;
+
+@group(0)
+@binding(4)
+var<storage, read_write> particle_indices: array<u32>;
+
+@group(0)
+@binding(5)
+var<storage, read> attraction_matrix: array<f32>;
+
+@group(0)
+@binding(6)
+var<storage, read> types: array<TypeProperties>;
+
+@group(0)
+@binding(7)
+var<storage, read_write> accelerations: array<vec3<f32>>;
+
+// The center of each obstacle in xyz and its radius in w, followed by 2 elements for each attractor,
+// its position in xyz and strength in w, then its radius in x
+@group(0)
+@binding(8)
+var<storage, read> obstacles_and_attractors: array<vec4<f32>>;
+
+fn cell_coord(position: vec3<f32>) -> vec3<i32> {
+    return vec3<i32>(position / parameters.cell_size);
+}
+
+fn hash(cell: vec3<i32>) -> u32 {
+    let h = (bitcast<u32>(cell.x) * 73856093u) ^ (bitcast<u32>(cell.y) * 19349663u) ^ (bitcast<u32>(cell.z) * 83492791u);
+    return h % parameters.particle_count;
+}
+
+// The same as `LENNARD_JONES_MAX_REPULSION`
+const lennard_jones_max_repulsion = 4.0;
+
+// The same as `ForceProfile::force`, `parameters.force_profile` is the index of the profile
+fn force(distance: f32, attraction: f32) -> f32 {
+    let m = parameters.min_attraction_percentage;
+    if distance < m {
+        if parameters.force_profile == 1u {
+            let x = 1.0 - distance / m;
+            return -x * x;
+        } else if parameters.force_profile == 2u {
+            return max(1.0 - pow(m / distance, 6.0), -lennard_jones_max_repulsion);
+        }
+        return distance / m - 1.0;
+    } else if m < distance && distance < 1.0 {
+        // how far between `m` and 1 the particles are
+        let t = (distance - m) / (1.0 - m);
+        if parameters.force_profile == 1u {
+            return attraction * 4.0 * t * (1.0 - t);
+        } else if parameters.force_profile == 2u {
+            return attraction * 27.0 / 4.0 * t * (1.0 - t) * (1.0 - t);
+        }
+        return attraction * (1.0 - abs(2.0 * t - 1.0));
+    } else {
+        return 0.0;
+    }
+}
+
+@compute
+@workgroup_size(64)
+fn clear_hash_table(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x <= parameters.particle_count {
+        atomicStore(&hash_table[id.x], 0u);
+    }
+}
+
+@compute
+@workgroup_size(64)
+fn count_particles(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x < parameters.particle_count {
+        atomicAdd(&hash_table[hash(cell_coord(particles_in[id.x].position))], 1u);
+    }
+}
+
+// This runs on a single invocation, so it is slow for large particle counts
+@compute
+@workgroup_size(1)
+fn prefix_sum() {
+    for (var i = 1u; i <= parameters.particle_count; i++) {
+        atomicAdd(&hash_table[i], atomicLoad(&hash_table[i - 1u]));
+    }
+}
+
+@compute
+@workgroup_size(64)
+fn fill_particle_indices(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x < parameters.particle_count {
+        let index = atomicSub(&hash_table[hash(cell_coord(particles_in[id.x].position))], 1u);
+        particle_indices[index - 1u] = id.x;
+    }
+}
+
+@compute
+@workgroup_size(64)
+fn update_particles(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= parameters.particle_count {
+        return;
+    }
+
+    var particle = particles_in[id.x];
+    let properties = types[particle.id];
+    let world_size = parameters.world_size;
+    let radius = parameters.particle_effect_radius;
+    let cell_range = parameters.cell_range;
+
+    var total_force = vec3<f32>(0.0);
+    var offset_range = vec3<i32>(1) - vec3<i32>(parameters.boundaries);
+    var z_cell_range = cell_range;
+    if parameters.two_dimensional != 0u {
+        offset_range.z = 0;
+        z_cell_range = 0;
+    }
+    for (var x_offset = -offset_range.x; x_offset <= offset_range.x; x_offset++) {
+        for (var y_offset = -offset_range.y; y_offset <= offset_range.y; y_offset++) {
+            for (var z_offset = -offset_range.z; z_offset <= offset_range.z; z_offset++) {
+                let offset = vec3<f32>(f32(x_offset), f32(y_offset), f32(z_offset)) * world_size;
+                let center_cell = cell_coord(particle.position + offset);
+
+                for (var x_cell_offset = -cell_range; x_cell_offset <= cell_range; x_cell_offset++) {
+                    for (var y_cell_offset = -cell_range; y_cell_offset <= cell_range; y_cell_offset++) {
+                        for (var z_cell_offset = -z_cell_range; z_cell_offset <= z_cell_range; z_cell_offset++) {
+                            let cell = center_cell + vec3<i32>(x_cell_offset, y_cell_offset, z_cell_offset);
+                            let bucket = hash(cell);
+                            let start = atomicLoad(&hash_table[bucket]);
+                            let end = atomicLoad(&hash_table[bucket + 1u]);
+                            for (var i = start; i < end; i++) {
+                                let other_particle = particles_in[particle_indices[i]];
+
+                                // Different cells can hash to the same bucket
+                                if any(cell_coord(other_particle.position) != cell) {
+                                    continue;
+                                }
+
+                                let relative_position = other_particle.position - (particle.position + offset);
+                                let sqr_distance = dot(relative_position, relative_position);
+                                if sqr_distance > 0.0 && sqr_distance < properties.radius * properties.radius {
+                                    let distance = sqrt(sqr_distance);
+                                    let f = force(distance, attraction_matrix[particle.id * parameters.id_count + other_particle.id]);
+                                    total_force += relative_position / distance * f;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let ts = parameters.ts;
+    var attractor_force = vec3<f32>(0.0);
+    for (var i = 0u; i < parameters.attractor_count; i++) {
+        let attractor = obstacles_and_attractors[parameters.obstacle_count + i * 2u];
+        let attractor_radius = obstacles_and_attractors[parameters.obstacle_count + i * 2u + 1u].x;
+        let relative_position = attractor.xyz - particle.position;
+        let sqr_distance = dot(relative_position, relative_position);
+        if sqr_distance > 0.0 && sqr_distance < attractor_radius * attractor_radius {
+            let distance = sqrt(sqr_distance);
+            attractor_force += relative_position / distance * attractor.w * (1.0 - distance / attractor_radius);
+        }
+    }
+    var gravity = parameters.gravity;
+    let center_distance = length(particle.position);
+    if center_distance > 0.0 {
+        gravity -= particle.position / center_distance * parameters.radial_gravity;
+    }
+    let acceleration = (total_force * properties.force_scale * radius + attractor_force) / properties.mass + gravity;
+
+    // Update velocity
+    if parameters.velocity_verlet != 0u {
+        var previous_acceleration = acceleration;
+        if parameters.has_previous_accelerations != 0u {
+            previous_acceleration = accelerations[id.x];
+        }
+        particle.velocity += (previous_acceleration + acceleration) * 0.5 * ts;
+    } else {
+        particle.velocity += acceleration * ts;
+    }
+    accelerations[id.x] = acceleration;
+
+    // the same exponential decay as the cpu update, so it doesnt depend on the tick rate
+    particle.velocity *= exp(-properties.friction * ts);
+
+    if parameters.max_velocity >= 0.0 && dot(particle.velocity, particle.velocity) > parameters.max_velocity * parameters.max_velocity {
+        particle.velocity = normalize(particle.velocity) * parameters.max_velocity;
+    }
+
+    // Update position
+    particle.position += particle.velocity * ts;
+    if parameters.velocity_verlet != 0u {
+        particle.position += acceleration * 0.5 * ts * ts;
+    }
+    for (var i = 0u; i < parameters.obstacle_count; i++) {
+        let center = obstacles_and_attractors[i].xyz;
+        let radius = obstacles_and_attractors[i].w;
+        let relative_position = particle.position - center;
+        let sqr_distance = dot(relative_position, relative_position);
+        if sqr_distance < radius * radius {
+            var normal = vec3<f32>(0.0, 1.0, 0.0);
+            if sqr_distance > 0.0 {
+                normal = relative_position / sqrt(sqr_distance);
+            }
+            particle.position = center + normal * radius;
+            let normal_speed = dot(particle.velocity, normal);
+            if normal_speed < 0.0 {
+                particle.velocity -= normal * normal_speed * (1.0 + parameters.wall_restitution);
+            }
+        }
+    }
+    if parameters.two_dimensional != 0u {
+        particle.position.z = 0.0;
+        particle.velocity.z = 0.0;
+    }
+    for (var axis = 0; axis < 3; axis++) {
+        let solid = parameters.boundaries[axis] != 0u;
+        let world_size = world_size[axis];
+        if particle.position[axis] > world_size * 0.5 {
+            if solid {
+                particle.position[axis] = world_size * 0.5;
+                if particle.velocity[axis] > 0.0 {
+                    particle.velocity[axis] *= -parameters.wall_restitution;
+                }
+            } else {
+                particle.position[axis] -= world_size;
+            }
+        }
+        if particle.position[axis] < -world_size * 0.5 {
+            if solid {
+                particle.position[axis] = -world_size * 0.5;
+                if particle.velocity[axis] < 0.0 {
+                    particle.velocity[axis] *= -parameters.wall_restitution;
+                }
+            } else {
+                particle.position[axis] += world_size;
+            }
+        }
+    }
+
+    particles_out[id.x] = particle;
+}
@@ -0,0 +1,107 @@
+struct VertexIn {
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) segment_index: u32,
+};
+
+struct VertexOut {
+    @builtin(position) position: vec4<f32>,
+    @location(0) alpha: f32,
+    @location(1) @interpolate(flat) particle_index: u32,
+};
+
+struct Camera {
+    view_matrix: mat4x4<f32>,
+    projection_matrix: mat4x4<f32>,
+    particle_render_radius: f32,
+    color_mode: u32,
+    max_color_speed: f32,
+    selected_particle: u32,
+    fog_color: vec3<f32>,
+    fog_density: f32,
+    border_color: vec3<f32>,
+    size_mode: u32,
+    arrow_scale: f32,
+    slice_axis: u32,
+    slice_center: f32,
+    slice_thickness: f32,
+};
+
+@group(0)
+@binding(0)
+var<uniform> camera: Camera;
+
+struct Particle {
+    position: vec3<f32>,
+    velocity: vec3<f32>,
+    id: u32,
+};
+
+struct Particles {
+    world_size: vec3<f32>,
+    length: u32,
+    particles: array<Particle>,
+};
+
+@group(1)
+@binding(0)
+var<storage, read> particles: Particles;
+
+struct TypeStyle {
+    color: vec3<f32>,
+    render_scale: f32,
+};
+
+struct Colors {
+    length: u32,
+    colors: array<TypeStyle>,
+};
+
+@group(1)
+@binding(1)
+var<storage, read> colors: Colors;
+
+// The positions of every particle for each of the last `frame_count` updates, oldest first
+struct Trails {
+    frame_count: u32,
+    opacity: f32,
+    length: u32,
+    positions: array<vec3<f32>>,
+};
+
+@group(1)
+@binding(2)
+var<storage, read> trails: Trails;
+
+@vertex
+fn vs_main(in: VertexIn) -> VertexOut {
+    var out: VertexOut;
+
+    let particle_count = particles.length;
+    let particle_index = in.segment_index % particle_count;
+    let frame = in.segment_index / particle_count;
+    out.particle_index = particle_index;
+
+    let start = trails.positions[frame * particle_count + particle_index];
+    let end = trails.positions[(frame + 1u) * particle_count + particle_index];
+    var position = start;
+    if in.vertex_index == 1u {
+        position = end;
+    }
+
+    // Older segments fade out
+    out.alpha = trails.opacity * f32(frame + 1u) / f32(trails.frame_count - 1u);
+
+    // A particle that wrapped around the world jumps across it, so that segment shouldnt be drawn
+    if any(abs(end - start) > particles.world_size * 0.5) {
+        out.position = vec4(0.0, 0.0, 2.0, 1.0);
+        return out;
+    }
+
+    out.position = camera.projection_matrix * camera.view_matrix * vec4(position, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOut) -> @location(0) vec4<f32> {
+    return vec4(colors.colors[particles.particles[in.particle_index].id].color, in.alpha);
+}
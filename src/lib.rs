@@ -1,234 +1,3581 @@
+pub mod presets;
+pub mod replay;
+mod simd;
+
 use std::{
-    collections::hash_map::DefaultHasher,
-    hash::{Hash, Hasher},
+    io::{BufRead, Write},
+    path::Path,
     sync::atomic::{AtomicUsize, Ordering::Relaxed},
 };
 
 use cgmath::prelude::*;
-use encase::ShaderType;
+use rand::prelude::*;
 use rayon::prelude::*;
 
-#[derive(Clone, Copy, ShaderType)]
-pub struct Particle {
+pub use particle::Particle;
+
+mod particle {
+    // the `ShaderType` derive generates a `check` function for each field that is never called
+    #![allow(dead_code)]
+
+    use encase::ShaderType;
+
+    #[derive(Clone, Copy, Debug, PartialEq, ShaderType)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct Particle {
+        pub position: cgmath::Vector3<f32>,
+        pub velocity: cgmath::Vector3<f32>,
+        pub id: u32,
+    }
+}
+
+/// A point that pulls particles towards it, or pushes them away when `strength` is negative
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Attractor {
     pub position: cgmath::Vector3<f32>,
-    pub velocity: cgmath::Vector3<f32>,
-    pub id: u32,
+    /// The force at the attractor's position, which falls off linearly to 0 at `radius`
+    pub strength: f32,
+    pub radius: f32,
+}
+
+/// What happens to particles at the edges of the world along an axis
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BoundaryMode {
+    /// Particles wrap around to the other side of the world and interact across the edge
+    Periodic,
+    /// Particles are stopped by a wall
+    Solid,
+}
+
+/// How the velocities and positions of particles are updated from the forces on them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Integrator {
+    /// Updates the velocity and then moves by the new velocity,
+    /// this is cheap but loses accuracy quickly as the timestep gets bigger
+    SemiImplicitEuler,
+    /// Updates the velocity with the average of the last and current acceleration and moves using both the velocity and acceleration,
+    /// this is more accurate for bigger timesteps but has to store the acceleration of every particle between updates.
+    /// After an update the velocities are the ones at `previous_particles`, the velocities at the new positions need the next acceleration
+    VelocityVerlet,
+}
+
+/// The shape of the force between two particles as they get further apart.
+/// Distances are in the same units as `Particles::force`, where `m` is `min_attraction_percentage`,
+/// particles closer than `m` always push each other away, and the attraction matrix scales the force between `m` and 1
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ForceProfile {
+    /// Repulsion that falls linearly from -1 at 0 to 0 at `m`,
+    /// then a triangle that peaks at the attraction halfway between `m` and 1
+    #[default]
+    ParticleLifeDefault,
+    /// Repulsion of `-(1 - d/m)^2` that eases into 0 at `m`,
+    /// then a parabola that peaks at the attraction halfway between `m` and 1, so there are no sharp corners
+    SmoothQuadratic,
+    /// A steep wall of `1 - (m/d)^6` inside `m`, capped at `-LENNARD_JONES_MAX_REPULSION`,
+    /// then an attraction that peaks a third of the way from `m` to 1 and has a long tail, like the Lennard-Jones potential
+    LennardJonesLike,
+}
+
+/// A force that accelerates every particle the same amount no matter its mass
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GravityMode {
+    #[default]
+    None,
+    /// Accelerates every particle along the same vector
+    Uniform(cgmath::Vector3<f32>),
+    /// Accelerates every particle towards the center of the world by `strength`, which pulls the particles into a ball
+    Radial { strength: f32 },
+}
+
+impl GravityMode {
+    /// The acceleration of a particle at `position`
+    pub fn acceleration(self, position: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+        match self {
+            GravityMode::None => cgmath::Vector3::zero(),
+            GravityMode::Uniform(gravity) => gravity,
+            GravityMode::Radial { strength } => {
+                let distance = position.magnitude();
+                if distance > 0.0 {
+                    -position / distance * strength
+                } else {
+                    cgmath::Vector3::zero()
+                }
+            }
+        }
+    }
+}
+
+/// Reads a gravity that is either a `GravityMode` or a single vector,
+/// which is what gravity was before there was more than one mode
+#[cfg(feature = "serde")]
+fn deserialize_gravity<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<GravityMode, D::Error> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum Gravity {
+        Mode(GravityMode),
+        Vector(cgmath::Vector3<f32>),
+    }
+
+    Ok(match serde::Deserialize::deserialize(deserializer)? {
+        Gravity::Mode(mode) => mode,
+        Gravity::Vector(gravity) if gravity == cgmath::Vector3::zero() => GravityMode::None,
+        Gravity::Vector(gravity) => GravityMode::Uniform(gravity),
+    })
+}
+
+/// The strongest repulsion from `ForceProfile::LennardJonesLike`, without a limit particles that overlap would be thrown apart
+pub const LENNARD_JONES_MAX_REPULSION: f32 = 4.0;
+
+impl ForceProfile {
+    /// The force between two particles `distance` apart, positive forces pull the particles together
+    pub fn force(self, distance: f32, attraction: f32, min_attraction_percentage: f32) -> f32 {
+        let m = min_attraction_percentage;
+        if distance < m {
+            match self {
+                ForceProfile::ParticleLifeDefault => distance / m - 1.0,
+                ForceProfile::SmoothQuadratic => -(1.0 - distance / m).powi(2),
+                ForceProfile::LennardJonesLike => {
+                    (1.0 - (m / distance).powi(6)).max(-LENNARD_JONES_MAX_REPULSION)
+                }
+            }
+        } else if m < distance && distance < 1.0 {
+            // how far between `m` and 1 the particles are
+            let t = (distance - m) / (1.0 - m);
+            attraction
+                * match self {
+                    ForceProfile::ParticleLifeDefault => 1.0 - (2.0 * t - 1.0).abs(),
+                    ForceProfile::SmoothQuadratic => 4.0 * t * (1.0 - t),
+                    ForceProfile::LennardJonesLike => 27.0 / 4.0 * t * (1.0 - t).powi(2),
+                }
+        } else {
+            0.0
+        }
+    }
+}
+
+/// The parameters of a simulation without any particles, so they can be saved and loaded separately
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Preset {
+    /// Presets saved before the world could be a different size along each axis have a single size for every axis
+    #[serde(deserialize_with = "deserialize_world_size")]
+    pub world_size: cgmath::Vector3<f32>,
+    pub id_count: u32,
+    pub colors: Vec<cgmath::Vector3<f32>>,
+    pub attraction_matrix: Vec<f32>,
+    pub friction: f32,
+    pub force_scale: f32,
+    pub min_attraction_percentage: f32,
+    /// Presets saved before force profiles existed use the default profile
+    #[serde(default)]
+    pub force_profile: ForceProfile,
+    pub particle_effect_radius: f32,
+    /// Presets saved before there were gravity modes have a single gravity vector
+    #[serde(deserialize_with = "deserialize_gravity")]
+    pub gravity: GravityMode,
+    pub boundaries: [BoundaryMode; 3],
+}
+
+/// Reads a world size that is either the same along every axis or given separately for each axis
+#[cfg(feature = "serde")]
+fn deserialize_world_size<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<cgmath::Vector3<f32>, D::Error> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum WorldSize {
+        Cube(f32),
+        PerAxis(cgmath::Vector3<f32>),
+    }
+
+    Ok(match serde::Deserialize::deserialize(deserializer)? {
+        WorldSize::Cube(size) => cgmath::vec3(size, size, size),
+        WorldSize::PerAxis(size) => size,
+    })
+}
+
+#[cfg(feature = "serde")]
+impl Preset {
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap()
+    }
+
+    pub fn from_toml(s: &str) -> Result<Preset, toml::de::Error> {
+        toml::from_str(s)
+    }
 }
 
+/// Whether the particles move in a plane or through the whole world
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Dimensions {
+    /// The particles stay on the `z = 0` plane, which is much cheaper to simulate
+    Two,
+    Three,
+}
+
+/// How `update` finds the particles that are close enough to interact
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AccelStructure {
+    /// Hashes the cells into as many buckets as there are particles,
+    /// the memory used doesnt depend on the world size but different cells can share a bucket
+    SpatialHash,
+    /// Stores every cell in the world in a grid, so cells never share a bucket,
+    /// but the grid gets big when there are a lot of cells in the world
+    UniformGrid,
+    /// Like `SpatialHash`, but the buckets are the Morton code of the cell instead of a generic hash,
+    /// so cells that are close together usually have buckets that are close together in memory
+    MortonHash,
+}
+
+/// Interleaves the lower 21 bits of each coordinate into a 63 bit index along a Z-order curve,
+/// bit `i` of `cell[axis]` becomes bit `3 * i + axis` of the result, so x is the lowest bit of every group of 3
+fn morton_code(cell: [u64; 3]) -> u64 {
+    /// Moves each of the lower 21 bits of `x` so there are 2 zero bits between them
+    fn spread_bits(x: u64) -> u64 {
+        let mut x = x & 0x1f_ffff;
+        x = (x | x << 32) & 0x001f_0000_0000_ffff;
+        x = (x | x << 16) & 0x001f_0000_ff00_00ff;
+        x = (x | x << 8) & 0x100f_00f0_0f00_f00f;
+        x = (x | x << 4) & 0x10c3_0c30_c30c_30c3;
+        x = (x | x << 2) & 0x1249_2492_4924_9249;
+        x
+    }
+
+    spread_bits(cell[0]) | spread_bits(cell[1]) << 1 | spread_bits(cell[2]) << 2
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Particles {
-    pub world_size: f32,
+    /// The size of the world along the x, y, and z axes, the world is centered on the origin
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "deserialize_world_size"))]
+    pub world_size: cgmath::Vector3<f32>,
     pub current_particles: Vec<Particle>,
     pub previous_particles: Vec<Particle>,
     pub id_count: u32,
     pub attraction_matrix: Vec<f32>,
     pub colors: Vec<cgmath::Vector3<f32>>,
+    /// The mass of each particle id, every particle has a mass of 1 when this is not set
+    pub masses: Option<Vec<f32>>,
+    /// How quickly particles slow down, velocities decay by a factor of `exp(-friction)` every second
+    /// no matter how many updates that second is split into
     pub friction: f32,
+    /// Overrides `friction` for each particle id when set
+    pub per_type_friction: Option<Vec<f32>>,
     pub force_scale: f32,
+    /// Multiplies `force_scale` for each particle id when set
+    pub per_type_force_scale: Option<Vec<f32>>,
+    /// Overrides `particle_effect_radius` as the distance that particles of each id feel other particles from when set,
+    /// these cant be bigger than `particle_effect_radius` because that decides which cells are searched
+    pub per_type_radius: Option<Vec<f32>>,
+    /// The speed particles are limited to after their velocity is updated
+    pub max_velocity: Option<f32>,
+    /// The mean speed that the thermostat pulls the particles towards after every update, the thermostat is off when this is not set
+    pub target_speed: Option<f32>,
+    /// How quickly the thermostat corrects the mean speed, as the fraction of the difference removed per second
+    pub thermostat_coupling: f32,
     pub min_attraction_percentage: f32,
+    pub force_profile: ForceProfile,
     pub particle_effect_radius: f32,
-    pub solid_walls: bool,
-    pub gravity: cgmath::Vector3<f32>,
+    /// The size of the cells used to find neighbouring particles, defaults to `particle_effect_radius`.
+    /// Bigger cells mean fewer cells to search but more particles that are too far away to interact,
+    /// smaller cells skip more of those particles at the cost of searching more cells
+    pub cell_size: f32,
+    /// What happens to particles at the edges of the world along the x, y, and z axes
+    pub boundaries: [BoundaryMode; 3],
+    /// How much of a particle's speed is kept when it bounces off a solid wall,
+    /// 0 stops the particle and 1 is a perfectly elastic bounce
+    pub wall_restitution: f32,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "deserialize_gravity"))]
+    pub gravity: GravityMode,
+    /// Static spheres that particles bounce off, as `(center, radius)`.
+    /// Obstacles dont wrap around the edges of the world
+    pub obstacles: Vec<(cgmath::Vector3<f32>, f32)>,
+    /// Like obstacles, attractors dont wrap around the edges of the world
+    pub attractors: Vec<Attractor>,
+    pub integrator: Integrator,
+    pub dimensions: Dimensions,
+    /// The acceleration of each particle from the last update, used by `Integrator::VelocityVerlet`
+    pub accelerations: Vec<cgmath::Vector3<f32>>,
+    pub accel_structure: AccelStructure,
+    /// Makes `update` add up the forces on each particle in the same order no matter how many threads there are or how they are scheduled,
+    /// so the results are the same every time. This is slower, because the neighbours in each bucket have to be sorted
+    pub deterministic: bool,
+    /// Stops adding up the forces on a particle once this many candidates from the surrounding cells have been looked at,
+    /// which bounds the cost of an update when lots of particles collapse into the same cells at the cost of accuracy.
+    /// Every candidate counts, including ones outside the effect radius, on top of the particle, or from other cells in the same bucket.
+    /// The neighbours that are kept are the first ones found searching the cells from -x, -y, -z to +x, +y, +z,
+    /// so the forces that are left out are mostly from the +x, +y, +z side of the particle.
+    /// The buckets are sorted while this is set, like with `deterministic`, so the same neighbours are kept every update.
+    /// `update_brute_force` and the gpu update ignore this
+    pub max_neighbours: Option<usize>,
+    /// Scratch space for `update` that is kept between updates so it doesnt have to be reallocated every time
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub spatial_hash: SpatialHash,
+    /// Scratch space for `update` when using `AccelStructure::UniformGrid`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub uniform_grid: UniformGrid,
+    /// The number of pairs of particles within `particle_effect_radius` of each other in the last update,
+    /// each pair is counted from both particles and again for every periodic copy it is close to
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub last_step_pair_count: usize,
+    /// The number of particles that hit a solid wall of the world in the last update, a particle hitting several walls is counted once.
+    /// Particles are integrated in parallel, so there is no per particle callback for this,
+    /// compare `previous_particles` and `current_particles` to find which particles hit a wall
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub last_step_wall_hits: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParticlesError {
+    WorldTooSmall {
+        axis: usize,
+        world_size: f32,
+        particle_effect_radius: f32,
+    },
+    NoTypes,
+    AttractionMatrixLength {
+        expected: usize,
+        actual: usize,
+    },
+    ColorsLength {
+        expected: usize,
+        actual: usize,
+    },
+    PerTypeValuesLength {
+        name: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    InvalidCellSize {
+        cell_size: f32,
+    },
+    InvalidParticleId {
+        index: usize,
+        id: u32,
+    },
+    InvalidPerTypeRadius {
+        id: u32,
+        radius: f32,
+        particle_effect_radius: f32,
+    },
+}
+
+impl std::fmt::Display for ParticlesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            ParticlesError::WorldTooSmall {
+                axis,
+                world_size,
+                particle_effect_radius,
+            } => write!(
+                f,
+                "world size {world_size} along axis {axis} must be at least twice the particle effect radius {particle_effect_radius}"
+            ),
+            ParticlesError::NoTypes => write!(f, "there must be at least one particle type"),
+            ParticlesError::AttractionMatrixLength { expected, actual } => write!(
+                f,
+                "attraction matrix has {actual} entries but should have {expected}"
+            ),
+            ParticlesError::ColorsLength { expected, actual } => {
+                write!(f, "there are {actual} colors but there should be {expected}")
+            }
+            ParticlesError::PerTypeValuesLength {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "there are {actual} values in {name} but there should be {expected}"
+            ),
+            ParticlesError::InvalidCellSize { cell_size } => {
+                write!(f, "cell size {cell_size} must be greater than 0")
+            }
+            ParticlesError::InvalidParticleId { index, id } => {
+                write!(f, "particle {index} has id {id} which is out of range")
+            }
+            ParticlesError::InvalidPerTypeRadius {
+                id,
+                radius,
+                particle_effect_radius,
+            } => write!(
+                f,
+                "radius {radius} of id {id} must be between 0 and the particle effect radius {particle_effect_radius}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParticlesError {}
+
+impl Default for Particles {
+    /// Five types of particles that form small moving clusters, with no particles spawned yet
+    fn default() -> Self {
+        Particles {
+            world_size: cgmath::vec3(10.0, 10.0, 10.0),
+            current_particles: vec![],
+            previous_particles: vec![],
+            id_count: 5,
+            attraction_matrix: vec![
+                0.5, 1.0, -0.5, 0.0, -1.0, // red
+                1.0, 1.0, 1.0, 0.0, -1.0, // green
+                0.0, 0.0, 0.5, 1.5, -1.0, // blue
+                0.0, 0.0, 0.0, 0.0, -1.0, // yellow
+                1.0, 1.0, 1.0, 1.0, 0.5, // purple
+            ],
+            colors: vec![
+                cgmath::vec3(1.0, 0.0, 0.0), // red
+                cgmath::vec3(0.0, 1.0, 0.0), // green
+                cgmath::vec3(0.0, 0.0, 1.0), // blue
+                cgmath::vec3(1.0, 1.0, 0.0), // yellow
+                cgmath::vec3(1.0, 0.0, 1.0), // purple
+            ],
+            masses: None,
+            friction: 0.97,
+            per_type_friction: None,
+            force_scale: 1.0,
+            per_type_force_scale: None,
+            per_type_radius: None,
+            max_velocity: None,
+            target_speed: None,
+            thermostat_coupling: 1.0,
+            min_attraction_percentage: 0.3,
+            force_profile: ForceProfile::ParticleLifeDefault,
+            particle_effect_radius: 2.0,
+            cell_size: 2.0,
+            boundaries: [BoundaryMode::Periodic; 3],
+            wall_restitution: 0.0,
+            gravity: GravityMode::None,
+            obstacles: vec![],
+            attractors: vec![],
+            integrator: Integrator::SemiImplicitEuler,
+            dimensions: Dimensions::Three,
+            accelerations: vec![],
+            accel_structure: AccelStructure::SpatialHash,
+            deterministic: false,
+            max_neighbours: None,
+            spatial_hash: SpatialHash::default(),
+            uniform_grid: UniformGrid::default(),
+            last_step_pair_count: 0,
+            last_step_wall_hits: 0,
+        }
+    }
+}
+
+/// Builds `Particles` starting from `Particles::default`, checking the invariants once every value has been set
+#[derive(Clone, Default)]
+pub struct ParticlesBuilder {
+    particles: Particles,
+    /// Follows `particle_effect_radius` when this is not set, like in `Particles::new`
+    cell_size: Option<f32>,
+}
+
+impl ParticlesBuilder {
+    pub fn world_size(mut self, world_size: cgmath::Vector3<f32>) -> Self {
+        self.particles.world_size = world_size;
+        self
+    }
+
+    /// Replaces the particle types, the number of types is the number of colors
+    pub fn types(mut self, attraction_matrix: Vec<f32>, colors: Vec<cgmath::Vector3<f32>>) -> Self {
+        self.particles.id_count = colors.len() as u32;
+        self.particles.attraction_matrix = attraction_matrix;
+        self.particles.colors = colors;
+        self
+    }
+
+    pub fn masses(mut self, masses: Vec<f32>) -> Self {
+        self.particles.masses = Some(masses);
+        self
+    }
+
+    pub fn per_type_radius(mut self, per_type_radius: Vec<f32>) -> Self {
+        self.particles.per_type_radius = Some(per_type_radius);
+        self
+    }
+
+    pub fn friction(mut self, friction: f32) -> Self {
+        self.particles.friction = friction;
+        self
+    }
+
+    pub fn force_scale(mut self, force_scale: f32) -> Self {
+        self.particles.force_scale = force_scale;
+        self
+    }
+
+    pub fn max_velocity(mut self, max_velocity: f32) -> Self {
+        self.particles.max_velocity = Some(max_velocity);
+        self
+    }
+
+    pub fn min_attraction_percentage(mut self, min_attraction_percentage: f32) -> Self {
+        self.particles.min_attraction_percentage = min_attraction_percentage;
+        self
+    }
+
+    pub fn force_profile(mut self, force_profile: ForceProfile) -> Self {
+        self.particles.force_profile = force_profile;
+        self
+    }
+
+    pub fn particle_effect_radius(mut self, particle_effect_radius: f32) -> Self {
+        self.particles.particle_effect_radius = particle_effect_radius;
+        self
+    }
+
+    pub fn cell_size(mut self, cell_size: f32) -> Self {
+        self.cell_size = Some(cell_size);
+        self
+    }
+
+    pub fn boundaries(mut self, boundaries: [BoundaryMode; 3]) -> Self {
+        self.particles.boundaries = boundaries;
+        self
+    }
+
+    pub fn wall_restitution(mut self, wall_restitution: f32) -> Self {
+        self.particles.wall_restitution = wall_restitution;
+        self
+    }
+
+    pub fn gravity(mut self, gravity: GravityMode) -> Self {
+        self.particles.gravity = gravity;
+        self
+    }
+
+    pub fn integrator(mut self, integrator: Integrator) -> Self {
+        self.particles.integrator = integrator;
+        self
+    }
+
+    pub fn dimensions(mut self, dimensions: Dimensions) -> Self {
+        self.particles.dimensions = dimensions;
+        self
+    }
+
+    pub fn accel_structure(mut self, accel_structure: AccelStructure) -> Self {
+        self.particles.accel_structure = accel_structure;
+        self
+    }
+
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.particles.deterministic = deterministic;
+        self
+    }
+
+    pub fn max_neighbours(mut self, max_neighbours: usize) -> Self {
+        self.particles.max_neighbours = Some(max_neighbours);
+        self
+    }
+
+    pub fn particles(mut self, particles: Vec<Particle>) -> Self {
+        self.particles.current_particles = particles;
+        self
+    }
+
+    pub fn build(self) -> Result<Particles, ParticlesError> {
+        let mut particles = self.particles;
+        particles.cell_size = self.cell_size.unwrap_or(particles.particle_effect_radius);
+        particles.validate()?;
+        Ok(particles)
+    }
 }
 
 impl Particles {
-    pub fn update(&mut self, ts: f32) {
-        // Apply forces
+    pub fn builder() -> ParticlesBuilder {
+        ParticlesBuilder::default()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        world_size: cgmath::Vector3<f32>,
+        id_count: u32,
+        attraction_matrix: Vec<f32>,
+        colors: Vec<cgmath::Vector3<f32>>,
+        friction: f32,
+        force_scale: f32,
+        min_attraction_percentage: f32,
+        particle_effect_radius: f32,
+        boundaries: [BoundaryMode; 3],
+        gravity: GravityMode,
+        particles: Vec<Particle>,
+    ) -> Result<Particles, ParticlesError> {
+        let particles = Particles {
+            world_size,
+            current_particles: particles,
+            previous_particles: vec![],
+            id_count,
+            attraction_matrix,
+            colors,
+            masses: None,
+            friction,
+            per_type_friction: None,
+            force_scale,
+            per_type_force_scale: None,
+            per_type_radius: None,
+            max_velocity: None,
+            target_speed: None,
+            thermostat_coupling: 1.0,
+            min_attraction_percentage,
+            force_profile: ForceProfile::ParticleLifeDefault,
+            particle_effect_radius,
+            cell_size: particle_effect_radius,
+            boundaries,
+            wall_restitution: 0.0,
+            gravity,
+            obstacles: vec![],
+            attractors: vec![],
+            integrator: Integrator::SemiImplicitEuler,
+            dimensions: Dimensions::Three,
+            accelerations: vec![],
+            accel_structure: AccelStructure::SpatialHash,
+            deterministic: false,
+            max_neighbours: None,
+            spatial_hash: SpatialHash::default(),
+            uniform_grid: UniformGrid::default(),
+            last_step_pair_count: 0,
+            last_step_wall_hits: 0,
+        };
+        particles.validate()?;
+        Ok(particles)
+    }
+
+    /// Checks all the invariants that `update` relies on
+    pub fn validate(&self) -> Result<(), ParticlesError> {
+        for axis in 0..3 {
+            if self.world_size[axis] < 2.0 * self.particle_effect_radius {
+                return Err(ParticlesError::WorldTooSmall {
+                    axis,
+                    world_size: self.world_size[axis],
+                    particle_effect_radius: self.particle_effect_radius,
+                });
+            }
+        }
+        if self.id_count == 0 {
+            return Err(ParticlesError::NoTypes);
+        }
+        let id_count = self.id_count as usize;
+        if self.attraction_matrix.len() != id_count * id_count {
+            return Err(ParticlesError::AttractionMatrixLength {
+                expected: id_count * id_count,
+                actual: self.attraction_matrix.len(),
+            });
+        }
+        if self.colors.len() != id_count {
+            return Err(ParticlesError::ColorsLength {
+                expected: id_count,
+                actual: self.colors.len(),
+            });
+        }
+        for (name, values) in [
+            ("per_type_friction", &self.per_type_friction),
+            ("per_type_force_scale", &self.per_type_force_scale),
+            ("per_type_radius", &self.per_type_radius),
+            ("masses", &self.masses),
+        ] {
+            if let Some(values) = values {
+                if values.len() != id_count {
+                    return Err(ParticlesError::PerTypeValuesLength {
+                        name,
+                        expected: id_count,
+                        actual: values.len(),
+                    });
+                }
+            }
+        }
+        if let Some(per_type_radius) = &self.per_type_radius {
+            if let Some((id, &radius)) = per_type_radius
+                .iter()
+                .enumerate()
+                .find(|&(_, &radius)| !(0.0..=self.particle_effect_radius).contains(&radius))
+            {
+                return Err(ParticlesError::InvalidPerTypeRadius {
+                    id: id as u32,
+                    radius,
+                    particle_effect_radius: self.particle_effect_radius,
+                });
+            }
+        }
+        if self.cell_size <= 0.0 || self.cell_size.is_nan() {
+            return Err(ParticlesError::InvalidCellSize {
+                cell_size: self.cell_size,
+            });
+        }
+        if let Some((index, particle)) = self
+            .current_particles
+            .iter()
+            .enumerate()
+            .find(|(_, particle)| particle.id >= self.id_count)
         {
-            assert!(self.world_size >= 2.0 * self.particle_effect_radius);
+            return Err(ParticlesError::InvalidParticleId {
+                index,
+                id: particle.id,
+            });
+        }
+        Ok(())
+    }
 
-            let cell_coord = |v: cgmath::Vector3<f32>| -> cgmath::Vector3<isize> {
-                cgmath::vec3(
-                    (v.x / self.particle_effect_radius) as isize,
-                    (v.y / self.particle_effect_radius) as isize,
-                    (v.z / self.particle_effect_radius) as isize,
-                )
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn from_json(s: &str) -> Result<Particles, serde_json::Error> {
+        serde_json::from_str(s)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn preset(&self) -> Preset {
+        Preset {
+            world_size: self.world_size,
+            id_count: self.id_count,
+            colors: self.colors.clone(),
+            attraction_matrix: self.attraction_matrix.clone(),
+            friction: self.friction,
+            force_scale: self.force_scale,
+            min_attraction_percentage: self.min_attraction_percentage,
+            force_profile: self.force_profile,
+            particle_effect_radius: self.particle_effect_radius,
+            gravity: self.gravity,
+            boundaries: self.boundaries,
+        }
+    }
+
+    /// Replaces the parameters with the ones in `preset`, leaving everything unchanged if they are invalid.
+    /// Particles with an id that no longer exists are removed, and the per id values are reset when the number of ids changes
+    #[cfg(feature = "serde")]
+    pub fn apply_preset(&mut self, preset: Preset) -> Result<(), ParticlesError> {
+        let mut particles = Particles {
+            world_size: preset.world_size,
+            id_count: preset.id_count,
+            colors: preset.colors,
+            attraction_matrix: preset.attraction_matrix,
+            friction: preset.friction,
+            force_scale: preset.force_scale,
+            min_attraction_percentage: preset.min_attraction_percentage,
+            force_profile: preset.force_profile,
+            particle_effect_radius: preset.particle_effect_radius,
+            gravity: preset.gravity,
+            boundaries: preset.boundaries,
+            ..self.clone()
+        };
+        if particles.id_count != self.id_count {
+            particles.masses = None;
+            particles.per_type_friction = None;
+            particles.per_type_force_scale = None;
+            particles.per_type_radius = None;
+        }
+        particles
+            .current_particles
+            .retain(|particle| particle.id < preset.id_count);
+        particles.validate()?;
+
+        if particles.current_particles.len() != self.current_particles.len() {
+            particles.previous_particles.clear();
+            particles.accelerations.clear();
+        }
+        *self = particles;
+        Ok(())
+    }
+
+    /// Replaces all the particles with `count` particles spread uniformly throughout the world,
+    /// spawning with the same seed and the same parameters will always produce the same particles
+    pub fn spawn_random(&mut self, count: usize, seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        self.current_particles = std::iter::repeat_with(|| self.random_particle(&mut rng))
+            .take(count)
+            .collect();
+        self.accelerations.clear();
+    }
+
+    /// Replaces all the particles with `count` particles spread uniformly throughout the largest sphere centered on the origin
+    /// that fits in the world, which is a circle in 2d
+    pub fn spawn_in_sphere(&mut self, count: usize, seed: u64) {
+        self.spawn_in_shell(count, 0.0, self.min_world_size() * 0.5, seed);
+    }
+
+    /// Replaces all the particles with `count` particles spread uniformly throughout the space between
+    /// the spheres of radius `inner_r` and `outer_r` centered on the origin, which are circles in 2d.
+    /// `outer_r` cant be more than half of `min_world_size`, so that the shell fits inside the world
+    pub fn spawn_in_shell(&mut self, count: usize, inner_r: f32, outer_r: f32, seed: u64) {
+        assert!(0.0 <= inner_r && inner_r <= outer_r);
+        assert!(outer_r <= self.min_world_size() * 0.5);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let dimensions = match self.dimensions {
+            Dimensions::Two => 2,
+            Dimensions::Three => 3,
+        };
+        self.current_particles = std::iter::repeat_with(|| {
+            let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+            let z: f32 = match self.dimensions {
+                Dimensions::Two => 0.0,
+                Dimensions::Three => rng.gen_range(-1.0..=1.0),
             };
+            let ring_radius = (1.0 - z * z).sqrt();
+            let direction = cgmath::vec3(ring_radius * angle.cos(), ring_radius * angle.sin(), z);
+            // the amount of space at each radius grows with the radius, so the radius isnt chosen uniformly
+            let radius = (inner_r.powi(dimensions)
+                + rng.gen::<f32>() * (outer_r.powi(dimensions) - inner_r.powi(dimensions)))
+            .powf(1.0 / dimensions as f32)
+            .clamp(inner_r, outer_r);
+            Particle {
+                position: direction * radius,
+                velocity: cgmath::vec3(0.0, 0.0, 0.0),
+                id: rng.gen_range(0..self.id_count),
+            }
+        })
+        .take(count)
+        .collect();
+        self.accelerations.clear();
+    }
 
-            fn hash(cgmath::Vector3 { x, y, z }: cgmath::Vector3<isize>) -> usize {
-                let mut hasher = DefaultHasher::new();
-                x.hash(&mut hasher);
-                y.hash(&mut hasher);
-                z.hash(&mut hasher);
-                hasher.finish() as usize
+    /// Makes the attraction matrix symmetric by setting both `i -> j` and `j -> i` to their average
+    pub fn symmetric_attraction(&mut self) {
+        let n = self.id_count as usize;
+        for i in 0..n {
+            for j in i + 1..n {
+                let average =
+                    (self.attraction_matrix[i * n + j] + self.attraction_matrix[j * n + i]) * 0.5;
+                self.attraction_matrix[i * n + j] = average;
+                self.attraction_matrix[j * n + i] = average;
             }
+        }
+    }
 
-            let hash_table_length = self.current_particles.len();
-            let hash_table: Vec<_> = std::iter::repeat_with(|| AtomicUsize::new(0))
-                .take(hash_table_length + 1)
-                .collect();
+    /// Fills the attraction matrix with values chosen uniformly from `range`
+    pub fn randomize_attraction(&mut self, seed: u64, range: std::ops::RangeInclusive<f32>) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        for attraction in &mut self.attraction_matrix {
+            *attraction = rng.gen_range(range.clone());
+        }
+    }
 
-            self.current_particles.par_iter().for_each(|sphere| {
-                let index = hash(cell_coord(sphere.position)) % hash_table_length;
-                hash_table[index].fetch_add(1, Relaxed);
-            });
+    /// Adds a new particle id with the color `color` that doesnt attract or repel any other particles,
+    /// returning the new id
+    pub fn add_type(&mut self, color: cgmath::Vector3<f32>) -> u32 {
+        let n = self.id_count as usize;
+        let mut attraction_matrix = vec![0.0; (n + 1) * (n + 1)];
+        for i in 0..n {
+            attraction_matrix[i * (n + 1)..i * (n + 1) + n]
+                .copy_from_slice(&self.attraction_matrix[i * n..(i + 1) * n]);
+        }
+        self.attraction_matrix = attraction_matrix;
+        self.colors.push(color);
+        if let Some(masses) = &mut self.masses {
+            masses.push(1.0);
+        }
+        if let Some(per_type_friction) = &mut self.per_type_friction {
+            per_type_friction.push(self.friction);
+        }
+        if let Some(per_type_force_scale) = &mut self.per_type_force_scale {
+            per_type_force_scale.push(1.0);
+        }
+        if let Some(per_type_radius) = &mut self.per_type_radius {
+            per_type_radius.push(self.particle_effect_radius);
+        }
+        self.id_count += 1;
+        self.id_count - 1
+    }
+
+    /// Gives every particle with an id that doesnt exist anymore the id it wraps around to, so it can keep its position
+    /// after `id_count` shrinks. Every particle is removed if there are no ids left
+    pub fn wrap_ids(&mut self) {
+        if self.id_count == 0 {
+            self.retain_particles(|_| false);
+            return;
+        }
+        for particle in &mut self.current_particles {
+            particle.id %= self.id_count;
+        }
+        for particle in &mut self.previous_particles {
+            particle.id %= self.id_count;
+        }
+    }
+
+    /// Replaces the particle types, the number of types is the number of colors.
+    /// Every particle keeps its position, particles with an id that doesnt exist anymore get the id it wraps around to,
+    /// and the per id values are reset when the number of ids changes
+    pub fn set_types(&mut self, attraction_matrix: Vec<f32>, colors: Vec<cgmath::Vector3<f32>>) {
+        assert_eq!(attraction_matrix.len(), colors.len() * colors.len());
+        let id_count = colors.len() as u32;
+        if id_count != self.id_count {
+            self.masses = None;
+            self.per_type_friction = None;
+            self.per_type_force_scale = None;
+            self.per_type_radius = None;
+        }
+        self.id_count = id_count;
+        self.attraction_matrix = attraction_matrix;
+        self.colors = colors;
+        self.wrap_ids();
+    }
+
+    /// Removes every particle that `keep` returns false for,
+    /// the particles from the last update and their accelerations are removed with them so they still line up
+    pub fn retain_particles(&mut self, mut keep: impl FnMut(&Particle) -> bool) {
+        let kept: Vec<bool> = self.current_particles.iter().map(&mut keep).collect();
+        let mut kept_iter = kept.iter();
+        self.current_particles
+            .retain(|_| *kept_iter.next().unwrap());
+        if self.previous_particles.len() == kept.len() {
+            let mut kept_iter = kept.iter();
+            self.previous_particles
+                .retain(|_| *kept_iter.next().unwrap());
+        } else {
+            self.previous_particles.clear();
+        }
+        if self.accelerations.len() == kept.len() {
+            let mut kept_iter = kept.iter();
+            self.accelerations.retain(|_| *kept_iter.next().unwrap());
+        } else {
+            self.accelerations.clear();
+        }
+    }
+
+    /// Removes the particle id `id` along with every particle that has it,
+    /// particles with a bigger id are moved down by 1 to fill the gap
+    pub fn remove_type(&mut self, id: u32) {
+        assert!(id < self.id_count);
+        let n = self.id_count as usize;
+        let removed = id as usize;
+        self.attraction_matrix = (0..n)
+            .filter(|&i| i != removed)
+            .flat_map(|i| (0..n).filter(|&j| j != removed).map(move |j| i * n + j))
+            .map(|index| self.attraction_matrix[index])
+            .collect();
+        self.colors.remove(removed);
+        if let Some(masses) = &mut self.masses {
+            masses.remove(removed);
+        }
+        if let Some(per_type_friction) = &mut self.per_type_friction {
+            per_type_friction.remove(removed);
+        }
+        if let Some(per_type_force_scale) = &mut self.per_type_force_scale {
+            per_type_force_scale.remove(removed);
+        }
+        if let Some(per_type_radius) = &mut self.per_type_radius {
+            per_type_radius.remove(removed);
+        }
+        self.id_count -= 1;
 
-            for i in 1..hash_table.len() {
-                hash_table[i].fetch_add(hash_table[i - 1].load(Relaxed), Relaxed);
+        self.current_particles.retain(|particle| particle.id != id);
+        for particle in &mut self.current_particles {
+            if particle.id > id {
+                particle.id -= 1;
             }
+        }
+        // the particles no longer line up with the ones from the last update
+        self.previous_particles.clear();
+        self.accelerations.clear();
+    }
 
-            let particle_indices: Vec<_> = std::iter::repeat_with(|| AtomicUsize::new(0))
-                .take(self.current_particles.len())
-                .collect();
-            self.current_particles
-                .par_iter()
-                .enumerate()
-                .for_each(|(i, sphere)| {
-                    let index = hash(cell_coord(sphere.position)) % hash_table_length;
-                    let index = hash_table[index].fetch_sub(1, Relaxed);
-                    particle_indices[index - 1].store(i, Relaxed);
-                });
+    /// Creates a particle with a random position inside the world, a random id, and no velocity
+    pub fn random_particle(&self, rng: &mut impl Rng) -> Particle {
+        let mut position = self
+            .world_size
+            .map(|world_size| rng.gen_range(world_size * -0.5..=world_size * 0.5));
+        if self.dimensions == Dimensions::Two {
+            position.z = 0.0;
+        }
+        Particle {
+            position,
+            velocity: cgmath::vec3(0.0, 0.0, 0.0),
+            id: rng.gen_range(0..self.id_count),
+        }
+    }
 
-            std::mem::swap(&mut self.current_particles, &mut self.previous_particles);
-            self.current_particles.clear();
-            self.current_particles
-                .par_extend(self.previous_particles.par_iter().map(|&(mut particle)| {
-                    let mut total_force = cgmath::Vector3::zero();
-                    for x_offset in -1..=1 {
-                        for y_offset in -1..=1 {
-                            for z_offset in -1..=1 {
-                                let offset =
-                                    cgmath::vec3(x_offset as _, y_offset as _, z_offset as _)
-                                        * self.world_size;
-                                let cell = cell_coord(particle.position + offset);
-
-                                for x_cell_offset in -1isize..=1 {
-                                    for y_cell_offset in -1isize..=1 {
-                                        for z_cell_offset in -1isize..=1 {
-                                            let cell = cell
-                                                + cgmath::vec3(
-                                                    x_cell_offset,
-                                                    y_cell_offset,
-                                                    z_cell_offset,
-                                                );
-
-                                            let index = hash(cell) % hash_table_length;
-                                            for index in &particle_indices[hash_table[index]
-                                                .load(Relaxed)
-                                                ..hash_table[index + 1].load(Relaxed)]
-                                            {
-                                                let other_particle =
-                                                    &self.previous_particles[index.load(Relaxed)];
-
-                                                let relative_position = other_particle.position
-                                                    - (particle.position + offset);
-                                                let sqr_distance = relative_position.magnitude2();
-                                                if sqr_distance > 0.0
-                                                    && sqr_distance
-                                                        < self.particle_effect_radius
-                                                            * self.particle_effect_radius
-                                                {
-                                                    let distance = sqr_distance.sqrt();
-                                                    let force =
-                                                        |distance: f32, attraction: f32| -> f32 {
-                                                            if distance
-                                                                < self.min_attraction_percentage
-                                                            {
-                                                                distance
-                                                                    / self.min_attraction_percentage
-                                                                    - 1.0
-                                                            } else if self.min_attraction_percentage
-                                                                < distance
-                                                                && distance < 1.0
-                                                            {
-                                                                attraction
-                                                                * (1.0 - (2.0 * distance
-                                                                    - 1.0
-                                                                    - self
-                                                                        .min_attraction_percentage)
-                                                                    .abs()
-                                                                    / (1.0 - self
-                                                                        .min_attraction_percentage))
-                                                            } else {
-                                                                0.0
-                                                            }
-                                                        };
-                                                    let f = force(
-                                                        distance,
-                                                        self.attraction_matrix[(particle.id
-                                                            * self.id_count
-                                                            + other_particle.id)
-                                                            as usize],
-                                                    );
-                                                    total_force += relative_position / distance * f;
-                                                }
-                                            }
-                                        }
-                                    }
+    pub fn update(&mut self, ts: f32) {
+        // Apply forces
+        {
+            for axis in 0..3 {
+                assert!(self.world_size[axis] >= 2.0 * self.particle_effect_radius);
+            }
+            assert!(self.cell_size > 0.0);
+
+            match self.accel_structure {
+                AccelStructure::SpatialHash | AccelStructure::MortonHash => {
+                    self.update_spatial_hash(ts)
+                }
+                AccelStructure::UniformGrid => self.update_uniform_grid(ts),
+            }
+        }
+    }
+
+    fn update_spatial_hash(&mut self, ts: f32) {
+        let cell_range = (self.particle_effect_radius / self.cell_size).ceil() as isize;
+        let z_cell_range = self.z_cell_range(cell_range);
+        let mut spatial_hash = std::mem::take(&mut self.spatial_hash);
+        spatial_hash.build(self, &self.current_particles);
+        let pair_count = AtomicUsize::new(0);
+
+        self.step(ts, |particles, particle| {
+            let mut total_force = particles.force_accumulator(particle.id);
+            'search: for offset in particles.periodic_offsets() {
+                let cell = particles.cell_coord(particle.position + offset);
+
+                for x_cell_offset in -cell_range..=cell_range {
+                    for y_cell_offset in -cell_range..=cell_range {
+                        for z_cell_offset in -z_cell_range..=z_cell_range {
+                            let cell =
+                                cell + cgmath::vec3(x_cell_offset, y_cell_offset, z_cell_offset);
+
+                            for index in spatial_hash.cell_particles(cell) {
+                                if total_force.is_full() {
+                                    break 'search;
                                 }
+                                let other_particle = &particles.previous_particles[index];
+
+                                // Different cells can hash to the same bucket
+                                if particles.cell_coord(other_particle.position) != cell {
+                                    total_force.skip();
+                                    continue;
+                                }
+
+                                total_force.push(
+                                    other_particle.position - (particle.position + offset),
+                                    particles.attraction(particle.id, other_particle.id),
+                                );
                             }
                         }
                     }
+                }
+            }
 
-                    // Update velocity
-                    {
-                        particle.velocity +=
-                            total_force * self.force_scale * self.particle_effect_radius * ts;
+            pair_count.fetch_add(total_force.pair_count(), Relaxed);
+            total_force.finish()
+        });
+        self.spatial_hash = spatial_hash;
+        self.last_step_pair_count = pair_count.into_inner();
+    }
 
-                        particle.velocity += self.gravity * ts;
+    fn update_uniform_grid(&mut self, ts: f32) {
+        let mut uniform_grid = std::mem::take(&mut self.uniform_grid);
+        uniform_grid.build(self, &self.current_particles);
+        // the cells are rounded to fit the world, so they can be a different size along each axis
+        let cell_range = uniform_grid
+            .cell_size
+            .map(|cell_size| (self.particle_effect_radius / cell_size).ceil() as isize);
+        let z_cell_range = self.z_cell_range(cell_range[2]);
+        let pair_count = AtomicUsize::new(0);
 
-                        let velocity_change = particle.velocity * self.friction * ts;
-                        if velocity_change.magnitude2() > particle.velocity.magnitude2() {
-                            particle.velocity = cgmath::vec3(0.0, 0.0, 0.0);
-                        } else {
-                            particle.velocity -= velocity_change;
-                        }
-                    }
+        self.step(ts, |particles, particle| {
+            let cells_per_axis = uniform_grid.cells_per_axis.map(|cells| cells as isize);
+            let cell = uniform_grid.cell_coord(particles, particle.position);
 
-                    // Update position
-                    {
-                        particle.position += particle.velocity * ts;
-                        if particle.position.x > self.world_size * 0.5 {
-                            if self.solid_walls {
-                                particle.position.x = self.world_size * 0.5;
-                                particle.velocity.x = particle.velocity.y.min(0.0);
-                            } else {
-                                particle.position.x -= self.world_size;
-                            }
-                        }
-                        if particle.position.x < -self.world_size * 0.5 {
-                            if self.solid_walls {
-                                particle.position.x = -self.world_size * 0.5;
-                                particle.velocity.x = particle.velocity.x.max(0.0);
-                            } else {
-                                particle.position.x += self.world_size;
-                            }
-                        }
-                        if particle.position.y > self.world_size * 0.5 {
-                            if self.solid_walls {
-                                particle.position.y = self.world_size * 0.5;
-                                particle.velocity.y = particle.velocity.x.min(0.0);
-                            } else {
-                                particle.position.y -= self.world_size;
-                            }
-                        }
-                        if particle.position.y < -self.world_size * 0.5 {
-                            if self.solid_walls {
-                                particle.position.y = -self.world_size * 0.5;
-                                particle.velocity.y = particle.velocity.y.max(0.0);
-                            } else {
-                                particle.position.y += self.world_size;
-                            }
-                        }
-                        if particle.position.z > self.world_size * 0.5 {
-                            if self.solid_walls {
-                                particle.position.z = self.world_size * 0.5;
-                                particle.velocity.z = particle.velocity.z.min(0.0);
-                            } else {
-                                particle.position.z -= self.world_size;
+            let mut total_force = particles.force_accumulator(particle.id);
+            'search: for x_cell_offset in -cell_range[0]..=cell_range[0] {
+                for y_cell_offset in -cell_range[1]..=cell_range[1] {
+                    'cells: for z_cell_offset in -z_cell_range..=z_cell_range {
+                        let cell_offset = cgmath::vec3(x_cell_offset, y_cell_offset, z_cell_offset);
+
+                        // Wrap the cell around periodic axes, moving the particle to the copy of the world next to the cell
+                        let mut other_cell = cgmath::vec3(0, 0, 0);
+                        let mut offset = cgmath::Vector3::zero();
+                        for axis in 0..3 {
+                            let coord = cell[axis] + cell_offset[axis];
+                            match particles.boundaries[axis] {
+                                BoundaryMode::Periodic => {
+                                    other_cell[axis] = coord.rem_euclid(cells_per_axis[axis]);
+                                    offset[axis] = -coord.div_euclid(cells_per_axis[axis]) as f32
+                                        * particles.world_size[axis];
+                                }
+                                BoundaryMode::Solid => {
+                                    if !(0..cells_per_axis[axis]).contains(&coord) {
+                                        continue 'cells;
+                                    }
+                                    other_cell[axis] = coord;
+                                }
                             }
                         }
-                        if particle.position.z < -self.world_size * 0.5 {
-                            if self.solid_walls {
-                                particle.position.z = -self.world_size * 0.5;
-                                particle.velocity.z = particle.velocity.z.max(0.0);
-                            } else {
-                                particle.position.z += self.world_size;
+
+                        for index in uniform_grid.cell_particles(other_cell) {
+                            if total_force.is_full() {
+                                break 'search;
                             }
+                            let other_particle = &particles.previous_particles[index];
+                            total_force.push(
+                                other_particle.position - (particle.position + offset),
+                                particles.attraction(particle.id, other_particle.id),
+                            );
                         }
                     }
+                }
+            }
+
+            pair_count.fetch_add(total_force.pair_count(), Relaxed);
+            total_force.finish()
+        });
+        self.uniform_grid = uniform_grid;
+        self.last_step_pair_count = pair_count.into_inner();
+    }
+
+    /// Calls `update` `steps` times, returning how long they took altogether
+    pub fn update_n(&mut self, ts: f32, steps: usize) -> std::time::Duration {
+        let start = std::time::Instant::now();
+        for _ in 0..steps {
+            self.update(ts);
+        }
+        start.elapsed()
+    }
+
+    /// Updates the particles by checking every pair of particles instead of using the spatial hash,
+    /// this is much slower but is useful as a reference to compare `update` against
+    pub fn update_brute_force(&mut self, ts: f32) {
+        let pair_count = AtomicUsize::new(0);
+        self.step(ts, |particles, particle| {
+            let mut total_force = cgmath::Vector3::zero();
+            for offset in particles.periodic_offsets() {
+                for other_particle in &particles.previous_particles {
+                    if particles.in_effect_radius(
+                        other_particle.position - (particle.position + offset),
+                        particle.id,
+                    ) {
+                        pair_count.fetch_add(1, Relaxed);
+                    }
+                    total_force += particles.pair_force(particle, other_particle, offset);
+                }
+            }
+            total_force
+        });
+        self.last_step_pair_count = pair_count.into_inner();
+    }
+
+    /// Moves the current particles into `previous_particles` and integrates each of them using the force returned by `total_force`
+    fn step(
+        &mut self,
+        ts: f32,
+        total_force: impl Fn(&Particles, &Particle) -> cgmath::Vector3<f32> + Sync,
+    ) {
+        std::mem::swap(&mut self.current_particles, &mut self.previous_particles);
+        let mut current_particles = std::mem::take(&mut self.current_particles);
+        let previous_accelerations = std::mem::take(&mut self.accelerations);
+        let mut accelerations = Vec::with_capacity(self.previous_particles.len());
+        let wall_hits = AtomicUsize::new(0);
+        self.previous_particles
+            .par_iter()
+            .enumerate()
+            .map(|(i, particle)| {
+                // the accelerations are out of date if particles have been added or removed
+                let previous_acceleration = (previous_accelerations.len()
+                    == self.previous_particles.len())
+                .then(|| previous_accelerations[i]);
+                self.integrate(
+                    *particle,
+                    total_force(self, particle),
+                    previous_acceleration,
+                    ts,
+                    &wall_hits,
+                )
+            })
+            .unzip_into_vecs(&mut current_particles, &mut accelerations);
+        self.current_particles = current_particles;
+        self.accelerations = accelerations;
+        self.last_step_wall_hits = wall_hits.into_inner();
+        self.apply_thermostat(ts);
+    }
+
+    /// Rescales every velocity so the mean speed moves towards `target_speed`, this is a Berendsen thermostat using speed instead of temperature.
+    /// This is called at the end of every update
+    pub fn apply_thermostat(&mut self, ts: f32) {
+        let Some(target_speed) = self.target_speed else {
+            return;
+        };
+        if self.current_particles.is_empty() {
+            return;
+        }
+
+        let speed = |particle: &Particle| particle.velocity.magnitude();
+        // a parallel sum adds the speeds in an order that depends on the threads
+        let total_speed: f32 = if self.deterministic {
+            self.current_particles.iter().map(speed).sum()
+        } else {
+            self.current_particles.par_iter().map(speed).sum()
+        };
+        let mean_speed = total_speed / self.current_particles.len() as f32;
+        // particles that are all stopped have no direction to speed up in
+        if mean_speed <= 0.0 {
+            return;
+        }
+
+        let coupling = (self.thermostat_coupling * ts).min(1.0);
+        let scale = 1.0 + coupling * (target_speed / mean_speed - 1.0);
+        self.current_particles
+            .par_iter_mut()
+            .for_each(|particle| particle.velocity *= scale);
+    }
+
+    /// The number of other particles within `particle_effect_radius` of the particle at `index`, including across periodic edges
+    pub fn neighbour_count(&self, index: usize) -> usize {
+        let particle = &self.current_particles[index];
+        let sqr_radius = self.particle_effect_radius * self.particle_effect_radius;
+        self.current_particles
+            .par_iter()
+            .enumerate()
+            .filter(|&(other_index, other_particle)| {
+                other_index != index
+                    && self.periodic_offsets().any(|offset| {
+                        (other_particle.position - (particle.position + offset)).magnitude2()
+                            < sqr_radius
+                    })
+            })
+            .count()
+    }
 
-                    particle
-                }));
+    /// The radial distribution function of the particles, split into `bins` evenly sized bins from 0 to `max_r`.
+    /// Each bin is the number of particles at that distance from a particle compared to if the particles were spread out evenly,
+    /// so bins above 1 are distances that particles prefer to be from each other.
+    /// Solid boundaries have nothing past them, so the bins fall below 1 when `max_r` is a large part of the world.
+    /// `max_r` is clamped to half of `min_world_size`, past that pairs would be counted again across periodic edges
+    pub fn pair_correlation(&self, bins: usize, max_r: f32) -> Vec<f32> {
+        let max_r = max_r.min(self.min_world_size() * 0.5);
+        let particle_count = self.current_particles.len();
+        if bins == 0 || particle_count == 0 || max_r <= 0.0 {
+            return vec![0.0; bins];
         }
+
+        let spatial_hash = SpatialHash::new(self, &self.current_particles);
+        let cell_range = (max_r / self.cell_size).ceil() as isize;
+        let z_cell_range = self.z_cell_range(cell_range);
+        let bin_size = max_r / bins as f32;
+
+        let counts = self
+            .current_particles
+            .par_iter()
+            .fold(
+                || vec![0u64; bins],
+                |mut counts, particle| {
+                    for offset in self.periodic_offsets() {
+                        let cell = self.cell_coord(particle.position + offset);
+                        for x_cell_offset in -cell_range..=cell_range {
+                            for y_cell_offset in -cell_range..=cell_range {
+                                for z_cell_offset in -z_cell_range..=z_cell_range {
+                                    let cell = cell
+                                        + cgmath::vec3(x_cell_offset, y_cell_offset, z_cell_offset);
+                                    for index in spatial_hash.cell_particles(cell) {
+                                        let other_particle = &self.current_particles[index];
+
+                                        // Different cells can hash to the same bucket
+                                        if self.cell_coord(other_particle.position) != cell {
+                                            continue;
+                                        }
+
+                                        let distance = (other_particle.position
+                                            - (particle.position + offset))
+                                            .magnitude();
+                                        if distance > 0.0 && distance < max_r {
+                                            counts
+                                                [((distance / bin_size) as usize).min(bins - 1)] +=
+                                                1;
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    counts
+                },
+            )
+            .reduce(
+                || vec![0u64; bins],
+                |mut a, b| {
+                    a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+                    a
+                },
+            );
+
+        // the number of particles expected in each bin if they were spread out evenly
+        let (density, shell_size): (f32, fn(f32, f32) -> f32) = match self.dimensions {
+            Dimensions::Two => (
+                particle_count as f32 / (self.world_size.x * self.world_size.y),
+                |inner, outer| std::f32::consts::PI * (outer.powi(2) - inner.powi(2)),
+            ),
+            Dimensions::Three => (
+                particle_count as f32 / self.world_size.product(),
+                |inner, outer| 4.0 / 3.0 * std::f32::consts::PI * (outer.powi(3) - inner.powi(3)),
+            ),
+        };
+        counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| {
+                let expected = particle_count as f32
+                    * density
+                    * shell_size(i as f32 * bin_size, (i + 1) as f32 * bin_size);
+                count as f32 / expected
+            })
+            .collect()
+    }
+
+    /// The bounds of every cell of the acceleration structure that has a particle in it, as `(min, max)` corners.
+    /// The spatial hash cells round towards 0, so the cells at 0 along each axis are twice as big as the others
+    pub fn occupied_cells(&self) -> Vec<(cgmath::Vector3<f32>, cgmath::Vector3<f32>)> {
+        match self.accel_structure {
+            AccelStructure::SpatialHash | AccelStructure::MortonHash => {
+                let mut cells: Vec<_> = self
+                    .current_particles
+                    .iter()
+                    .map(|particle| {
+                        let cell = self.cell_coord(particle.position);
+                        (cell.x, cell.y, cell.z)
+                    })
+                    .collect();
+                cells.sort_unstable();
+                cells.dedup();
+                let s = self.cell_size;
+                cells
+                    .into_iter()
+                    .map(|(x, y, z)| {
+                        let cell = cgmath::vec3(x as f32, y as f32, z as f32);
+                        (
+                            cell.map(|c| if c > 0.0 { c * s } else { (c - 1.0) * s }),
+                            cell.map(|c| if c < 0.0 { c * s } else { (c + 1.0) * s }),
+                        )
+                    })
+                    .collect()
+            }
+            AccelStructure::UniformGrid => {
+                let mut uniform_grid = UniformGrid::default();
+                uniform_grid.resize(self);
+                let mut cells: Vec<_> = self
+                    .current_particles
+                    .iter()
+                    .map(|particle| {
+                        let cell = uniform_grid.cell_coord(self, particle.position);
+                        (cell.x, cell.y, cell.z)
+                    })
+                    .collect();
+                cells.sort_unstable();
+                cells.dedup();
+                let cell_size = cgmath::Vector3::from(uniform_grid.cell_size);
+                cells
+                    .into_iter()
+                    .map(|(x, y, z)| {
+                        let min = cgmath::vec3(x as f32, y as f32, z as f32)
+                            .mul_element_wise(cell_size)
+                            - self.world_size * 0.5;
+                        (min, min + cell_size)
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// The smallest size of the world along the axes that particles move along, in 2d the z axis is ignored
+    pub fn min_world_size(&self) -> f32 {
+        let axes = match self.dimensions {
+            Dimensions::Two => 2,
+            Dimensions::Three => 3,
+        };
+        (0..axes)
+            .map(|axis| self.world_size[axis])
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    /// Writes the header row for the csv written by `append_csv`
+    pub fn write_csv_header<W: Write>(writer: &mut W) -> std::io::Result<()> {
+        writeln!(writer, "frame,index,id,x,y,z,vx,vy,vz")
+    }
+
+    /// Writes a csv row for every particle, each row has the columns `frame,index,id,x,y,z,vx,vy,vz`
+    pub fn append_csv<W: Write>(&self, writer: &mut W, frame: u64) -> std::io::Result<()> {
+        let mut writer = std::io::BufWriter::new(writer);
+        for (index, particle) in self.current_particles.iter().enumerate() {
+            let Particle {
+                position,
+                velocity,
+                id,
+            } = particle;
+            writeln!(
+                writer,
+                "{frame},{index},{id},{},{},{},{},{},{}",
+                position.x, position.y, position.z, velocity.x, velocity.y, velocity.z
+            )?;
+        }
+        writer.flush()
+    }
+
+    /// A hash of the bits of every position, velocity, and id in `current_particles`, in order.
+    /// This only changes when the particles change, so it can be compared between runs to check that they gave exactly the same result
+    pub fn state_hash(&self) -> u64 {
+        // FNV-1a, which is simple enough to stay the same on every platform and rust version
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0000_0100_0000_01b3;
+        self.current_particles
+            .iter()
+            .flat_map(|particle| {
+                let [px, py, pz]: [f32; 3] = particle.position.into();
+                let [vx, vy, vz]: [f32; 3] = particle.velocity.into();
+                [px, py, pz, vx, vy, vz]
+                    .map(f32::to_bits)
+                    .into_iter()
+                    .chain(std::iter::once(particle.id))
+            })
+            .flat_map(u32::to_le_bytes)
+            .fold(OFFSET_BASIS, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(PRIME)
+            })
+    }
+
+    /// Replaces every particle with the ones in the csv file at `path`, see `read_positions` for the format
+    pub fn load_positions(&mut self, path: &Path) -> std::io::Result<()> {
+        let file = std::fs::File::open(path)?;
+        self.read_positions(std::io::BufReader::new(file))
+    }
+
+    /// Replaces every particle with the ones in a csv with a header row, like the one written by `append_csv`.
+    /// The `id`, `x`, `y`, and `z` columns are required, `vx`, `vy`, and `vz` are optional and any other columns are ignored.
+    /// When there is a `frame` column only the rows from the last frame are used.
+    /// Positions outside the world are wrapped or clamped into it, and nothing is changed if any row is invalid
+    pub fn read_positions<R: BufRead>(&mut self, reader: R) -> std::io::Result<()> {
+        let invalid_data =
+            |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+
+        let mut lines = reader.lines();
+        let header = lines
+            .next()
+            .transpose()?
+            .ok_or_else(|| invalid_data("there is no header row".into()))?;
+        let columns: Vec<&str> = header.split(',').map(str::trim).collect();
+        let column = |name: &str| columns.iter().position(|&column| column == name);
+        let required_column = |name: &str| {
+            column(name).ok_or_else(|| invalid_data(format!("there is no {name} column")))
+        };
+        let id_column = required_column("id")?;
+        let position_columns = [
+            required_column("x")?,
+            required_column("y")?,
+            required_column("z")?,
+        ];
+        let velocity_columns = [column("vx"), column("vy"), column("vz")];
+        let frame_column = column("frame");
+
+        let mut last_frame = None;
+        let mut particles = vec![];
+        for (index, line) in lines.enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            // the header is line 1
+            let line_number = index + 2;
+            let values: Vec<&str> = line.split(',').map(str::trim).collect();
+            let value = |column: usize| {
+                values.get(column).copied().ok_or_else(|| {
+                    invalid_data(format!(
+                        "line {line_number} has {} columns but the header has {}",
+                        values.len(),
+                        columns.len()
+                    ))
+                })
+            };
+            let parse_error = |error: &dyn std::fmt::Display| {
+                invalid_data(format!("line {line_number}: {error}"))
+            };
+
+            if let Some(frame_column) = frame_column {
+                let frame: u64 = value(frame_column)?
+                    .parse()
+                    .map_err(|error| parse_error(&error))?;
+                match last_frame {
+                    Some(last_frame) if frame < last_frame => continue,
+                    Some(last_frame) if frame == last_frame => {}
+                    _ => {
+                        particles.clear();
+                        last_frame = Some(frame);
+                    }
+                }
+            }
+
+            let id: u32 = value(id_column)?
+                .parse()
+                .map_err(|error| parse_error(&error))?;
+            if id >= self.id_count {
+                return Err(invalid_data(format!(
+                    "line {line_number} has id {id} but there are only {} ids",
+                    self.id_count
+                )));
+            }
+            let mut position = cgmath::Vector3::zero();
+            let mut velocity = cgmath::Vector3::zero();
+            for axis in 0..3 {
+                position[axis] = value(position_columns[axis])?
+                    .parse()
+                    .map_err(|error| parse_error(&error))?;
+                if let Some(velocity_column) = velocity_columns[axis] {
+                    velocity[axis] = value(velocity_column)?
+                        .parse()
+                        .map_err(|error| parse_error(&error))?;
+                }
+            }
+            let mut position = self.wrap_position(position);
+            if self.dimensions == Dimensions::Two {
+                position.z = 0.0;
+                velocity.z = 0.0;
+            }
+            particles.push(Particle {
+                position,
+                velocity,
+                id,
+            });
+        }
+
+        self.current_particles = particles;
+        self.previous_particles.clear();
+        self.accelerations.clear();
+        Ok(())
+    }
+
+    /// The sum of `0.5 * m * |v|²` over every particle
+    pub fn total_kinetic_energy(&self) -> f32 {
+        self.current_particles
+            .par_iter()
+            .map(|particle| 0.5 * self.mass(particle.id) * particle.velocity.magnitude2())
+            .sum()
+    }
+
+    /// The sum of `m * v` over every particle
+    pub fn total_momentum(&self) -> cgmath::Vector3<f32> {
+        self.current_particles
+            .iter()
+            .fold(cgmath::Vector3::zero(), |momentum, particle| {
+                momentum + particle.velocity * self.mass(particle.id)
+            })
+    }
+
+    /// The number of particles with each id, indexed by id
+    pub fn count_by_type(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.id_count as usize];
+        for particle in &self.current_particles {
+            counts[particle.id as usize] += 1;
+        }
+        counts
+    }
+
+    /// Iterates over the current particles with the id `id`
+    pub fn iter_type(&self, id: u32) -> impl Iterator<Item = &Particle> {
+        self.current_particles
+            .iter()
+            .filter(move |particle| particle.id == id)
+    }
+
+    /// The positions of the current particles with the id `id`
+    pub fn positions_of_type(&self, id: u32) -> Vec<cgmath::Vector3<f32>> {
+        self.iter_type(id)
+            .map(|particle| particle.position)
+            .collect()
+    }
+
+    /// The mass weighted average position of every particle
+    pub fn center_of_mass(&self) -> cgmath::Vector3<f32> {
+        let (weighted_sum, total_mass) = self.current_particles.iter().fold(
+            (cgmath::Vector3::zero(), 0.0),
+            |(weighted_sum, total_mass), particle| {
+                let mass = self.mass(particle.id);
+                (weighted_sum + particle.position * mass, total_mass + mass)
+            },
+        );
+        if total_mass > 0.0 {
+            weighted_sum / total_mass
+        } else {
+            cgmath::Vector3::zero()
+        }
+    }
+
+    /// The mass of particles with the id `id`
+    pub fn mass(&self, id: u32) -> f32 {
+        self.masses
+            .as_ref()
+            .map_or(1.0, |masses| masses[id as usize])
+    }
+
+    /// The force scale of particles with the id `id`, including the global `force_scale`
+    pub fn force_scale(&self, id: u32) -> f32 {
+        self.per_type_force_scale
+            .as_ref()
+            .map_or(1.0, |force_scale| force_scale[id as usize])
+            * self.force_scale
+    }
+
+    /// Changes the world size to `new_size` and scales every position along each axis by how much that axis changed,
+    /// so particles keep their place relative to the world
+    pub fn rescale_world(&mut self, new_size: cgmath::Vector3<f32>) {
+        let scale = new_size.div_element_wise(self.world_size);
+        for particle in self
+            .current_particles
+            .iter_mut()
+            .chain(&mut self.previous_particles)
+        {
+            particle.position.mul_assign_element_wise(scale);
+        }
+        self.world_size = new_size;
+    }
+
+    /// Reorders the particles along a Z-order curve through the cells of the world, so particles that are close together
+    /// are also close together in memory and the force loop reads memory in a more cache friendly order.
+    /// `previous_particles` and `accelerations` are reordered the same way, and the index each particle had before the sort is returned
+    pub fn spatial_sort(&mut self) -> Vec<usize> {
+        let mut keys: Vec<(u64, usize)> = self
+            .current_particles
+            .par_iter()
+            .enumerate()
+            .map(|(index, particle)| (self.spatial_sort_key(particle.position), index))
+            .collect();
+        keys.par_sort_unstable();
+        let order: Vec<usize> = keys.into_iter().map(|(_, index)| index).collect();
+
+        fn reorder<T: Copy>(values: &mut Vec<T>, order: &[usize]) {
+            *values = order.iter().map(|&index| values[index]).collect();
+        }
+        reorder(&mut self.current_particles, &order);
+        if self.previous_particles.len() == order.len() {
+            reorder(&mut self.previous_particles, &order);
+        }
+        if self.accelerations.len() == order.len() {
+            reorder(&mut self.accelerations, &order);
+        }
+        order
+    }
+
+    /// The Morton code of the cell containing `position`, counting cells from the lowest corner of the world
+    fn spatial_sort_key(&self, position: cgmath::Vector3<f32>) -> u64 {
+        // the cast saturates, so positions outside the world go in the first or last cell
+        morton_code([0, 1, 2].map(|axis| {
+            (((position[axis] + self.world_size[axis] * 0.5) / self.cell_size) as u64)
+                .min(0x1f_ffff)
+        }))
+    }
+
+    /// Moves every particle so that the center of mass is at the origin,
+    /// particles that get moved outside the world are wrapped around or clamped to the walls
+    pub fn recenter(&mut self) {
+        let center_of_mass = self.center_of_mass();
+        let mut particles = std::mem::take(&mut self.current_particles);
+        for particle in &mut particles {
+            particle.position = self.wrap_position(particle.position - center_of_mass);
+        }
+        self.current_particles = particles;
+    }
+
+    /// Moves a position that is outside the world back inside it,
+    /// wrapping it around periodic axes and clamping it to the walls of solid axes
+    pub fn wrap_position(&self, mut position: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+        for axis in 0..3 {
+            let world_size = self.world_size[axis];
+            match self.boundaries[axis] {
+                BoundaryMode::Periodic => {
+                    position[axis] -= (position[axis] / world_size).round() * world_size;
+                }
+                BoundaryMode::Solid => {
+                    position[axis] = position[axis].clamp(-world_size * 0.5, world_size * 0.5);
+                }
+            }
+        }
+        position
+    }
+
+    /// Stops every particle, the simulation carries on from rest on the next update.
+    /// `previous_particles` and the stored accelerations are reset too, so the next update starts fresh instead of
+    /// continuing the motion from before
+    pub fn zero_velocities(&mut self) {
+        for particle in self
+            .current_particles
+            .iter_mut()
+            .chain(&mut self.previous_particles)
+        {
+            particle.velocity = cgmath::Vector3::zero();
+        }
+        self.accelerations.clear();
+    }
+
+    /// Returns the indices of every particle within `radius` of `center`,
+    /// this also finds particles across the edges of the world along periodic axes
+    pub fn query_radius(&self, center: cgmath::Vector3<f32>, radius: f32) -> Vec<usize> {
+        let mut indices = vec![];
+        if self.current_particles.is_empty() {
+            return indices;
+        }
+
+        let cell_range = (radius / self.cell_size).ceil();
+        // when there are at least as many cells to search as particles, checking every particle directly is faster.
+        // a `radius` bigger than the world only ends up here if there are few enough particles,
+        // otherwise the cells are searched and the particles found more than once are removed below
+        if (2.0 * cell_range + 1.0).powi(3) >= self.current_particles.len() as f32 {
+            indices.extend(
+                self.current_particles
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, particle)| {
+                        self.periodic_offsets().any(|offset| {
+                            (particle.position - (center + offset)).magnitude2() <= radius * radius
+                        })
+                    })
+                    .map(|(index, _)| index),
+            );
+            return indices;
+        }
+
+        let cell_range = cell_range as isize;
+        let spatial_hash = SpatialHash::new(self, &self.current_particles);
+        for offset in self.periodic_offsets() {
+            let cell = self.cell_coord(center + offset);
+
+            for x_cell_offset in -cell_range..=cell_range {
+                for y_cell_offset in -cell_range..=cell_range {
+                    for z_cell_offset in -cell_range..=cell_range {
+                        let cell = cell + cgmath::vec3(x_cell_offset, y_cell_offset, z_cell_offset);
+                        for index in spatial_hash.cell_particles(cell) {
+                            let position = self.current_particles[index].position;
+                            if self.cell_coord(position) == cell
+                                && (position - (center + offset)).magnitude2() <= radius * radius
+                            {
+                                indices.push(index);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // a particle can be found more than once when `radius` is big compared to the world
+        indices.sort_unstable();
+        indices.dedup();
+        indices
+    }
+
+    /// The offsets to the neighbouring copies of the world that particles can interact across,
+    /// this only includes copies along periodic axes, and never along z in 2d
+    fn periodic_offsets(&self) -> impl Iterator<Item = cgmath::Vector3<f32>> + '_ {
+        let offsets = |axis: usize| match self.boundaries[axis] {
+            BoundaryMode::Periodic if axis != 2 || self.dimensions == Dimensions::Three => -1..=1,
+            _ => 0..=0,
+        };
+        offsets(0).flat_map(move |x_offset| {
+            offsets(1).flat_map(move |y_offset| {
+                offsets(2).map(move |z_offset| {
+                    cgmath::vec3(
+                        x_offset as f32 * self.world_size.x,
+                        y_offset as f32 * self.world_size.y,
+                        z_offset as f32 * self.world_size.z,
+                    )
+                })
+            })
+        })
+    }
+
+    /// How many cells along the z axis `update` needs to search, in 2d every particle is in the same layer of cells
+    fn z_cell_range(&self, cell_range: isize) -> isize {
+        match self.dimensions {
+            Dimensions::Two => 0,
+            Dimensions::Three => cell_range,
+        }
+    }
+
+    fn cell_coord(&self, v: cgmath::Vector3<f32>) -> cgmath::Vector3<isize> {
+        cgmath::vec3(
+            (v.x / self.cell_size) as isize,
+            (v.y / self.cell_size) as isize,
+            (v.z / self.cell_size) as isize,
+        )
+    }
+
+    fn force(&self, distance: f32, attraction: f32) -> f32 {
+        self.force_profile
+            .force(distance, attraction, self.min_attraction_percentage)
+    }
+
+    /// How much particles with id `id` are attracted to particles with id `other_id`
+    fn attraction(&self, id: u32, other_id: u32) -> f32 {
+        self.attraction_matrix[(id * self.id_count + other_id) as usize]
+    }
+
+    /// The distance that particles with the id `id` feel other particles from
+    pub fn effect_radius(&self, id: u32) -> f32 {
+        self.per_type_radius
+            .as_ref()
+            .map_or(self.particle_effect_radius, |radius| {
+                // only cells within `particle_effect_radius` are searched
+                radius[id as usize].min(self.particle_effect_radius)
+            })
+    }
+
+    /// Whether the particles in each bucket have to be in a fixed order, which `max_neighbours` needs to keep the same neighbours
+    fn sorts_buckets(&self) -> bool {
+        self.deterministic || self.max_neighbours.is_some()
+    }
+
+    /// Collects the forces on a particle with the id `id`
+    fn force_accumulator(&self, id: u32) -> simd::ForceAccumulator {
+        simd::ForceAccumulator::new(
+            self.force_profile,
+            self.min_attraction_percentage,
+            self.effect_radius(id),
+            self.max_neighbours,
+        )
+    }
+
+    /// Whether a particle at `relative_position` from a particle with the id `id` is close enough to exert a force on it, but not at the same position
+    fn in_effect_radius(&self, relative_position: cgmath::Vector3<f32>, id: u32) -> bool {
+        let sqr_distance = relative_position.magnitude2();
+        let radius = self.effect_radius(id);
+        sqr_distance > 0.0 && sqr_distance < radius * radius
+    }
+
+    /// The force that `other_particle` exerts on `particle` when `particle` is moved by `offset`
+    fn pair_force(
+        &self,
+        particle: &Particle,
+        other_particle: &Particle,
+        offset: cgmath::Vector3<f32>,
+    ) -> cgmath::Vector3<f32> {
+        let relative_position = other_particle.position - (particle.position + offset);
+        if self.in_effect_radius(relative_position, particle.id) {
+            let distance = relative_position.magnitude();
+            let f = self.force(distance, self.attraction(particle.id, other_particle.id));
+            relative_position / distance * f
+        } else {
+            cgmath::Vector3::zero()
+        }
+    }
+
+    /// Updates the velocity and position of `particle`, returning the updated particle and its acceleration.
+    /// `wall_hits` is incremented if the particle hits a solid wall of the world
+    fn integrate(
+        &self,
+        mut particle: Particle,
+        total_force: cgmath::Vector3<f32>,
+        previous_acceleration: Option<cgmath::Vector3<f32>>,
+        ts: f32,
+        wall_hits: &AtomicUsize,
+    ) -> (Particle, cgmath::Vector3<f32>) {
+        let attractor_force = self
+            .attractors
+            .iter()
+            .map(|attractor| {
+                let relative_position = attractor.position - particle.position;
+                let sqr_distance = relative_position.magnitude2();
+                if sqr_distance > 0.0 && sqr_distance < attractor.radius * attractor.radius {
+                    let distance = sqr_distance.sqrt();
+                    relative_position / distance
+                        * attractor.strength
+                        * (1.0 - distance / attractor.radius)
+                } else {
+                    cgmath::Vector3::zero()
+                }
+            })
+            .sum::<cgmath::Vector3<f32>>();
+        let acceleration =
+            (total_force * self.force_scale(particle.id) * self.particle_effect_radius
+                + attractor_force)
+                / self.mass(particle.id)
+                + self.gravity.acceleration(particle.position);
+
+        // Update velocity
+        {
+            match self.integrator {
+                Integrator::SemiImplicitEuler => {
+                    particle.velocity += acceleration * ts;
+                }
+                Integrator::VelocityVerlet => {
+                    let previous_acceleration = previous_acceleration.unwrap_or(acceleration);
+                    particle.velocity += (previous_acceleration + acceleration) * 0.5 * ts;
+                }
+            }
+
+            let friction = self
+                .per_type_friction
+                .as_ref()
+                .map_or(self.friction, |friction| friction[particle.id as usize]);
+            // decaying exponentially instead of by `friction * ts` keeps the slowdown the same at any tick rate,
+            // and it can never overshoot and reverse the velocity at large time steps
+            particle.velocity *= (-friction * ts).exp();
+
+            if let Some(max_velocity) = self.max_velocity {
+                if particle.velocity.magnitude2() > max_velocity * max_velocity {
+                    particle.velocity = particle.velocity.normalize_to(max_velocity);
+                }
+            }
+        }
+
+        // Update position
+        {
+            particle.position += particle.velocity * ts;
+            if self.integrator == Integrator::VelocityVerlet {
+                particle.position += acceleration * 0.5 * ts * ts;
+            }
+            for &(center, radius) in &self.obstacles {
+                let relative_position = particle.position - center;
+                let sqr_distance = relative_position.magnitude2();
+                if sqr_distance < radius * radius {
+                    let normal = if sqr_distance > 0.0 {
+                        relative_position / sqr_distance.sqrt()
+                    } else {
+                        cgmath::vec3(0.0, 1.0, 0.0)
+                    };
+                    particle.position = center + normal * radius;
+                    let normal_speed = particle.velocity.dot(normal);
+                    if normal_speed < 0.0 {
+                        particle.velocity -= normal * normal_speed * (1.0 + self.wall_restitution);
+                    }
+                }
+            }
+            if self.dimensions == Dimensions::Two {
+                particle.position.z = 0.0;
+                particle.velocity.z = 0.0;
+            }
+            let mut hit_wall = false;
+            for axis in 0..3 {
+                let world_size = self.world_size[axis];
+                if particle.position[axis] > world_size * 0.5 {
+                    match self.boundaries[axis] {
+                        BoundaryMode::Periodic => {
+                            particle.position[axis] -= world_size;
+                        }
+                        BoundaryMode::Solid => {
+                            hit_wall = true;
+                            particle.position[axis] = world_size * 0.5;
+                            if particle.velocity[axis] > 0.0 {
+                                particle.velocity[axis] *= -self.wall_restitution;
+                            }
+                        }
+                    }
+                }
+                if particle.position[axis] < -world_size * 0.5 {
+                    match self.boundaries[axis] {
+                        BoundaryMode::Periodic => {
+                            particle.position[axis] += world_size;
+                        }
+                        BoundaryMode::Solid => {
+                            hit_wall = true;
+                            particle.position[axis] = -world_size * 0.5;
+                            if particle.velocity[axis] < 0.0 {
+                                particle.velocity[axis] *= -self.wall_restitution;
+                            }
+                        }
+                    }
+                }
+            }
+            if hit_wall {
+                wall_hits.fetch_add(1, Relaxed);
+            }
+        }
+
+        (particle, acceleration)
+    }
+}
+
+/// Buckets particles by the cell that they are in so that neighbouring particles can be found quickly
+#[derive(Default)]
+pub struct SpatialHash {
+    hash_table: Vec<AtomicUsize>,
+    particle_indices: Vec<AtomicUsize>,
+    /// Whether the buckets use `morton_hash` instead of `hash`, for `AccelStructure::MortonHash`
+    morton: bool,
+}
+
+impl Clone for SpatialHash {
+    fn clone(&self) -> Self {
+        // this only holds scratch space that gets rebuilt on every update, so there is nothing worth copying
+        Self::default()
+    }
+}
+
+impl SpatialHash {
+    /// Hashes a cell with fixed integer arithmetic so the buckets are the same on every platform and rust version,
+    /// this is the same hash that `particles_update.wgsl` uses
+    fn hash(cgmath::Vector3 { x, y, z }: cgmath::Vector3<isize>) -> usize {
+        ((x as u32).wrapping_mul(73856093)
+            ^ (y as u32).wrapping_mul(19349663)
+            ^ (z as u32).wrapping_mul(83492791)) as usize
+    }
+
+    /// Cells can be negative, so each coordinate is offset by this before it is Morton encoded.
+    /// This keeps the cells within 2^20 of the origin in the 21 bits that each axis gets
+    const MORTON_CELL_OFFSET: isize = 1 << 20;
+
+    /// The Morton code of a cell, cells further than `MORTON_CELL_OFFSET` from the origin wrap around and share a bucket with closer cells,
+    /// which is fine because the cell of every particle in a bucket is checked anyway
+    fn morton_hash(cell: cgmath::Vector3<isize>) -> usize {
+        morton_code([cell.x, cell.y, cell.z].map(|coord| (coord + Self::MORTON_CELL_OFFSET) as u64))
+            as usize
+    }
+
+    fn bucket(&self, cell: cgmath::Vector3<isize>) -> usize {
+        let hash = if self.morton {
+            Self::morton_hash(cell)
+        } else {
+            Self::hash(cell)
+        };
+        hash % (self.hash_table.len() - 1)
+    }
+
+    fn new(particles: &Particles, spheres: &[Particle]) -> Self {
+        let mut spatial_hash = Self::default();
+        spatial_hash.build(particles, spheres);
+        spatial_hash
+    }
+
+    /// Fills the hash with `spheres`, reusing the existing buffers when they are big enough
+    fn build(&mut self, particles: &Particles, spheres: &[Particle]) {
+        let hash_table_length = spheres.len();
+        self.morton = particles.accel_structure == AccelStructure::MortonHash;
+        self.hash_table
+            .resize_with(hash_table_length + 1, || AtomicUsize::new(0));
+        self.hash_table
+            .par_iter()
+            .for_each(|count| count.store(0, Relaxed));
+        self.particle_indices
+            .resize_with(spheres.len(), || AtomicUsize::new(0));
+
+        let hash_table = &self.hash_table;
+
+        spheres.par_iter().for_each(|sphere| {
+            let index = self.bucket(particles.cell_coord(sphere.position));
+            hash_table[index].fetch_add(1, Relaxed);
+        });
+
+        for i in 1..hash_table.len() {
+            hash_table[i].fetch_add(hash_table[i - 1].load(Relaxed), Relaxed);
+        }
+
+        let particle_indices = &self.particle_indices;
+        spheres.par_iter().enumerate().for_each(|(i, sphere)| {
+            let index = self.bucket(particles.cell_coord(sphere.position));
+            let index = hash_table[index].fetch_sub(1, Relaxed);
+            particle_indices[index - 1].store(i, Relaxed);
+        });
+
+        if particles.sorts_buckets() {
+            sort_buckets(&self.hash_table, &mut self.particle_indices);
+        }
+    }
+
+    /// The indices of the particles in the same bucket as `cell`, this can include particles from other cells
+    fn cell_particles(&self, cell: cgmath::Vector3<isize>) -> impl Iterator<Item = usize> + '_ {
+        let index = self.bucket(cell);
+        self.particle_indices
+            [self.hash_table[index].load(Relaxed)..self.hash_table[index + 1].load(Relaxed)]
+            .iter()
+            .map(|index| index.load(Relaxed))
+    }
+}
+
+/// Sorts the particle indices in each bucket, they are filled in parallel so they are in a different order every time otherwise.
+/// `bucket_starts` has the index in `particle_indices` that each bucket starts at, followed by the end of the last bucket
+fn sort_buckets(bucket_starts: &[AtomicUsize], particle_indices: &mut [AtomicUsize]) {
+    for bucket in bucket_starts.windows(2) {
+        particle_indices[bucket[0].load(Relaxed)..bucket[1].load(Relaxed)]
+            .sort_unstable_by_key(|index| index.load(Relaxed));
+    }
+}
+
+/// Buckets particles by the cell of the world that they are in, every cell gets its own bucket
+#[derive(Default)]
+pub struct UniformGrid {
+    cells_per_axis: [usize; 3],
+    /// `cell_size` rounded up so that a whole number of cells fit in the world along each axis
+    cell_size: [f32; 3],
+    cell_starts: Vec<AtomicUsize>,
+    particle_indices: Vec<AtomicUsize>,
+}
+
+impl Clone for UniformGrid {
+    fn clone(&self) -> Self {
+        // this only holds scratch space that gets rebuilt on every update, so there is nothing worth copying
+        Self::default()
+    }
+}
+
+impl UniformGrid {
+    fn cell_coord(&self, particles: &Particles, v: cgmath::Vector3<f32>) -> cgmath::Vector3<isize> {
+        let mut cell = cgmath::vec3(0, 0, 0);
+        for axis in 0..3 {
+            cell[axis] = (((v[axis] + particles.world_size[axis] * 0.5) / self.cell_size[axis])
+                .floor() as isize)
+                .clamp(0, self.cells_per_axis[axis] as isize - 1);
+        }
+        cell
+    }
+
+    fn cell_index(&self, cell: cgmath::Vector3<isize>) -> usize {
+        let [x_cells, y_cells, _] = self.cells_per_axis;
+        cell.x as usize + (cell.y as usize + cell.z as usize * y_cells) * x_cells
+    }
+
+    /// Sets the number and size of the cells to fit the world of `particles`
+    fn resize(&mut self, particles: &Particles) {
+        for axis in 0..3 {
+            self.cells_per_axis[axis] =
+                ((particles.world_size[axis] / particles.cell_size) as usize).max(1);
+            self.cell_size[axis] = particles.world_size[axis] / self.cells_per_axis[axis] as f32;
+        }
+    }
+
+    /// Fills the grid with `spheres`, reusing the existing buffers when they are big enough
+    fn build(&mut self, particles: &Particles, spheres: &[Particle]) {
+        self.resize(particles);
+
+        let cell_count: usize = self.cells_per_axis.iter().product();
+        self.cell_starts
+            .resize_with(cell_count + 1, || AtomicUsize::new(0));
+        self.cell_starts
+            .par_iter()
+            .for_each(|count| count.store(0, Relaxed));
+        self.particle_indices
+            .resize_with(spheres.len(), || AtomicUsize::new(0));
+
+        let this = &*self;
+        spheres.par_iter().for_each(|sphere| {
+            let index = this.cell_index(this.cell_coord(particles, sphere.position));
+            this.cell_starts[index].fetch_add(1, Relaxed);
+        });
+
+        for i in 1..this.cell_starts.len() {
+            this.cell_starts[i].fetch_add(this.cell_starts[i - 1].load(Relaxed), Relaxed);
+        }
+
+        spheres.par_iter().enumerate().for_each(|(i, sphere)| {
+            let index = this.cell_index(this.cell_coord(particles, sphere.position));
+            let index = this.cell_starts[index].fetch_sub(1, Relaxed);
+            this.particle_indices[index - 1].store(i, Relaxed);
+        });
+
+        if particles.sorts_buckets() {
+            sort_buckets(&self.cell_starts, &mut self.particle_indices);
+        }
+    }
+
+    /// The indices of the particles in `cell`
+    fn cell_particles(&self, cell: cgmath::Vector3<isize>) -> impl Iterator<Item = usize> + '_ {
+        let index = self.cell_index(cell);
+        self.particle_indices
+            [self.cell_starts[index].load(Relaxed)..self.cell_starts[index + 1].load(Relaxed)]
+            .iter()
+            .map(|index| index.load(Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solid_walls_bounce_particles_back_inside() {
+        for axis in 0..3 {
+            for direction in [-1.0, 1.0] {
+                let mut position = cgmath::Vector3::zero();
+                position[axis] = 4.9 * direction;
+                let mut velocity = cgmath::Vector3::zero();
+                velocity[axis] = 10.0 * direction;
+                let mut particles = Particles::builder()
+                    .boundaries([BoundaryMode::Solid; 3])
+                    .wall_restitution(0.5)
+                    .particles(vec![Particle {
+                        position,
+                        velocity,
+                        id: 0,
+                    }])
+                    .build()
+                    .unwrap();
+
+                particles.update(0.1);
+
+                let particle = particles.current_particles[0];
+                assert_eq!(particle.position[axis], 5.0 * direction);
+                assert!(particle.velocity[axis] * direction < 0.0);
+            }
+        }
+    }
+
+    fn particle(position: cgmath::Vector3<f32>, id: u32) -> Particle {
+        Particle {
+            position,
+            velocity: cgmath::Vector3::zero(),
+            id,
+        }
+    }
+
+    /// Steps one copy of `particles` with `update` and another with `update_brute_force` and checks that they stay together
+    fn assert_matches_brute_force(particles: &Particles, steps: usize) {
+        let mut fast = particles.clone();
+        let mut brute_force = particles.clone();
+        for _ in 0..steps {
+            fast.update(1.0 / 60.0);
+            brute_force.update_brute_force(1.0 / 60.0);
+        }
+        assert_particles_close(&fast.current_particles, &brute_force.current_particles);
+    }
+
+    fn assert_particles_close(a: &[Particle], b: &[Particle]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b) {
+            assert!(
+                (a.position - b.position).magnitude() < 1e-3,
+                "{:?} != {:?}",
+                a.position,
+                b.position
+            );
+            assert!(
+                (a.velocity - b.velocity).magnitude() < 1e-3,
+                "{:?} != {:?}",
+                a.velocity,
+                b.velocity
+            );
+        }
+    }
+
+    #[test]
+    fn cells_sharing_a_bucket_match_brute_force() {
+        // with 2 particles there are only 2 buckets, so many of the cells around them share the bucket of their own cell
+        let particles = Particles::builder()
+            .particles(vec![
+                particle(cgmath::vec3(0.5, 0.5, 0.5), 0),
+                particle(cgmath::vec3(1.0, 0.5, 0.5), 1),
+            ])
+            .build()
+            .unwrap();
+
+        let spatial_hash = SpatialHash::new(&particles, &particles.current_particles);
+        let cell = particles.cell_coord(particles.current_particles[0].position);
+        let colliding_cells = (-1..=1)
+            .flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| cgmath::vec3(x, y, z))))
+            .filter(|&offset| {
+                offset != cgmath::vec3(0, 0, 0)
+                    && spatial_hash.bucket(cell + offset) == spatial_hash.bucket(cell)
+            })
+            .count();
+        assert!(colliding_cells > 0);
+
+        assert_matches_brute_force(&particles, 1);
+    }
+
+    #[test]
+    fn update_matches_brute_force() {
+        let mut particles = Particles::default();
+        particles.spawn_random(200, 0);
+        assert_matches_brute_force(&particles, 10);
+    }
+
+    #[test]
+    fn spawning_with_the_same_seed_is_reproducible() {
+        let mut a = Particles::default();
+        a.spawn_random(100, 42);
+        let mut b = Particles::default();
+        b.spawn_random(100, 42);
+        assert_eq!(a.current_particles, b.current_particles);
+
+        b.spawn_random(100, 43);
+        assert_ne!(a.current_particles, b.current_particles);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn json_round_trip() {
+        let mut particles = Particles::default();
+        particles.spawn_random(50, 0);
+        particles.update(1.0 / 60.0);
+
+        let loaded = Particles::from_json(&particles.to_json()).unwrap();
+        assert_eq!(loaded.attraction_matrix, particles.attraction_matrix);
+        assert_eq!(loaded.colors, particles.colors);
+        assert_eq!(loaded.current_particles, particles.current_particles);
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected() {
+        let new = |world_size: f32, attraction_matrix: Vec<f32>, colors_len: usize, id: u32| {
+            Particles::new(
+                cgmath::vec3(world_size, world_size, world_size),
+                2,
+                attraction_matrix,
+                vec![cgmath::vec3(1.0, 1.0, 1.0); colors_len],
+                0.97,
+                1.0,
+                0.3,
+                2.0,
+                [BoundaryMode::Periodic; 3],
+                GravityMode::None,
+                vec![particle(cgmath::Vector3::zero(), id)],
+            )
+        };
+        assert!(new(10.0, vec![0.0; 4], 2, 1).is_ok());
+        assert_eq!(
+            new(3.0, vec![0.0; 4], 2, 1).err(),
+            Some(ParticlesError::WorldTooSmall {
+                axis: 0,
+                world_size: 3.0,
+                particle_effect_radius: 2.0,
+            })
+        );
+        assert_eq!(
+            new(10.0, vec![0.0; 3], 2, 1).err(),
+            Some(ParticlesError::AttractionMatrixLength {
+                expected: 4,
+                actual: 3,
+            })
+        );
+        assert_eq!(
+            new(10.0, vec![0.0; 4], 3, 1).err(),
+            Some(ParticlesError::ColorsLength {
+                expected: 2,
+                actual: 3,
+            })
+        );
+        assert_eq!(
+            new(10.0, vec![0.0; 4], 2, 2).err(),
+            Some(ParticlesError::InvalidParticleId { index: 0, id: 2 })
+        );
+        assert_eq!(
+            Particles::builder().types(vec![], vec![]).build().err(),
+            Some(ParticlesError::NoTypes)
+        );
+
+        let particles = Particles {
+            per_type_friction: Some(vec![0.9]),
+            ..Default::default()
+        };
+        assert_eq!(
+            particles.validate(),
+            Err(ParticlesError::PerTypeValuesLength {
+                name: "per_type_friction",
+                expected: 5,
+                actual: 1,
+            })
+        );
+
+        let particles = Particles {
+            cell_size: 0.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            particles.validate(),
+            Err(ParticlesError::InvalidCellSize { cell_size: 0.0 })
+        );
+
+        let particles = Particles {
+            per_type_radius: Some(vec![1.0, 1.0, 3.0, 1.0, 1.0]),
+            ..Default::default()
+        };
+        assert_eq!(
+            particles.validate(),
+            Err(ParticlesError::InvalidPerTypeRadius {
+                id: 2,
+                radius: 3.0,
+                particle_effect_radius: 2.0,
+            })
+        );
+    }
+
+    #[test]
+    fn per_type_friction_slows_types_differently() {
+        let mut particles = Particles::builder()
+            .types(vec![0.0; 4], vec![cgmath::vec3(1.0, 1.0, 1.0); 2])
+            .particles(
+                [(-3.0, 0), (3.0, 1)]
+                    .map(|(x, id)| Particle {
+                        position: cgmath::vec3(x, 0.0, 0.0),
+                        velocity: cgmath::vec3(0.0, 1.0, 0.0),
+                        id,
+                    })
+                    .to_vec(),
+            )
+            .build()
+            .unwrap();
+        particles.per_type_friction = Some(vec![0.0, 2.0]);
+
+        for _ in 0..60 {
+            particles.update(1.0 / 60.0);
+        }
+
+        let speeds = particles
+            .current_particles
+            .iter()
+            .map(|particle| particle.velocity.magnitude())
+            .collect::<Vec<_>>();
+        assert!((speeds[0] - 1.0).abs() < 1e-4);
+        assert!((speeds[1] - (-2.0f32).exp()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn speeds_never_exceed_max_velocity() {
+        let mut particles = Particles::builder()
+            .types(vec![1000.0], vec![cgmath::vec3(1.0, 1.0, 1.0)])
+            .friction(0.0)
+            .max_velocity(5.0)
+            .build()
+            .unwrap();
+        particles.spawn_random(100, 0);
+
+        for _ in 0..100 {
+            particles.update(1.0 / 60.0);
+            for particle in &particles.current_particles {
+                assert!(particle.velocity.magnitude() <= 5.0 + 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn random_symmetric_attraction() {
+        let mut particles = Particles::default();
+        particles.randomize_attraction(0, -0.5..=0.25);
+        assert!(particles
+            .attraction_matrix
+            .iter()
+            .all(|attraction| (-0.5..=0.25).contains(attraction)));
+
+        particles.symmetric_attraction();
+        let n = particles.id_count as usize;
+        for i in 0..n {
+            for j in 0..n {
+                assert_eq!(
+                    particles.attraction_matrix[i * n + j],
+                    particles.attraction_matrix[j * n + i]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn query_radius_matches_a_linear_scan() {
+        let mut particles = Particles::default();
+        particles.spawn_random(2000, 0);
+
+        for (center, radius) in [
+            (cgmath::vec3(0.0, 0.0, 0.0), 0.5),
+            (cgmath::vec3(4.5, -4.5, 3.0), 1.5),
+            (cgmath::vec3(-2.0, 1.0, 4.9), 3.0),
+            (cgmath::vec3(1.0, 2.0, 3.0), 7.0),
+            (cgmath::vec3(0.0, 0.0, 0.0), 1e9),
+        ] {
+            let expected = (0..particles.current_particles.len())
+                .filter(|&index| {
+                    let position = particles.current_particles[index].position;
+                    (-1..=1).any(|x| {
+                        (-1..=1).any(|y| {
+                            (-1..=1).any(|z| {
+                                let offset = cgmath::vec3(x as f32, y as f32, z as f32) * 10.0;
+                                (position - (center + offset)).magnitude2() <= radius * radius
+                            })
+                        })
+                    })
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(particles.query_radius(center, radius), expected);
+        }
+    }
+
+    #[test]
+    fn kinetic_energy_and_momentum() {
+        let particles = Particles::builder()
+            .types(vec![0.0; 4], vec![cgmath::vec3(1.0, 1.0, 1.0); 2])
+            .masses(vec![1.0, 2.0])
+            .particles(vec![
+                Particle {
+                    position: cgmath::vec3(-3.0, 0.0, 0.0),
+                    velocity: cgmath::vec3(1.0, 2.0, 0.0),
+                    id: 0,
+                },
+                Particle {
+                    position: cgmath::vec3(3.0, 0.0, 0.0),
+                    velocity: cgmath::vec3(0.0, -1.0, 3.0),
+                    id: 1,
+                },
+            ])
+            .build()
+            .unwrap();
+
+        assert_eq!(particles.total_kinetic_energy(), 12.5);
+        assert_eq!(particles.total_momentum(), cgmath::vec3(1.0, 0.0, 6.0));
+    }
+
+    #[test]
+    fn recenter_moves_the_center_of_mass_to_the_origin() {
+        let mut particles = Particles::default();
+        particles.spawn_random(100, 0);
+        for particle in &mut particles.current_particles {
+            particle.position = particle.position * 0.1 + cgmath::vec3(3.0, 2.0, -1.0);
+        }
+
+        particles.recenter();
+
+        assert!(particles.center_of_mass().magnitude() < 1e-4);
+    }
+
+    #[test]
+    fn heavier_particles_accelerate_less_except_under_gravity() {
+        let builder = || {
+            Particles::builder()
+                .types(vec![0.0; 4], vec![cgmath::vec3(1.0, 1.0, 1.0); 2])
+                .masses(vec![1.0, 4.0])
+                .friction(0.0)
+                .particles(vec![
+                    particle(cgmath::vec3(2.0, 0.0, 0.0), 0),
+                    particle(cgmath::vec3(-2.0, 0.0, 0.0), 1),
+                ])
+        };
+        let speeds = |particles: &Particles| {
+            particles
+                .current_particles
+                .iter()
+                .map(|particle| particle.velocity.magnitude())
+                .collect::<Vec<_>>()
+        };
+
+        let mut particles = builder().build().unwrap();
+        particles.attractors.push(Attractor {
+            position: cgmath::Vector3::zero(),
+            strength: 1.0,
+            radius: 4.0,
+        });
+        particles.update(1.0 / 60.0);
+        let attracted = speeds(&particles);
+        assert!(attracted[1] > 0.0);
+        assert!((attracted[0] - 4.0 * attracted[1]).abs() < 1e-6);
+
+        let mut particles = builder()
+            .gravity(GravityMode::Uniform(cgmath::vec3(0.0, -1.0, 0.0)))
+            .build()
+            .unwrap();
+        particles.update(1.0 / 60.0);
+        let falling = speeds(&particles);
+        assert!(falling[0] > 0.0);
+        assert_eq!(falling[0], falling[1]);
+    }
+
+    /// A single particle in an eccentric orbit around the center of the world, which has no friction so its energy should stay the same
+    fn orbit(integrator: Integrator) -> Particles {
+        Particles::builder()
+            .friction(0.0)
+            .gravity(GravityMode::Radial { strength: 1.0 })
+            .integrator(integrator)
+            .particles(vec![Particle {
+                position: cgmath::vec3(2.0, 0.0, 0.0),
+                velocity: cgmath::vec3(0.0, 1.0, 0.0),
+                id: 0,
+            }])
+            .build()
+            .unwrap()
+    }
+
+    /// The kinetic energy plus the potential energy of radial gravity,
+    /// after a velocity verlet step the velocities are the ones at the positions before the step
+    fn orbit_energy(particles: &Particles) -> f32 {
+        let GravityMode::Radial { strength } = particles.gravity else {
+            unreachable!()
+        };
+        let positions = match particles.integrator {
+            Integrator::VelocityVerlet if !particles.previous_particles.is_empty() => {
+                &particles.previous_particles
+            }
+            _ => &particles.current_particles,
+        };
+        particles.total_kinetic_energy()
+            + positions
+                .iter()
+                .map(|particle| strength * particle.position.magnitude())
+                .sum::<f32>()
+    }
+
+    /// The biggest difference from the starting energy of `orbit` over 1000 steps of `substeps` updates each
+    fn max_energy_drift(integrator: Integrator, ts: f32, substeps: usize) -> f32 {
+        let mut particles = orbit(integrator);
+        let energy = orbit_energy(&particles);
+        let mut max_drift = 0.0f32;
+        for _ in 0..1000 {
+            for _ in 0..substeps {
+                particles.update(ts / substeps as f32);
+            }
+            max_drift = max_drift.max((orbit_energy(&particles) - energy).abs());
+        }
+        max_drift
+    }
+
+    #[test]
+    fn velocity_verlet_drifts_less_than_euler() {
+        let euler = max_energy_drift(Integrator::SemiImplicitEuler, 0.05, 1);
+        let verlet = max_energy_drift(Integrator::VelocityVerlet, 0.05, 1);
+        assert!(verlet < euler * 0.5, "{verlet} >= {euler} * 0.5");
+    }
+
+    #[test]
+    fn solid_floor_with_periodic_sides() {
+        let builder = || {
+            Particles::builder().boundaries([
+                BoundaryMode::Periodic,
+                BoundaryMode::Solid,
+                BoundaryMode::Periodic,
+            ])
+        };
+
+        let mut particles = builder()
+            .particles(vec![Particle {
+                position: cgmath::vec3(4.9, 4.9, 4.9),
+                velocity: cgmath::vec3(10.0, 10.0, 10.0),
+                id: 0,
+            }])
+            .build()
+            .unwrap();
+        particles.update(0.1);
+        let moved = particles.current_particles[0];
+        assert!(moved.position.x < -4.0);
+        assert_eq!(moved.position.y, 5.0);
+        assert!(moved.velocity.y <= 0.0);
+        assert!(moved.position.z < -4.0);
+
+        // particles only interact across the edges of the periodic axes
+        for (axis, expected_pair_count) in [(0, 2), (1, 0), (2, 2)] {
+            let mut position = cgmath::Vector3::zero();
+            position[axis] = 4.5;
+            let mut particles = builder()
+                .particles(vec![particle(position, 0), particle(-position, 0)])
+                .build()
+                .unwrap();
+            particles.update(1.0 / 60.0);
+            assert_eq!(particles.last_step_pair_count, expected_pair_count);
+        }
+    }
+
+    #[test]
+    fn particles_rebound_off_the_floor_at_restitution_times_their_speed() {
+        let mut particles = Particles::builder()
+            .boundaries([BoundaryMode::Solid; 3])
+            .wall_restitution(0.5)
+            .friction(0.0)
+            .gravity(GravityMode::Uniform(cgmath::vec3(0.0, -10.0, 0.0)))
+            .particles(vec![Particle {
+                position: cgmath::vec3(0.0, -4.9, 0.0),
+                velocity: cgmath::vec3(0.0, -3.0, 0.0),
+                id: 0,
+            }])
+            .build()
+            .unwrap();
+
+        // the particle reaches a speed of 4 during the step and goes through the floor
+        particles.update(0.1);
+
+        let particle = particles.current_particles[0];
+        assert_eq!(particle.position.y, -5.0);
+        assert!((particle.velocity.y - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn csv_has_a_header_and_a_row_per_particle_per_frame() {
+        let mut particles = Particles::default();
+        particles.spawn_random(20, 0);
+
+        let mut csv = vec![];
+        Particles::write_csv_header(&mut csv).unwrap();
+        particles.append_csv(&mut csv, 0).unwrap();
+        particles.update(1.0 / 60.0);
+        particles.append_csv(&mut csv, 1).unwrap();
+
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("frame,index,id,x,y,z,vx,vy,vz"));
+        let rows = lines.collect::<Vec<_>>();
+        assert_eq!(rows.len(), 40);
+        assert!(rows.iter().all(|row| row.split(',').count() == 9));
+        assert!(rows[..20].iter().all(|row| row.starts_with("0,")));
+        assert!(rows[20..].iter().all(|row| row.starts_with("1,")));
+    }
+
+    #[test]
+    fn adding_and_removing_types_keeps_the_other_attractions() {
+        let mut particles = Particles::builder()
+            .types(
+                vec![1.0, 2.0, 3.0, 4.0],
+                vec![cgmath::vec3(1.0, 0.0, 0.0), cgmath::vec3(0.0, 1.0, 0.0)],
+            )
+            .particles(vec![
+                particle(cgmath::vec3(-3.0, 0.0, 0.0), 0),
+                particle(cgmath::vec3(3.0, 0.0, 0.0), 1),
+            ])
+            .build()
+            .unwrap();
+
+        let id = particles.add_type(cgmath::vec3(0.0, 0.0, 1.0));
+        assert_eq!(id, 2);
+        assert_eq!(particles.id_count, 3);
+        #[rustfmt::skip]
+        assert_eq!(particles.attraction_matrix, vec![
+            1.0, 2.0, 0.0,
+            3.0, 4.0, 0.0,
+            0.0, 0.0, 0.0,
+        ]);
+        assert_eq!(particles.colors.len(), 3);
+        particles.validate().unwrap();
+
+        particles.attraction_matrix[2 * 3 + 1] = 5.0;
+        particles.remove_type(0);
+        assert_eq!(particles.id_count, 2);
+        assert_eq!(particles.attraction_matrix, vec![4.0, 0.0, 5.0, 0.0]);
+        assert_eq!(
+            particles.colors,
+            vec![cgmath::vec3(0.0, 1.0, 0.0), cgmath::vec3(0.0, 0.0, 1.0)]
+        );
+        assert_eq!(
+            particles.current_particles,
+            vec![particle(cgmath::vec3(3.0, 0.0, 0.0), 0)]
+        );
+        particles.validate().unwrap();
+    }
+
+    #[test]
+    fn cell_hashes_are_fixed() {
+        // these have to match the hash in `particles_update.wgsl`
+        assert_eq!(SpatialHash::hash(cgmath::vec3(0, 0, 0)), 0);
+        assert_eq!(SpatialHash::hash(cgmath::vec3(1, 2, 3)), 147163718);
+        assert_eq!(SpatialHash::hash(cgmath::vec3(-1, 0, 0)), 4221111203);
+        assert_eq!(SpatialHash::hash(cgmath::vec3(5, -7, 11)), 3612476075);
+
+        let mut particles = Particles::default();
+        particles.spawn_random(1000, 0);
+        let spatial_hash = SpatialHash::new(&particles, &particles.current_particles);
+        assert_eq!(spatial_hash.bucket(cgmath::vec3(1, 2, 3)), 718);
+        assert_eq!(spatial_hash.bucket(cgmath::vec3(-1, 0, 0)), 203);
+        assert_eq!(spatial_hash.bucket(cgmath::vec3(5, -7, 11)), 75);
+    }
+
+    #[test]
+    fn reused_spatial_hash_matches_brute_force() {
+        let mut particles = Particles::default();
+        particles.spawn_random(300, 1);
+        for _ in 0..3 {
+            particles.update(1.0 / 60.0);
+        }
+        // the spatial hash now has buffers for more particles than are left
+        let mut index = 0;
+        particles.retain_particles(|_| {
+            index += 1;
+            index % 2 == 0
+        });
+
+        let mut brute_force = particles.clone();
+        for _ in 0..5 {
+            particles.update(1.0 / 60.0);
+            brute_force.update_brute_force(1.0 / 60.0);
+        }
+        assert_particles_close(&particles.current_particles, &brute_force.current_particles);
+    }
+
+    #[test]
+    fn uniform_grid_matches_brute_force() {
+        // 11 isnt a multiple of the cell size, so the cells get rounded up to fit the world
+        for world_size in [10.0, 11.0] {
+            let mut particles = Particles::builder()
+                .world_size(cgmath::vec3(world_size, world_size, world_size))
+                .accel_structure(AccelStructure::UniformGrid)
+                .build()
+                .unwrap();
+            particles.spawn_random(200, 0);
+            assert_matches_brute_force(&particles, 10);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn preset_toml_round_trip() {
+        let mut particles = Particles::builder()
+            .world_size(cgmath::vec3(10.0, 20.0, 30.0))
+            .boundaries([
+                BoundaryMode::Periodic,
+                BoundaryMode::Solid,
+                BoundaryMode::Periodic,
+            ])
+            .gravity(GravityMode::Radial { strength: 2.0 })
+            .build()
+            .unwrap();
+        particles.randomize_attraction(0, -1.0..=1.0);
+
+        let preset = particles.preset();
+        assert_eq!(Preset::from_toml(&preset.to_toml()).unwrap(), preset);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn applying_a_preset_removes_particles_with_missing_ids() {
+        let mut particles = Particles::default();
+        particles.spawn_random(100, 0);
+        let kept = particles.iter_type(0).count() + particles.iter_type(1).count();
+
+        let mut preset = particles.preset();
+        preset.id_count = 2;
+        preset.attraction_matrix = vec![0.5; 4];
+        preset.colors.truncate(2);
+        particles.apply_preset(preset).unwrap();
+
+        assert_eq!(particles.id_count, 2);
+        assert_eq!(particles.current_particles.len(), kept);
+        assert!(particles
+            .current_particles
+            .iter()
+            .all(|particle| particle.id < 2));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn applying_an_invalid_preset_changes_nothing() {
+        let mut particles = Particles::default();
+        particles.spawn_random(100, 0);
+        let original = particles.clone();
+
+        let mut preset = particles.preset();
+        preset.attraction_matrix.pop();
+        assert_eq!(
+            particles.apply_preset(preset),
+            Err(ParticlesError::AttractionMatrixLength {
+                expected: 25,
+                actual: 24,
+            })
+        );
+        assert_eq!(particles.attraction_matrix, original.attraction_matrix);
+        assert_eq!(particles.current_particles, original.current_particles);
+    }
+
+    #[test]
+    fn per_type_force_scale_scales_the_acceleration() {
+        let mut particles = Particles::builder()
+            .types(vec![1.0; 4], vec![cgmath::vec3(1.0, 1.0, 1.0); 2])
+            .particles(vec![
+                particle(cgmath::vec3(-0.25, 0.0, 0.0), 0),
+                particle(cgmath::vec3(0.25, 0.0, 0.0), 1),
+            ])
+            .build()
+            .unwrap();
+        particles.per_type_force_scale = Some(vec![1.0, 3.0]);
+
+        particles.update(1.0 / 60.0);
+
+        let [low, high] = [0, 1].map(|i| particles.current_particles[i].velocity.magnitude());
+        assert!(low > 0.0);
+        assert!((high - 3.0 * low).abs() < 1e-5);
+    }
+
+    #[test]
+    fn particles_stay_in_the_plane_in_2d() {
+        let mut particles = Particles::builder()
+            .dimensions(Dimensions::Two)
+            .gravity(GravityMode::Uniform(cgmath::vec3(0.0, 0.0, 1.0)))
+            .build()
+            .unwrap();
+        particles.spawn_random(200, 0);
+
+        for _ in 0..100 {
+            particles.update(1.0 / 60.0);
+            assert!(particles
+                .current_particles
+                .iter()
+                .all(|particle| particle.position.z == 0.0 && particle.velocity.z == 0.0));
+        }
+    }
+
+    #[test]
+    fn obstacles_deflect_particles() {
+        let mut particles = Particles::builder()
+            .friction(0.0)
+            .particles(vec![Particle {
+                position: cgmath::vec3(-3.0, 0.3, 0.0),
+                velocity: cgmath::vec3(5.0, 0.0, 0.0),
+                id: 0,
+            }])
+            .build()
+            .unwrap();
+        particles.obstacles.push((cgmath::Vector3::zero(), 1.0));
+
+        for _ in 0..60 {
+            particles.update(1.0 / 60.0);
+            assert!(particles.current_particles[0].position.magnitude() >= 1.0 - 1e-5);
+        }
+
+        let particle = particles.current_particles[0];
+        assert!(particle.velocity.y > 0.0);
+        assert!(particle.velocity.x < 5.0);
+    }
+
+    #[test]
+    fn attractors_pull_particles_towards_them() {
+        let start = cgmath::vec3(2.0, 1.0, 0.0);
+        let mut particles = Particles::builder()
+            .particles(vec![particle(start, 0)])
+            .build()
+            .unwrap();
+        let attractor = Attractor {
+            position: cgmath::vec3(1.0, -1.0, 0.5),
+            strength: 1.0,
+            radius: 4.0,
+        };
+        particles.attractors.push(attractor);
+
+        particles.update(1.0 / 60.0);
+
+        let particle = particles.current_particles[0];
+        let towards_attractor = (attractor.position - start).normalize();
+        assert!(particle.velocity.dot(towards_attractor) > 0.0);
+        assert!(particle.velocity.cross(towards_attractor).magnitude() < 1e-6);
+        assert!(
+            (particle.position - attractor.position).magnitude()
+                < (start - attractor.position).magnitude()
+        );
+    }
+
+    /// Runs `steps` updates of `particles` on a thread pool with `threads` threads
+    fn update_on_threads(mut particles: Particles, threads: usize, steps: usize) -> Particles {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap()
+            .install(|| {
+                for _ in 0..steps {
+                    particles.update(1.0 / 60.0);
+                }
+            });
+        particles
+    }
+
+    #[test]
+    fn deterministic_updates_are_the_same_on_different_thread_pools() {
+        let mut particles = Particles::builder().deterministic(true).build().unwrap();
+        particles.target_speed = Some(0.5);
+        particles.spawn_random(500, 0);
+
+        let a = update_on_threads(particles.clone(), 2, 20);
+        let b = update_on_threads(particles, 4, 20);
+        assert_eq!(a.current_particles, b.current_particles);
+    }
+
+    #[test]
+    fn thermostat_moves_the_mean_speed_to_the_target() {
+        for start_speed in [0.2, 3.0] {
+            let mut particles = Particles::builder()
+                .types(vec![0.0], vec![cgmath::vec3(1.0, 1.0, 1.0)])
+                .friction(0.0)
+                .build()
+                .unwrap();
+            particles.spawn_random(20, 0);
+            let mut rng = StdRng::seed_from_u64(0);
+            for particle in &mut particles.current_particles {
+                particle.velocity = cgmath::vec3(
+                    rng.gen_range(-1.0..=1.0),
+                    rng.gen_range(-1.0..=1.0),
+                    rng.gen_range(-1.0..=1.0),
+                )
+                .normalize_to(start_speed * rng.gen_range(0.5..=1.5));
+            }
+            particles.target_speed = Some(1.0);
+            particles.thermostat_coupling = 2.0;
+
+            for _ in 0..300 {
+                particles.update(1.0 / 60.0);
+            }
+
+            let mean_speed = particles
+                .current_particles
+                .iter()
+                .map(|particle| particle.velocity.magnitude())
+                .sum::<f32>()
+                / particles.current_particles.len() as f32;
+            assert!((mean_speed - 1.0).abs() < 1e-2, "{mean_speed}");
+        }
+    }
+
+    #[test]
+    fn pair_correlation_of_a_lattice() {
+        let coord = |i: usize| i as f32 - 4.5;
+        let particles = Particles::builder()
+            .particles(
+                (0..1000)
+                    .map(|i| {
+                        particle(
+                            cgmath::vec3(coord(i % 10), coord(i / 10 % 10), coord(i / 100)),
+                            0,
+                        )
+                    })
+                    .collect(),
+            )
+            .build()
+            .unwrap();
+
+        // the bins are 0.125 wide, and the neighbours are at 1, sqrt(2), sqrt(3), 2, sqrt(5), and sqrt(6)
+        let bins = particles.pair_correlation(20, 2.5);
+        let peaks = (0..bins.len())
+            .filter(|&i| bins[i] > 0.0)
+            .collect::<Vec<_>>();
+        assert_eq!(peaks, vec![8, 11, 13, 16, 17, 19]);
+
+        assert_eq!(
+            particles.pair_correlation(10, 100.0),
+            particles.pair_correlation(10, 5.0)
+        );
+    }
+
+    #[test]
+    fn zeroed_velocities_stay_zeroed() {
+        for integrator in [Integrator::SemiImplicitEuler, Integrator::VelocityVerlet] {
+            let mut particles = Particles::builder()
+                .friction(0.0)
+                .gravity(GravityMode::Uniform(cgmath::vec3(0.0, -1.0, 0.0)))
+                .integrator(integrator)
+                .particles(vec![Particle {
+                    position: cgmath::Vector3::zero(),
+                    velocity: cgmath::vec3(5.0, 0.0, 0.0),
+                    id: 0,
+                }])
+                .build()
+                .unwrap();
+            for _ in 0..10 {
+                particles.update(0.1);
+            }
+
+            particles.zero_velocities();
+            assert!(particles
+                .current_particles
+                .iter()
+                .chain(&particles.previous_particles)
+                .all(|particle| particle.velocity == cgmath::Vector3::zero()));
+
+            // only gravity from this update is left
+            particles.update(0.1);
+            let velocity = particles.current_particles[0].velocity;
+            assert_eq!(velocity.x, 0.0);
+            assert!((velocity.y + 0.1).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn force_profiles_at_known_distances() {
+        let m = 0.3;
+        let attraction = 0.8;
+        for (force_profile, samples) in [
+            (
+                ForceProfile::ParticleLifeDefault,
+                [
+                    (0.0, -1.0),
+                    (0.15, -0.5),
+                    (0.475, 0.4),
+                    (0.65, 0.8),
+                    (1.0, 0.0),
+                    (1.5, 0.0),
+                ],
+            ),
+            (
+                ForceProfile::SmoothQuadratic,
+                [
+                    (0.0, -1.0),
+                    (0.15, -0.25),
+                    (0.475, 0.6),
+                    (0.65, 0.8),
+                    (1.0, 0.0),
+                    (1.5, 0.0),
+                ],
+            ),
+            (
+                ForceProfile::LennardJonesLike,
+                [
+                    (0.15, -LENNARD_JONES_MAX_REPULSION),
+                    (0.3 / 1.5f32.powf(1.0 / 6.0), -0.5),
+                    (0.3 + 0.7 / 3.0, 0.8),
+                    (0.65, 0.675),
+                    (1.0, 0.0),
+                    (1.5, 0.0),
+                ],
+            ),
+        ] {
+            for (distance, expected) in samples {
+                let force = force_profile.force(distance, attraction, m);
+                assert!(
+                    (force - expected).abs() < 1e-5,
+                    "{force_profile:?} at {distance} is {force} instead of {expected}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn flat_world() {
+        let world_size = cgmath::vec3(16.0, 4.0, 8.0);
+        let mut particles = Particles::builder().world_size(world_size).build().unwrap();
+        particles.spawn_random(200, 0);
+        assert_matches_brute_force(&particles, 5);
+
+        for _ in 0..20 {
+            particles.update(1.0 / 60.0);
+        }
+        for particle in &particles.current_particles {
+            for axis in 0..3 {
+                assert!(particle.position[axis].abs() <= world_size[axis] * 0.5);
+            }
+        }
+    }
+
+    #[test]
+    fn default_particles_update() {
+        let mut particles = Particles::default();
+        particles.spawn_random(100, 0);
+        particles.update(1.0 / 60.0);
+        assert_eq!(particles.current_particles.len(), 100);
+        assert!(particles
+            .current_particles
+            .iter()
+            .all(|particle| particle.position.x.is_finite()
+                && particle.position.y.is_finite()
+                && particle.position.z.is_finite()));
+    }
+
+    #[test]
+    fn builder_validates_once_everything_is_set() {
+        // the cell size follows the radius unless it is set
+        let particles = Particles::builder()
+            .world_size(cgmath::vec3(20.0, 20.0, 20.0))
+            .particle_effect_radius(3.0)
+            .build()
+            .unwrap();
+        assert_eq!(particles.cell_size, 3.0);
+        let particles = Particles::builder().cell_size(1.5).build().unwrap();
+        assert_eq!(particles.cell_size, 1.5);
+
+        assert_eq!(
+            Particles::builder()
+                .particle_effect_radius(6.0)
+                .build()
+                .err(),
+            Some(ParticlesError::WorldTooSmall {
+                axis: 0,
+                world_size: 10.0,
+                particle_effect_radius: 6.0,
+            })
+        );
+        assert_eq!(
+            Particles::builder()
+                .types(vec![0.0; 4], vec![cgmath::vec3(1.0, 1.0, 1.0); 3])
+                .build()
+                .err(),
+            Some(ParticlesError::AttractionMatrixLength {
+                expected: 9,
+                actual: 4,
+            })
+        );
+        assert_eq!(
+            Particles::builder().masses(vec![1.0; 4]).build().err(),
+            Some(ParticlesError::PerTypeValuesLength {
+                name: "masses",
+                expected: 5,
+                actual: 4,
+            })
+        );
+        assert_eq!(
+            Particles::builder().cell_size(-1.0).build().err(),
+            Some(ParticlesError::InvalidCellSize { cell_size: -1.0 })
+        );
+        assert_eq!(
+            Particles::builder()
+                .particles(vec![particle(cgmath::Vector3::zero(), 5)])
+                .build()
+                .err(),
+            Some(ParticlesError::InvalidParticleId { index: 0, id: 5 })
+        );
+    }
+
+    #[test]
+    fn substeps_reduce_energy_drift() {
+        let drifts = [1, 4, 16]
+            .map(|substeps| max_energy_drift(Integrator::SemiImplicitEuler, 0.05, substeps));
+        assert!(drifts[1] < drifts[0] * 0.5, "{drifts:?}");
+        assert!(drifts[2] < drifts[1] * 0.5, "{drifts:?}");
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let mut particles = Particles::default();
+        particles.spawn_random(50, 0);
+
+        let mut csv = vec![];
+        Particles::write_csv_header(&mut csv).unwrap();
+        particles.append_csv(&mut csv, 0).unwrap();
+        particles.update(1.0 / 60.0);
+        particles.append_csv(&mut csv, 1).unwrap();
+
+        // only the last frame is loaded
+        let mut loaded = Particles::default();
+        loaded.read_positions(csv.as_slice()).unwrap();
+        assert_eq!(loaded.current_particles, particles.current_particles);
+    }
+
+    #[test]
+    fn rescaling_the_world_keeps_relative_positions() {
+        let mut particles = Particles::default();
+        particles.spawn_random(100, 0);
+        let relative_positions = |particles: &Particles| {
+            particles
+                .current_particles
+                .iter()
+                .map(|particle| particle.position.div_element_wise(particles.world_size))
+                .collect::<Vec<_>>()
+        };
+        let before = relative_positions(&particles);
+
+        particles.rescale_world(cgmath::vec3(20.0, 15.0, 10.0));
+
+        assert_eq!(particles.world_size, cgmath::vec3(20.0, 15.0, 10.0));
+        for (after, before) in relative_positions(&particles).into_iter().zip(before) {
+            assert!((after - before).magnitude() < 1e-6);
+        }
+        particles.validate().unwrap();
+    }
+
+    #[test]
+    fn pair_count_of_a_line_of_particles() {
+        // the middle particle is close to both ends, but the ends are too far from each other
+        let line = vec![
+            particle(cgmath::vec3(-1.0, 0.0, 0.0), 0),
+            particle(cgmath::vec3(0.0, 0.0, 0.0), 0),
+            particle(cgmath::vec3(1.5, 0.0, 0.0), 0),
+        ];
+        for accel_structure in [
+            AccelStructure::SpatialHash,
+            AccelStructure::MortonHash,
+            AccelStructure::UniformGrid,
+        ] {
+            let mut particles = Particles::builder()
+                .accel_structure(accel_structure)
+                .particles(line.clone())
+                .build()
+                .unwrap();
+            particles.update(1.0 / 60.0);
+            assert_eq!(particles.last_step_pair_count, 4, "{accel_structure:?}");
+        }
+    }
+
+    #[test]
+    fn morton_hash_matches_brute_force() {
+        let mut particles = Particles::builder()
+            .accel_structure(AccelStructure::MortonHash)
+            .build()
+            .unwrap();
+        particles.spawn_random(200, 0);
+        assert_matches_brute_force(&particles, 10);
+    }
+
+    #[test]
+    fn deterministic_updates_are_the_same_on_1_and_8_threads() {
+        for accel_structure in [
+            AccelStructure::SpatialHash,
+            AccelStructure::MortonHash,
+            AccelStructure::UniformGrid,
+        ] {
+            let mut particles = Particles::builder()
+                .accel_structure(accel_structure)
+                .deterministic(true)
+                .build()
+                .unwrap();
+            particles.spawn_random(300, 0);
+
+            let single_threaded = update_on_threads(particles.clone(), 1, 10);
+            let multi_threaded = update_on_threads(particles, 8, 10);
+            assert_eq!(
+                single_threaded.state_hash(),
+                multi_threaded.state_hash(),
+                "{accel_structure:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn short_radius_types_ignore_further_neighbours() {
+        let mut particles = Particles::builder()
+            .types(vec![1.0; 4], vec![cgmath::vec3(1.0, 1.0, 1.0); 2])
+            .per_type_radius(vec![0.5, 2.0])
+            .particles(vec![
+                particle(cgmath::vec3(-0.3, 0.0, 0.0), 0),
+                particle(cgmath::vec3(0.3, 0.0, 0.0), 1),
+            ])
+            .build()
+            .unwrap();
+
+        particles.update(1.0 / 60.0);
+
+        assert_eq!(
+            particles.current_particles[0].velocity,
+            cgmath::Vector3::zero()
+        );
+        assert!(particles.current_particles[1].velocity.x < 0.0);
+    }
+
+    #[test]
+    fn particles_dont_interact_through_solid_walls() {
+        for (boundary, interacts) in [(BoundaryMode::Periodic, true), (BoundaryMode::Solid, false)]
+        {
+            let mut particles = Particles::builder()
+                .types(vec![1.0], vec![cgmath::vec3(1.0, 1.0, 1.0)])
+                .boundaries([boundary; 3])
+                .particles(vec![
+                    particle(cgmath::vec3(-4.6, 0.0, 0.0), 0),
+                    particle(cgmath::vec3(4.6, 0.0, 0.0), 0),
+                ])
+                .build()
+                .unwrap();
+
+            particles.update(1.0 / 60.0);
+
+            for particle in &particles.current_particles {
+                assert_eq!(
+                    particle.velocity != cgmath::Vector3::zero(),
+                    interacts,
+                    "{boundary:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn count_by_type_of_a_mixed_set() {
+        let particles = Particles::builder()
+            .particles(
+                [0, 2, 2, 4, 2, 0]
+                    .into_iter()
+                    .map(|id| particle(cgmath::Vector3::zero(), id))
+                    .collect(),
+            )
+            .build()
+            .unwrap();
+        assert_eq!(particles.count_by_type(), vec![2, 0, 3, 0, 1]);
+    }
+
+    #[test]
+    fn update_n_is_the_same_as_calling_update() {
+        let mut particles = Particles::builder().deterministic(true).build().unwrap();
+        particles.spawn_random(100, 0);
+        let mut updated = particles.clone();
+
+        particles.update_n(1.0 / 60.0, 3);
+        for _ in 0..3 {
+            updated.update(1.0 / 60.0);
+        }
+        assert_eq!(particles.current_particles, updated.current_particles);
+    }
+
+    #[test]
+    fn spawned_particles_are_inside_the_sphere_or_shell() {
+        for dimensions in [Dimensions::Two, Dimensions::Three] {
+            let mut particles = Particles::builder().dimensions(dimensions).build().unwrap();
+            let distances = |particles: &Particles| {
+                particles
+                    .current_particles
+                    .iter()
+                    .map(|particle| particle.position.magnitude())
+                    .collect::<Vec<_>>()
+            };
+
+            particles.spawn_in_sphere(1000, 0);
+            assert_eq!(particles.current_particles.len(), 1000);
+            assert!(distances(&particles)
+                .into_iter()
+                .all(|distance| distance <= 5.0 + 1e-5));
+
+            particles.spawn_in_shell(1000, 2.0, 3.0, 0);
+            assert_eq!(particles.current_particles.len(), 1000);
+            assert!(distances(&particles)
+                .into_iter()
+                .all(|distance| (2.0 - 1e-5..=3.0 + 1e-5).contains(&distance)));
+        }
+    }
+
+    #[test]
+    fn radial_gravity_pulls_towards_the_center() {
+        let mut particles = Particles::builder()
+            .gravity(GravityMode::Radial { strength: 1.0 })
+            .particles(vec![particle(cgmath::vec3(3.0, 0.0, 0.0), 0)])
+            .build()
+            .unwrap();
+
+        particles.update(1.0 / 60.0);
+
+        let velocity = particles.current_particles[0].velocity;
+        assert!(velocity.x < 0.0);
+        assert_eq!(velocity.y, 0.0);
+        assert_eq!(velocity.z, 0.0);
+    }
+
+    #[test]
+    fn setting_fewer_types_wraps_the_ids() {
+        let mut particles = Particles::builder()
+            .masses(vec![1.0, 2.0, 3.0, 4.0, 5.0])
+            .build()
+            .unwrap();
+        particles.spawn_random(100, 0);
+        let before = particles.current_particles.clone();
+
+        let (attraction_matrix, colors, _) = presets::clusters(3);
+        particles.set_types(attraction_matrix.clone(), colors.clone());
+
+        assert_eq!(particles.id_count, 3);
+        assert_eq!(particles.attraction_matrix, attraction_matrix);
+        assert_eq!(particles.colors, colors);
+        assert_eq!(particles.masses, None);
+        for (after, before) in particles.current_particles.iter().zip(&before) {
+            assert_eq!(after.position, before.position);
+            assert_eq!(after.id, before.id % 3);
+        }
+        particles.validate().unwrap();
+    }
+
+    #[test]
+    fn wall_hits_are_counted() {
+        let mut particles = Particles::builder()
+            .boundaries([BoundaryMode::Solid; 3])
+            .particles(vec![
+                Particle {
+                    position: cgmath::vec3(4.9, 4.9, 0.0),
+                    velocity: cgmath::vec3(10.0, 10.0, 0.0),
+                    id: 0,
+                },
+                particle(cgmath::vec3(-3.0, 0.0, 0.0), 0),
+            ])
+            .build()
+            .unwrap();
+
+        // hitting two walls at once only counts once
+        particles.update(0.1);
+        assert_eq!(particles.last_step_wall_hits, 1);
+
+        particles.zero_velocities();
+        particles.update(0.1);
+        assert_eq!(particles.last_step_wall_hits, 0);
+    }
+
+    #[test]
+    fn iterating_over_a_type() {
+        let positions = [
+            (cgmath::vec3(1.0, 0.0, 0.0), 1),
+            (cgmath::vec3(2.0, 0.0, 0.0), 3),
+            (cgmath::vec3(3.0, 0.0, 0.0), 1),
+            (cgmath::vec3(4.0, 0.0, 0.0), 0),
+        ];
+        let particles = Particles::builder()
+            .particles(
+                positions
+                    .iter()
+                    .map(|&(position, id)| particle(position, id))
+                    .collect(),
+            )
+            .build()
+            .unwrap();
+
+        for id in 0..particles.id_count {
+            let expected = positions
+                .iter()
+                .filter(|&&(_, other_id)| other_id == id)
+                .map(|&(position, _)| position)
+                .collect::<Vec<_>>();
+            assert!(particles.iter_type(id).all(|particle| particle.id == id));
+            assert_eq!(particles.iter_type(id).count(), expected.len());
+            assert_eq!(particles.positions_of_type(id), expected);
+        }
+    }
+
+    #[test]
+    fn max_neighbours_bounds_the_pairs_in_a_dense_cluster() {
+        // every particle is within the effect radius of every other one
+        let cluster = (0..1000)
+            .map(|i| {
+                let coord = |i: usize| 1.0 + (i % 10) as f32 * 0.1;
+                particle(cgmath::vec3(coord(i), coord(i / 10), coord(i / 100)), 0)
+            })
+            .collect::<Vec<_>>();
+
+        for accel_structure in [AccelStructure::SpatialHash, AccelStructure::UniformGrid] {
+            let builder = || {
+                Particles::builder()
+                    .accel_structure(accel_structure)
+                    .particles(cluster.clone())
+            };
+            let mut uncapped = builder().build().unwrap();
+            let mut capped = builder().max_neighbours(8).build().unwrap();
+            let mut capped_again = builder().max_neighbours(8).build().unwrap();
+
+            uncapped.update(1.0 / 60.0);
+            capped.update(1.0 / 60.0);
+            capped_again.update(1.0 / 60.0);
+
+            assert_eq!(uncapped.last_step_pair_count, 1000 * 999);
+            assert!(capped.last_step_pair_count <= 8 * 1000);
+            assert!(capped.last_step_pair_count * 100 < uncapped.last_step_pair_count);
+            // the same neighbours are kept every time
+            assert_eq!(capped.current_particles, capped_again.current_particles);
+        }
+    }
+
+    /// Runs two attracting particles for one second at `ticks_per_second`
+    fn run_for_a_second(ticks_per_second: usize) -> Vec<Particle> {
+        let mut particles = Particles::builder()
+            .types(vec![1.0], vec![cgmath::vec3(1.0, 1.0, 1.0)])
+            .friction(2.0)
+            .particles(vec![
+                Particle {
+                    position: cgmath::vec3(-0.3, 0.0, 0.0),
+                    velocity: cgmath::vec3(0.0, 1.0, 0.0),
+                    id: 0,
+                },
+                Particle {
+                    position: cgmath::vec3(0.3, 0.0, 0.0),
+                    velocity: cgmath::vec3(0.0, -1.0, 0.0),
+                    id: 0,
+                },
+            ])
+            .build()
+            .unwrap();
+        particles.update_n(1.0 / ticks_per_second as f32, ticks_per_second);
+        particles.current_particles
+    }
+
+    #[test]
+    fn trajectories_converge_as_the_tick_rate_increases() {
+        let reference = run_for_a_second(480);
+        let error = |particles: &[Particle]| {
+            particles
+                .iter()
+                .zip(&reference)
+                .map(|(particle, reference)| {
+                    (particle.position - reference.position).magnitude()
+                        + (particle.velocity - reference.velocity).magnitude()
+                })
+                .fold(0.0, f32::max)
+        };
+
+        let slow = error(&run_for_a_second(30));
+        let fast = error(&run_for_a_second(120));
+        assert!(fast < slow * 0.5);
+        assert!(fast < 0.01);
+    }
+
+    #[test]
+    fn friction_is_the_same_at_any_tick_rate() {
+        let velocity_after_a_second = |ticks_per_second: usize| {
+            let mut particles = Particles::builder()
+                .friction(2.0)
+                .particles(vec![Particle {
+                    position: cgmath::vec3(0.0, 0.0, 0.0),
+                    velocity: cgmath::vec3(1.0, 0.0, 0.0),
+                    id: 0,
+                }])
+                .build()
+                .unwrap();
+            particles.update_n(1.0 / ticks_per_second as f32, ticks_per_second);
+            particles.current_particles[0].velocity.x
+        };
+
+        let slow = velocity_after_a_second(30);
+        let fast = velocity_after_a_second(120);
+        assert!((slow - fast).abs() < 1e-5);
+        assert!((fast - (-2.0f32).exp()).abs() < 1e-5);
     }
 }
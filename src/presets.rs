@@ -0,0 +1,145 @@
+//! Attraction matrices that produce interesting behaviour, each function returns `(attraction_matrix, colors, id_count)`
+//! where `attraction_matrix[i * id_count + j]` is how much particles with id `i` are attracted to particles with id `j`
+
+use rand::prelude::*;
+
+/// `id_count` evenly spaced hues
+fn rainbow(id_count: u32) -> Vec<cgmath::Vector3<f32>> {
+    (0..id_count)
+        .map(|i| {
+            let hue = i as f32 / id_count as f32 * 6.0;
+            let channel = |offset: f32| (2.0 - ((hue + offset) % 6.0 - 3.0).abs()).clamp(0.0, 1.0);
+            cgmath::vec3(channel(3.0), channel(1.0), channel(5.0))
+        })
+        .collect()
+}
+
+/// Every id is attracted to itself and pushed away from the others,
+/// so the particles separate into clumps of a single id that drift away from each other
+pub fn clusters(id_count: u32) -> (Vec<f32>, Vec<cgmath::Vector3<f32>>, u32) {
+    let n = id_count as usize;
+    let attraction_matrix = (0..n * n)
+        .map(|index| if index / n == index % n { 1.0 } else { -0.3 })
+        .collect();
+    (attraction_matrix, rainbow(id_count), id_count)
+}
+
+/// Each id is attracted to the next one in a loop, so the particles link up into long chains
+/// that slowly chase their own tails
+pub fn chains() -> (Vec<f32>, Vec<cgmath::Vector3<f32>>, u32) {
+    #[rustfmt::skip]
+    let attraction_matrix = vec![
+        0.2, 0.8, -0.1, -0.1, // red
+        -0.1, 0.2, 0.8, -0.1, // yellow
+        -0.1, -0.1, 0.2, 0.8, // cyan
+        0.8, -0.1, -0.1, 0.2, // blue
+    ];
+    let colors = vec![
+        cgmath::vec3(1.0, 0.2, 0.2), // red
+        cgmath::vec3(1.0, 1.0, 0.2), // yellow
+        cgmath::vec3(0.2, 1.0, 1.0), // cyan
+        cgmath::vec3(0.2, 0.2, 1.0), // blue
+    ];
+    (attraction_matrix, colors, 4)
+}
+
+/// Prey flock together and run away from predators, while predators chase the prey and spread out from each other,
+/// which produces flocks that keep breaking apart and reforming
+pub fn predator_prey() -> (Vec<f32>, Vec<cgmath::Vector3<f32>>, u32) {
+    #[rustfmt::skip]
+    let attraction_matrix = vec![
+        0.6, -1.0, // prey
+        1.0, -0.2, // predator
+    ];
+    let colors = vec![
+        cgmath::vec3(0.2, 1.0, 0.2), // prey
+        cgmath::vec3(1.0, 0.2, 0.2), // predator
+    ];
+    (attraction_matrix, colors, 2)
+}
+
+/// A random matrix that usually keeps moving instead of turning into a gas or collapsing into a single clump.
+///
+/// Each id is attracted to itself by a value from 0.3 to 0.8, so every id forms clumps without pulling them into a point.
+/// The other entries start uniform in -1 to 1 and are shifted to have a mean of 0, so the attractions between different ids
+/// cancel out on average and the clumps keep chasing and fleeing each other instead of all merging or all flying apart.
+/// They are then scaled back down if the shift pushed any of them outside of -1 to 1.
+/// The same `id_count` and `seed` always produce the same matrix
+pub fn balanced_random(id_count: u32, seed: u64) -> (Vec<f32>, Vec<cgmath::Vector3<f32>>, u32) {
+    let n = id_count as usize;
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut attraction_matrix: Vec<f32> = (0..n * n)
+        .map(|index| {
+            if index / n == index % n {
+                rng.gen_range(0.3..=0.8)
+            } else {
+                rng.gen_range(-1.0..=1.0)
+            }
+        })
+        .collect();
+
+    let off_diagonal = |index: &usize| index / n != index % n;
+    let off_diagonal_count = n * n - n;
+    if off_diagonal_count > 0 {
+        let mean = (0..n * n)
+            .filter(off_diagonal)
+            .map(|index| attraction_matrix[index])
+            .sum::<f32>()
+            / off_diagonal_count as f32;
+        for index in (0..n * n).filter(off_diagonal) {
+            attraction_matrix[index] -= mean;
+        }
+        let max = (0..n * n)
+            .filter(off_diagonal)
+            .map(|index| attraction_matrix[index].abs())
+            .fold(1.0, f32::max);
+        for index in (0..n * n).filter(off_diagonal) {
+            attraction_matrix[index] /= max;
+        }
+    }
+
+    (attraction_matrix, rainbow(id_count), id_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_have_a_value_for_every_id() {
+        for (attraction_matrix, colors, id_count) in [
+            clusters(1),
+            clusters(6),
+            chains(),
+            predator_prey(),
+            balanced_random(1, 0),
+            balanced_random(7, 0),
+        ] {
+            assert_eq!(attraction_matrix.len(), (id_count * id_count) as usize);
+            assert_eq!(colors.len(), id_count as usize);
+        }
+    }
+
+    #[test]
+    fn balanced_random_statistics() {
+        for seed in 0..20 {
+            let (attraction_matrix, _, id_count) = balanced_random(8, seed);
+            let n = id_count as usize;
+            let mut off_diagonal_sum = 0.0;
+            for i in 0..n {
+                for j in 0..n {
+                    let attraction = attraction_matrix[i * n + j];
+                    assert!((-1.0..=1.0).contains(&attraction));
+                    if i == j {
+                        assert!((0.3..=0.8).contains(&attraction));
+                    } else {
+                        off_diagonal_sum += attraction;
+                    }
+                }
+            }
+            assert!((off_diagonal_sum / (n * n - n) as f32).abs() < 1e-5);
+        }
+
+        assert_eq!(balanced_random(8, 3), balanced_random(8, 3));
+    }
+}
@@ -0,0 +1,278 @@
+//! Recording the particles every frame into a compact binary log, and playing those recordings back.
+//!
+//! A recording starts with the 4 byte magic `PLRP`, a version byte, and a byte that is 1 when positions are quantized.
+//! Every frame is then stored as its world size along each axis as 3 `f32`s, its particle count as a `u32`,
+//! and each particle as its id as a `u32`, its position, and its velocity as 3 `f32`s.
+//! Quantized positions are stored as 3 `u16`s that divide the world into 65536 steps along each axis,
+//! which is a lot more precise than 16 bit floats for the size of world particles are usually in.
+//! Every value is little endian
+
+use crate::{Particle, Particles};
+
+const MAGIC: &[u8; 4] = b"PLRP";
+const VERSION: u8 = 2;
+
+/// How the positions of particles are stored in a recording
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precision {
+    /// Positions are stored exactly
+    Full,
+    /// Positions are rounded to 1/65536 of the world size along each axis, this makes each particle 6 bytes smaller
+    Quantized,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplayError {
+    InvalidMagic,
+    UnsupportedVersion { version: u8 },
+    UnexpectedEnd,
+}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            ReplayError::InvalidMagic => write!(f, "the data is not a particle recording"),
+            ReplayError::UnsupportedVersion { version } => {
+                write!(f, "recording version {version} is not supported")
+            }
+            ReplayError::UnexpectedEnd => write!(f, "the recording ended in the middle of a frame"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+/// Records the particles every time `push` is called
+pub struct Recorder {
+    precision: Precision,
+    data: Vec<u8>,
+    frame_count: usize,
+}
+
+impl Recorder {
+    pub fn new(precision: Precision) -> Self {
+        let mut data = MAGIC.to_vec();
+        data.push(VERSION);
+        data.push((precision == Precision::Quantized) as u8);
+        Self {
+            precision,
+            data,
+            frame_count: 0,
+        }
+    }
+
+    /// Adds the current particles as a new frame
+    pub fn push(&mut self, particles: &Particles) {
+        let world_size = particles.world_size;
+        for axis in 0..3 {
+            self.data.extend(world_size[axis].to_le_bytes());
+        }
+        self.data
+            .extend((particles.current_particles.len() as u32).to_le_bytes());
+        for particle in &particles.current_particles {
+            self.data.extend(particle.id.to_le_bytes());
+            for axis in 0..3 {
+                match self.precision {
+                    Precision::Full => self.data.extend(particle.position[axis].to_le_bytes()),
+                    Precision::Quantized => self
+                        .data
+                        .extend(quantize(particle.position[axis], world_size[axis]).to_le_bytes()),
+                }
+            }
+            for axis in 0..3 {
+                self.data.extend(particle.velocity[axis].to_le_bytes());
+            }
+        }
+        self.frame_count += 1;
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// The recording in the format described in the module docs
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// Plays back a recording made by `Recorder`, the whole recording is decoded up front so any frame can be shown immediately
+pub struct Player {
+    frames: Vec<Vec<Particle>>,
+    world_sizes: Vec<cgmath::Vector3<f32>>,
+}
+
+impl Player {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Player, ReplayError> {
+        let mut reader = Reader { bytes };
+        if reader.take(4)? != MAGIC {
+            return Err(ReplayError::InvalidMagic);
+        }
+        let version = reader.take(1)?[0];
+        if version != VERSION {
+            return Err(ReplayError::UnsupportedVersion { version });
+        }
+        let quantized = reader.take(1)?[0] != 0;
+
+        let mut frames = vec![];
+        let mut world_sizes = vec![];
+        while !reader.bytes.is_empty() {
+            let world_size = cgmath::vec3(reader.f32()?, reader.f32()?, reader.f32()?);
+            let particle_count = reader.u32()? as usize;
+            let frame = (0..particle_count)
+                .map(|_| {
+                    let id = reader.u32()?;
+                    let mut position = cgmath::vec3(0.0, 0.0, 0.0);
+                    for axis in 0..3 {
+                        position[axis] = if quantized {
+                            dequantize(reader.u16()?, world_size[axis])
+                        } else {
+                            reader.f32()?
+                        };
+                    }
+                    let velocity = cgmath::vec3(reader.f32()?, reader.f32()?, reader.f32()?);
+                    Ok(Particle {
+                        position,
+                        velocity,
+                        id,
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+            frames.push(frame);
+            world_sizes.push(world_size);
+        }
+
+        Ok(Player {
+            frames,
+            world_sizes,
+        })
+    }
+
+    pub fn from_recorder(recorder: &Recorder) -> Player {
+        Self::from_bytes(recorder.bytes()).unwrap()
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// The particles in frame `i`
+    pub fn frame(&self, i: usize) -> &[Particle] {
+        &self.frames[i]
+    }
+
+    /// The world size when frame `i` was recorded
+    pub fn world_size(&self, i: usize) -> cgmath::Vector3<f32> {
+        self.world_sizes[i]
+    }
+}
+
+/// Maps a position in the world to the nearest of 65536 evenly spaced steps across it
+fn quantize(position: f32, world_size: f32) -> u16 {
+    ((position / world_size + 0.5) * u16::MAX as f32)
+        .round()
+        .clamp(0.0, u16::MAX as f32) as u16
+}
+
+fn dequantize(quantized: u16, world_size: f32) -> f32 {
+    (quantized as f32 / u16::MAX as f32 - 0.5) * world_size
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], ReplayError> {
+        if self.bytes.len() < count {
+            return Err(ReplayError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u16(&mut self) -> Result<u16, ReplayError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, ReplayError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, ReplayError> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records a few frames of moving particles, returning the recording and the particles in each frame
+    fn record(precision: Precision) -> (Recorder, Vec<Vec<Particle>>) {
+        let mut particles = Particles::default();
+        particles.spawn_random(100, 0);
+        let mut recorder = Recorder::new(precision);
+        let mut frames = vec![];
+        for _ in 0..3 {
+            particles.update(1.0 / 60.0);
+            recorder.push(&particles);
+            frames.push(particles.current_particles.clone());
+        }
+        (recorder, frames)
+    }
+
+    #[test]
+    fn full_precision_round_trip_is_exact() {
+        let (recorder, frames) = record(Precision::Full);
+        let player = Player::from_bytes(recorder.bytes()).unwrap();
+
+        assert_eq!(player.frame_count(), 3);
+        for (i, frame) in frames.iter().enumerate() {
+            assert_eq!(player.frame(i), frame.as_slice());
+            assert_eq!(player.world_size(i), Particles::default().world_size);
+        }
+    }
+
+    #[test]
+    fn quantized_round_trip_is_within_one_step() {
+        let (recorder, frames) = record(Precision::Quantized);
+        let player = Player::from_bytes(recorder.bytes()).unwrap();
+
+        assert_eq!(player.frame_count(), 3);
+        for (i, frame) in frames.iter().enumerate() {
+            let world_size = player.world_size(i);
+            assert_eq!(player.frame(i).len(), frame.len());
+            for (played, recorded) in player.frame(i).iter().zip(frame) {
+                assert_eq!(played.id, recorded.id);
+                assert_eq!(played.velocity, recorded.velocity);
+                for axis in 0..3 {
+                    let error = (played.position[axis] - recorded.position[axis]).abs();
+                    assert!(error <= world_size[axis] / u16::MAX as f32);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_recordings_are_rejected() {
+        assert_eq!(
+            Player::from_bytes(b"PLRQ\x02\x00").err(),
+            Some(ReplayError::InvalidMagic)
+        );
+        assert_eq!(
+            Player::from_bytes(&[MAGIC.as_slice(), &[VERSION + 1, 0]].concat()).err(),
+            Some(ReplayError::UnsupportedVersion {
+                version: VERSION + 1
+            })
+        );
+
+        let (recorder, _) = record(Precision::Full);
+        let bytes = recorder.bytes();
+        assert_eq!(
+            Player::from_bytes(&bytes[..bytes.len() - 5]).err(),
+            Some(ReplayError::UnexpectedEnd)
+        );
+    }
+}
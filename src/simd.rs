@@ -0,0 +1,381 @@
+//! Evaluates the forces from several neighbours at once, using SSE on x86_64 and plain loops everywhere else.
+//! This gives the same forces as `Particles::pair_force`, except that they are added up in a different order.
+//! Only `ForceProfile::ParticleLifeDefault` has an SSE version, the other profiles always use the loops
+
+use cgmath::prelude::*;
+
+use crate::ForceProfile;
+
+/// The number of neighbours whose forces are evaluated together
+pub const LANES: usize = 4;
+
+/// Collects the neighbours of a particle and adds up the forces they exert on it a batch at a time
+pub struct ForceAccumulator {
+    force_profile: ForceProfile,
+    min_attraction_percentage: f32,
+    sqr_particle_effect_radius: f32,
+    /// Neighbours pushed after this many have been visited are ignored
+    max_neighbours: Option<usize>,
+    /// The position of each neighbour relative to the particle
+    xs: [f32; LANES],
+    ys: [f32; LANES],
+    zs: [f32; LANES],
+    attractions: [f32; LANES],
+    len: usize,
+    /// The number of neighbours pushed or skipped so far, whether or not they were close enough to exert a force
+    visited: usize,
+    /// The number of neighbours that were within `particle_effect_radius`
+    pair_count: usize,
+    /// The force from the neighbours in each lane, these are only added together in `finish`
+    total_force: [[f32; LANES]; 3],
+}
+
+impl ForceAccumulator {
+    pub fn new(
+        force_profile: ForceProfile,
+        min_attraction_percentage: f32,
+        particle_effect_radius: f32,
+        max_neighbours: Option<usize>,
+    ) -> Self {
+        Self {
+            force_profile,
+            min_attraction_percentage,
+            sqr_particle_effect_radius: particle_effect_radius * particle_effect_radius,
+            max_neighbours,
+            xs: [0.0; LANES],
+            ys: [0.0; LANES],
+            zs: [0.0; LANES],
+            attractions: [0.0; LANES],
+            len: 0,
+            visited: 0,
+            pair_count: 0,
+            total_force: [[0.0; LANES]; 3],
+        }
+    }
+
+    /// Adds a neighbour at `relative_position` from the particle, that attracts it by `attraction`
+    #[inline]
+    pub fn push(&mut self, relative_position: cgmath::Vector3<f32>, attraction: f32) {
+        if self.is_full() {
+            return;
+        }
+        self.visited += 1;
+        // most neighbours in the surrounding cells are too far away, and its cheaper to skip them here than in a batch
+        let sqr_distance = relative_position.magnitude2();
+        if !(sqr_distance > 0.0 && sqr_distance < self.sqr_particle_effect_radius) {
+            return;
+        }
+
+        self.xs[self.len] = relative_position.x;
+        self.ys[self.len] = relative_position.y;
+        self.zs[self.len] = relative_position.z;
+        self.attractions[self.len] = attraction;
+        self.len += 1;
+        self.pair_count += 1;
+        if self.len == LANES {
+            self.flush();
+        }
+    }
+
+    /// Counts a candidate that was looked at but isnt a neighbour, like one from another cell in the same bucket,
+    /// so it still counts towards `max_neighbours`
+    #[inline]
+    pub fn skip(&mut self) {
+        self.visited += 1;
+    }
+
+    /// Whether `max_neighbours` neighbours have been visited, so any more would be ignored
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.max_neighbours
+            .is_some_and(|max_neighbours| self.visited >= max_neighbours)
+    }
+
+    /// The number of neighbours pushed so far that were close enough to exert a force
+    pub fn pair_count(&self) -> usize {
+        self.pair_count
+    }
+
+    /// The total force from every neighbour that was pushed
+    pub fn finish(mut self) -> cgmath::Vector3<f32> {
+        if self.len > 0 {
+            // a relative position of 0 is ignored, so the unused lanes dont add any force
+            self.xs[self.len..].fill(0.0);
+            self.ys[self.len..].fill(0.0);
+            self.zs[self.len..].fill(0.0);
+            self.flush();
+        }
+        let [x, y, z] = self.total_force.map(|force| force.iter().sum());
+        cgmath::vec3(x, y, z)
+    }
+
+    fn flush(&mut self) {
+        if self.force_profile != ForceProfile::ParticleLifeDefault {
+            scalar_add_batch_force(
+                self.force_profile,
+                self.min_attraction_percentage,
+                self.sqr_particle_effect_radius,
+                &self.xs,
+                &self.ys,
+                &self.zs,
+                &self.attractions,
+                &mut self.total_force,
+            );
+            self.len = 0;
+            return;
+        }
+
+        add_batch_force(
+            self.min_attraction_percentage,
+            self.sqr_particle_effect_radius,
+            &self.xs,
+            &self.ys,
+            &self.zs,
+            &self.attractions,
+            &mut self.total_force,
+        );
+        self.len = 0;
+    }
+}
+
+/// Adds the force from each neighbour to the lane of `total_force` that it is in
+#[cfg(target_arch = "x86_64")]
+fn add_batch_force(
+    min_attraction_percentage: f32,
+    sqr_particle_effect_radius: f32,
+    xs: &[f32; LANES],
+    ys: &[f32; LANES],
+    zs: &[f32; LANES],
+    attractions: &[f32; LANES],
+    total_force: &mut [[f32; LANES]; 3],
+) {
+    use std::arch::x86_64::*;
+
+    // SAFETY: SSE and SSE2 are always available on x86_64, and every load and store is of exactly `LANES` floats
+    unsafe {
+        let x = _mm_loadu_ps(xs.as_ptr());
+        let y = _mm_loadu_ps(ys.as_ptr());
+        let z = _mm_loadu_ps(zs.as_ptr());
+
+        let sqr_distance = _mm_add_ps(
+            _mm_add_ps(_mm_mul_ps(x, x), _mm_mul_ps(y, y)),
+            _mm_mul_ps(z, z),
+        );
+        let in_range = _mm_and_ps(
+            _mm_cmpgt_ps(sqr_distance, _mm_setzero_ps()),
+            _mm_cmplt_ps(sqr_distance, _mm_set1_ps(sqr_particle_effect_radius)),
+        );
+        let distance = _mm_sqrt_ps(sqr_distance);
+
+        // the same as `Particles::force`
+        let one = _mm_set1_ps(1.0);
+        let min_attraction_percentage = _mm_set1_ps(min_attraction_percentage);
+        let repulsion = _mm_sub_ps(_mm_div_ps(distance, min_attraction_percentage), one);
+        let peak_distance = _mm_sub_ps(
+            _mm_sub_ps(_mm_add_ps(distance, distance), one),
+            min_attraction_percentage,
+        );
+        // clearing the sign bit is the absolute value
+        let abs_peak_distance = _mm_andnot_ps(_mm_set1_ps(-0.0), peak_distance);
+        let attraction = _mm_mul_ps(
+            _mm_loadu_ps(attractions.as_ptr()),
+            _mm_sub_ps(
+                one,
+                _mm_div_ps(
+                    abs_peak_distance,
+                    _mm_sub_ps(one, min_attraction_percentage),
+                ),
+            ),
+        );
+        let repelling = _mm_cmplt_ps(distance, min_attraction_percentage);
+        let attracting = _mm_and_ps(
+            _mm_cmplt_ps(min_attraction_percentage, distance),
+            _mm_cmplt_ps(distance, one),
+        );
+        let f = _mm_or_ps(
+            _mm_and_ps(repelling, repulsion),
+            _mm_andnot_ps(repelling, _mm_and_ps(attracting, attraction)),
+        );
+
+        // out of range lanes can divide by 0, so they are masked after the division
+        let scale = _mm_and_ps(in_range, _mm_div_ps(f, distance));
+        for (total_force, relative_position) in total_force.iter_mut().zip([x, y, z]) {
+            _mm_storeu_ps(
+                total_force.as_mut_ptr(),
+                _mm_add_ps(
+                    _mm_loadu_ps(total_force.as_ptr()),
+                    _mm_mul_ps(relative_position, scale),
+                ),
+            );
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn add_batch_force(
+    min_attraction_percentage: f32,
+    sqr_particle_effect_radius: f32,
+    xs: &[f32; LANES],
+    ys: &[f32; LANES],
+    zs: &[f32; LANES],
+    attractions: &[f32; LANES],
+    total_force: &mut [[f32; LANES]; 3],
+) {
+    scalar_add_batch_force(
+        ForceProfile::ParticleLifeDefault,
+        min_attraction_percentage,
+        sqr_particle_effect_radius,
+        xs,
+        ys,
+        zs,
+        attractions,
+        total_force,
+    );
+}
+
+/// The same as `add_batch_force` one neighbour at a time, for any force profile
+#[allow(clippy::too_many_arguments)]
+fn scalar_add_batch_force(
+    force_profile: ForceProfile,
+    min_attraction_percentage: f32,
+    sqr_particle_effect_radius: f32,
+    xs: &[f32; LANES],
+    ys: &[f32; LANES],
+    zs: &[f32; LANES],
+    attractions: &[f32; LANES],
+    total_force: &mut [[f32; LANES]; 3],
+) {
+    for lane in 0..LANES {
+        let relative_position = cgmath::vec3(xs[lane], ys[lane], zs[lane]);
+        let sqr_distance = relative_position.x * relative_position.x
+            + relative_position.y * relative_position.y
+            + relative_position.z * relative_position.z;
+        if sqr_distance > 0.0 && sqr_distance < sqr_particle_effect_radius {
+            let distance = sqr_distance.sqrt();
+            let f = force_profile.force(distance, attractions[lane], min_attraction_percentage);
+            for axis in 0..3 {
+                total_force[axis][lane] += relative_position[axis] * (f / distance);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+
+    struct Batch {
+        xs: [f32; LANES],
+        ys: [f32; LANES],
+        zs: [f32; LANES],
+        attractions: [f32; LANES],
+    }
+
+    impl Batch {
+        fn add_force(&self, total_force: &mut [[f32; LANES]; 3]) {
+            add_batch_force(
+                0.3,
+                4.0,
+                &self.xs,
+                &self.ys,
+                &self.zs,
+                &self.attractions,
+                total_force,
+            );
+        }
+
+        fn scalar_add_force(&self, total_force: &mut [[f32; LANES]; 3]) {
+            scalar_add_batch_force(
+                ForceProfile::ParticleLifeDefault,
+                0.3,
+                4.0,
+                &self.xs,
+                &self.ys,
+                &self.zs,
+                &self.attractions,
+                total_force,
+            );
+        }
+    }
+
+    /// Random batches of neighbours, including ones outside the effect radius and ones at the same position as the particle
+    fn random_batches(count: usize) -> Vec<Batch> {
+        let mut rng = StdRng::seed_from_u64(0);
+        (0..count)
+            .map(|i| {
+                let mut coord = |lane: usize| {
+                    if (i + lane) % 17 == 0 {
+                        0.0
+                    } else {
+                        rng.gen_range(-2.0..=2.0)
+                    }
+                };
+                let xs = std::array::from_fn(&mut coord);
+                let ys = std::array::from_fn(&mut coord);
+                let zs = std::array::from_fn(&mut coord);
+                Batch {
+                    xs,
+                    ys,
+                    zs,
+                    attractions: std::array::from_fn(|_| rng.gen_range(-1.0..=1.0)),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn batch_force_matches_scalar() {
+        for batch in random_batches(10_000) {
+            let mut total_force = [[0.0; LANES]; 3];
+            batch.add_force(&mut total_force);
+            let mut scalar_total_force = [[0.0; LANES]; 3];
+            batch.scalar_add_force(&mut scalar_total_force);
+            for (forces, scalar_forces) in total_force.iter().zip(&scalar_total_force) {
+                for (&force, &scalar_force) in forces.iter().zip(scalar_forces) {
+                    assert!(
+                        (force - scalar_force).abs() <= 1e-5 * scalar_force.abs().max(1.0),
+                        "{force} != {scalar_force}"
+                    );
+                }
+            }
+        }
+    }
+
+    /// Compares the speed of `add_batch_force` and `scalar_add_batch_force`,
+    /// run it with `cargo test --release --lib -- --ignored --nocapture batch_force_speed`
+    #[test]
+    #[ignore]
+    fn batch_force_speed() {
+        let batches = random_batches(100_000);
+        let time = |add_force: fn(&Batch, &mut [[f32; LANES]; 3])| {
+            let mut total_force = [[0.0; LANES]; 3];
+            let start = std::time::Instant::now();
+            for _ in 0..20 {
+                for batch in &batches {
+                    add_force(std::hint::black_box(batch), &mut total_force);
+                }
+            }
+            std::hint::black_box(total_force);
+            start.elapsed()
+        };
+
+        let batch = time(Batch::add_force);
+        let scalar = time(Batch::scalar_add_force);
+        println!("add_batch_force: {batch:?}, scalar_add_batch_force: {scalar:?}");
+    }
+
+    #[test]
+    fn max_neighbours_counts_every_candidate() {
+        let mut accumulator = ForceAccumulator::new(ForceProfile::default(), 0.3, 1.0, Some(8));
+        for _ in 0..4 {
+            accumulator.push(cgmath::Vector3::zero(), 1.0);
+            accumulator.push(cgmath::vec3(5.0, 0.0, 0.0), 1.0);
+        }
+        assert!(accumulator.is_full());
+
+        accumulator.push(cgmath::vec3(0.5, 0.0, 0.0), 1.0);
+        assert_eq!(accumulator.pair_count(), 0);
+        assert_eq!(accumulator.finish(), cgmath::Vector3::zero());
+    }
+}
@@ -0,0 +1,22 @@
+//! Compares the state hash after the fixed simulation in `src/bin/checksum.rs` with `EXPECTED_CHECKSUM`,
+//! so that accidental changes to the physics are noticed.
+//!
+//! When a change to the physics is intentional, run `cargo run --release --bin checksum`
+//! and replace `EXPECTED_CHECKSUM` with the value it prints
+
+#[path = "../src/bin/checksum.rs"]
+#[allow(dead_code)]
+mod checksum;
+
+const EXPECTED_CHECKSUM: u64 = 0x7b4a_d9af_a451_f904;
+
+#[test]
+#[cfg_attr(not(target_arch = "x86_64"), ignore)]
+fn state_hash_matches_expected_checksum() {
+    let checksum = checksum::checksum();
+    assert!(
+        checksum == EXPECTED_CHECKSUM,
+        "checksum {checksum:#018x} doesnt match the expected {EXPECTED_CHECKSUM:#018x}, \
+        if the physics changed on purpose replace EXPECTED_CHECKSUM with it"
+    );
+}